@@ -21,15 +21,41 @@
 //! The module handles file reading asynchronously and provides error handling for various
 //! failure scenarios including file I/O errors and JSON parsing failures.
 
+use anyhow::Context;
 use fancy_regex::Regex;
+use serde::Serialize;
 use serde_json::Value;
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
 use tokio::task;
 
+/// Where artifact meta tags (e.g. `watermark`, `signature`, `text`) are placed in e621 captions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArtifactPosition {
+    /// Place artifact tags at the very start of the caption
+    Front,
+    /// Place artifact tags at the end of the caption
+    #[default]
+    Back,
+    /// Remove artifact tags from the caption and write them to a `{stem}.artifacts` sidecar file
+    Sidecar,
+}
+
+/// e621 tag categories in their default order, with their format placeholder names.
+const E621_CATEGORIES: [(&str, &str); 6] = [
+    ("artist", "artists"),
+    ("character", "characters"),
+    ("species", "species"),
+    ("copyright", "copyright"),
+    ("general", "general"),
+    ("meta", "meta"),
+];
+
 /// Configuration for e621 caption processing.
 #[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct E621Config {
     /// Whether to filter out certain tags (years, aspect ratios, etc.)
     pub filter_tags: bool,
@@ -45,10 +71,14 @@ pub struct E621Config {
     /// - {copyright} - Copyright tags
     /// - {general} - General tags
     /// - {meta} - Meta tags
+    /// - {sources} - Source site tags (only filled when `include_sources` is true)
     ///
-    /// Each tag group will be joined with ", " internally.
+    /// Each tag group will be joined with ", " internally. Placeholders of categories
+    /// left out of `category_order` are removed along with their separators.
     ///
-    /// If None, uses the default format: "{rating}, {artists}, {characters}, {species}, {copyright}, {general}, {meta}"
+    /// If None, uses the default format: "{rating}, {artists}, {characters}, {species}, {copyright}, {general}, {meta}",
+    /// with the category placeholders following `category_order` when it is set
+    /// and `, {sources}` appended when `include_sources` is true.
     pub format: Option<String>,
     /// Optional prefix to add before artist names (default: "by ")
     pub artist_prefix: Option<String>,
@@ -56,6 +86,51 @@ pub struct E621Config {
     pub artist_suffix: Option<String>,
     /// Whether to replace underscores with spaces in tags (default: true)
     pub replace_underscores: bool,
+    /// Meta tags describing image artifacts (e.g. "watermark", "signature", "text").
+    /// Matching meta tags are routed according to `artifact_position` instead of
+    /// appearing in the `{meta}` group. Tags are matched after underscore replacement.
+    /// If None, meta tags are handled like any other category.
+    pub artifact_tags: Option<std::collections::HashSet<String>>,
+    /// Where matched artifact tags are placed (default: `ArtifactPosition::Back`)
+    pub artifact_position: ArtifactPosition,
+    /// Minimum `post.score.total` required to write a caption (default: None).
+    /// Posts without a score are always processed.
+    pub min_score: Option<i64>,
+    /// Tags that are always dropped, regardless of category (default: empty).
+    /// Tags are matched both as written in the post and after underscore replacement.
+    pub blacklist: std::collections::HashSet<String>,
+    /// Only write captions for posts containing at least one of these tags in any
+    /// category (default: None). Tags are matched both as written in the post and
+    /// after underscore replacement. An empty set matches no post, so every caption
+    /// is skipped.
+    pub require_any: Option<std::collections::HashSet<String>>,
+    /// Maximum number of general tags to keep (default: None).
+    /// Tags keep the order of the source JSON, so later tags are dropped first.
+    pub max_general: Option<usize>,
+    /// Maximum number of tags to keep per category, keyed by e621 category name
+    /// (e.g. "species", "meta"). A `max_general` value takes precedence for "general".
+    pub max_tags_per_category: Option<std::collections::HashMap<String, usize>>,
+    /// Order in which tag categories are processed (default: None, meaning
+    /// `artist, character, species, copyright, general, meta`). Categories left out
+    /// of the list are omitted from the caption. Unknown category names are an error.
+    pub category_order: Option<Vec<String>>,
+    /// Whether to drop tags already emitted by an earlier category (default: false).
+    /// The first occurrence keeps its placement; the rating is never deduplicated.
+    pub dedupe_across_categories: bool,
+    /// Whether to escape parentheses in non-artist tags with backslashes, as
+    /// [`crate::process_json_to_caption`] does (default: false)
+    pub escape_parentheses: bool,
+    /// Whether to emit the domains of `post.sources` (e.g. "twitter", "pixiv") as the
+    /// `{sources}` group (default: false). Domains are deduplicated and malformed URLs skipped.
+    pub include_sources: bool,
+    /// Additional regex patterns of tags to ignore, checked together with
+    /// `IGNORED_E621_TAGS` whenever `filter_tags` is true (default: None).
+    pub ignored_patterns: Option<Vec<String>>,
+    /// Tag substitutions applied after underscore replacement (default: None), e.g.
+    /// `canid` -> `canine`. Keys are matched both as written in the post and after
+    /// underscore replacement. Aliasing to an empty string drops the tag. Aliases are
+    /// applied before the blacklist and before deduplication.
+    pub aliases: Option<std::collections::HashMap<String, String>>,
 }
 
 impl Default for E621Config {
@@ -72,6 +147,19 @@ impl Default for E621Config {
             artist_prefix: Some("by ".to_string()),
             artist_suffix: None,
             replace_underscores: true,
+            artifact_tags: None,
+            artifact_position: ArtifactPosition::default(),
+            min_score: None,
+            blacklist: std::collections::HashSet::new(),
+            require_any: None,
+            max_general: None,
+            max_tags_per_category: None,
+            category_order: None,
+            dedupe_across_categories: false,
+            escape_parentheses: false,
+            include_sources: false,
+            ignored_patterns: None,
+            aliases: None,
         }
     }
 }
@@ -128,11 +216,192 @@ impl E621Config {
         self
     }
 
-    /// Gets the format string to use
-    fn get_format(&self) -> &str {
-        self.format.as_deref().unwrap_or(
-            "{rating}, {artists}, {characters}, {species}, {copyright}, {general}, {meta}",
-        )
+    /// Sets the meta tags treated as image artifacts (default: None)
+    #[must_use]
+    pub fn with_artifact_tags(
+        mut self,
+        artifact_tags: Option<std::collections::HashSet<String>>,
+    ) -> Self {
+        self.artifact_tags = artifact_tags;
+        self
+    }
+
+    /// Sets where artifact tags are placed (default: `ArtifactPosition::Back`)
+    #[must_use]
+    pub fn with_artifact_position(mut self, position: ArtifactPosition) -> Self {
+        self.artifact_position = position;
+        self
+    }
+
+    /// Sets the minimum post score required to write a caption (default: None)
+    #[must_use]
+    pub fn with_min_score(mut self, min_score: Option<i64>) -> Self {
+        self.min_score = min_score;
+        self
+    }
+
+    /// Sets the tags that are always dropped (default: empty)
+    #[must_use]
+    pub fn with_blacklist(mut self, blacklist: std::collections::HashSet<String>) -> Self {
+        self.blacklist = blacklist;
+        self
+    }
+
+    /// Sets the tags of which a post must contain at least one (default: None)
+    #[must_use]
+    pub fn with_require_any(
+        mut self,
+        require_any: Option<std::collections::HashSet<String>>,
+    ) -> Self {
+        self.require_any = require_any;
+        self
+    }
+
+    /// Sets the maximum number of general tags (default: None)
+    #[must_use]
+    pub fn with_max_general(mut self, max_general: Option<usize>) -> Self {
+        self.max_general = max_general;
+        self
+    }
+
+    /// Sets per-category maximum tag counts (default: None)
+    #[must_use]
+    pub fn with_max_tags_per_category(
+        mut self,
+        limits: Option<std::collections::HashMap<String, usize>>,
+    ) -> Self {
+        self.max_tags_per_category = limits;
+        self
+    }
+
+    /// Gets the maximum number of tags kept for a category
+    fn category_limit(&self, category: &str) -> usize {
+        let general_limit = self.max_general.filter(|_| category == "general");
+        general_limit
+            .or_else(|| {
+                self.max_tags_per_category
+                    .as_ref()
+                    .and_then(|limits| limits.get(category))
+                    .copied()
+            })
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Sets the order of tag categories (default: None)
+    #[must_use]
+    pub fn with_category_order(mut self, category_order: Option<Vec<String>>) -> Self {
+        self.category_order = category_order;
+        self
+    }
+
+    /// Sets whether to deduplicate tags across categories (default: false)
+    #[must_use]
+    pub fn with_dedupe_across_categories(mut self, dedupe: bool) -> Self {
+        self.dedupe_across_categories = dedupe;
+        self
+    }
+
+    /// Sets whether to escape parentheses in non-artist tags (default: false)
+    #[must_use]
+    pub fn with_escape_parentheses(mut self, escape_parentheses: bool) -> Self {
+        self.escape_parentheses = escape_parentheses;
+        self
+    }
+
+    /// Sets whether to emit post source domains as tags (default: false)
+    #[must_use]
+    pub fn with_include_sources(mut self, include_sources: bool) -> Self {
+        self.include_sources = include_sources;
+        self
+    }
+
+    /// Sets additional regex patterns of tags to ignore (default: None)
+    #[must_use]
+    pub fn with_ignored_patterns(mut self, ignored_patterns: Option<Vec<String>>) -> Self {
+        self.ignored_patterns = ignored_patterns;
+        self
+    }
+
+    /// Sets tag aliases (default: None)
+    #[must_use]
+    pub fn with_aliases(
+        mut self,
+        aliases: Option<std::collections::HashMap<String, String>>,
+    ) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    /// Compiles `ignored_patterns`.
+    ///
+    /// # Errors
+    /// Returns an error naming the first pattern that is not a valid regex.
+    fn compile_ignored_patterns(&self) -> anyhow::Result<Vec<Regex>> {
+        self.ignored_patterns
+            .iter()
+            .flatten()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .map_err(|e| anyhow::anyhow!("Invalid ignored tag pattern '{pattern}': {e}"))
+            })
+            .collect()
+    }
+
+    /// Checks whether a raw tag is filtered out by the built-in or user patterns
+    fn is_ignored(&self, tag: &str, ignored_patterns: &[Regex]) -> bool {
+        self.filter_tags
+            && (should_ignore_e621_tag(tag)
+                || ignored_patterns
+                    .iter()
+                    .any(|pattern| pattern.is_match(tag).unwrap_or(false)))
+    }
+
+    /// Validates the configuration into the rules shared by all posts of a call
+    fn rules(&self) -> anyhow::Result<E621Rules> {
+        Ok(E621Rules {
+            categories: self.categories()?,
+            ignored_patterns: self.compile_ignored_patterns()?,
+        })
+    }
+
+    /// Resolves `category_order` into the categories to process, in order.
+    ///
+    /// # Errors
+    /// Returns an error if `category_order` contains an unknown category name.
+    fn categories(&self) -> anyhow::Result<Vec<&'static str>> {
+        let Some(order) = &self.category_order else {
+            return Ok(E621_CATEGORIES.iter().map(|(name, _)| *name).collect());
+        };
+
+        order
+            .iter()
+            .map(|name| {
+                E621_CATEGORIES
+                    .iter()
+                    .map(|(category, _)| *category)
+                    .find(|category| category == name)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Unknown e621 category '{name}' in category_order, expected one of: artist, character, species, copyright, general, meta"
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    /// Gets the format string to use for the given categories
+    fn get_format(&self, categories: &[&str]) -> String {
+        self.format.clone().unwrap_or_else(|| {
+            std::iter::once("{rating}".to_string())
+                .chain(
+                    categories
+                        .iter()
+                        .map(|category| format!("{{{}}}", category_placeholder(category))),
+                )
+                .chain(self.include_sources.then(|| "{sources}".to_string()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
     }
 
     /// Converts a rating using the configured conversions
@@ -145,30 +414,391 @@ impl E621Config {
         rating.to_string()
     }
 
+    /// Checks whether a post's `score.total` is below the configured minimum
+    fn is_below_min_score(&self, post: &Value) -> bool {
+        let score = post
+            .get("score")
+            .and_then(|score| score.get("total"))
+            .and_then(Value::as_i64);
+        matches!((self.min_score, score), (Some(min_score), Some(score)) if score < min_score)
+    }
+
+    /// Extracts the deduplicated source domains of a post when `include_sources` is set
+    fn source_tags(&self, post: &Value) -> Vec<String> {
+        if !self.include_sources {
+            return Vec::new();
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        post.get("sources")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .filter_map(source_domain)
+            .filter(|domain| seen.insert(domain.clone()))
+            .collect()
+    }
+
+    /// Checks whether a post's tags satisfy `require_any`
+    fn has_required_tag(&self, tags: &serde_json::Map<String, Value>) -> bool {
+        let Some(required) = &self.require_any else {
+            return true;
+        };
+
+        tags.values()
+            .filter_map(Value::as_array)
+            .flatten()
+            .filter_map(Value::as_str)
+            .any(|tag| {
+                required.contains(tag) || required.contains(&underscores_to_spaces(tag, true))
+            })
+    }
+
+    /// Splits meta tags into (artifact tags, remaining meta tags)
+    fn partition_artifacts(&self, meta: Vec<String>) -> (Vec<String>, Vec<String>) {
+        match &self.artifact_tags {
+            Some(artifact_tags) => meta
+                .into_iter()
+                .partition(|tag| artifact_tags.contains(tag)),
+            None => (Vec::new(), meta),
+        }
+    }
+
+    /// Places artifact tags according to `artifact_position`.
+    ///
+    /// Returns the updated caption and, for `ArtifactPosition::Sidecar`, the sidecar content.
+    fn place_artifacts(&self, caption: String, artifacts: &[String]) -> (String, Option<String>) {
+        if artifacts.is_empty() {
+            return (caption, None);
+        }
+        let artifacts = artifacts.join(", ");
+        match self.artifact_position {
+            ArtifactPosition::Front => (format!("{artifacts}, {caption}"), None),
+            ArtifactPosition::Back => (format!("{caption}, {artifacts}"), None),
+            ArtifactPosition::Sidecar => (caption, Some(artifacts)),
+        }
+    }
+
+    /// Normalizes a single tag of the given category.
+    ///
+    /// Returns None if the tag is aliased to an empty string or blacklisted.
+    fn process_tag(&self, category: &str, tag: &str) -> Option<String> {
+        let normalized = underscores_to_spaces(tag, self.replace_underscores);
+
+        // Apply aliases before the blacklist
+        let alias = self
+            .aliases
+            .as_ref()
+            .and_then(|aliases| aliases.get(&normalized).or_else(|| aliases.get(tag)));
+        let normalized = match alias {
+            Some(alias) if alias.is_empty() => return None,
+            Some(alias) => alias.clone(),
+            None => normalized,
+        };
+
+        if self.blacklist.contains(tag) || self.blacklist.contains(&normalized) {
+            return None;
+        }
+
+        if category == "artist" {
+            Some(self.format_artist_name(&normalized))
+        } else if self.escape_parentheses {
+            Some(escape_parens(&normalized))
+        } else {
+            Some(normalized)
+        }
+    }
+
     /// Formats an artist name according to the configuration
     fn format_artist_name(&self, name: &str) -> String {
-        let name = name.replace('_', " ").replace(" (artist)", "");
-        let mut formatted = String::new();
+        format_artist_name(
+            name,
+            self.replace_underscores,
+            self.artist_prefix.as_deref(),
+            self.artist_suffix.as_deref(),
+        )
+    }
+}
+
+/// Danbooru tag categories (the suffixes of the `tag_string_*` fields) in their default
+/// order, with their format placeholder names.
+const DANBOORU_CATEGORIES: [(&str, &str); 5] = [
+    ("artist", "artists"),
+    ("character", "characters"),
+    ("copyright", "copyright"),
+    ("general", "general"),
+    ("meta", "meta"),
+];
+
+/// Configuration for Danbooru caption processing.
+///
+/// Danbooru posts store each tag category as a space-separated `tag_string_{category}`
+/// field instead of e621's `tags` object, so this is a smaller counterpart of
+/// [`E621Config`] covering the options that apply to both sites.
+#[derive(Debug, Clone)]
+pub struct DanbooruConfig {
+    /// Whether to filter out certain tags (years, aspect ratios, etc.), using the same
+    /// rules as [`should_ignore_e621_tag`]
+    pub filter_tags: bool,
+    /// Custom rating conversions. If None, uses the raw rating.
+    /// Danbooru ratings are "g", "s", "q", and "e".
+    /// If a rating is not found in the map, it will be used as-is.
+    pub rating_conversions: Option<std::collections::HashMap<String, String>>,
+    /// Custom format for the caption. Available placeholders:
+    /// - {rating} - The rating (after conversion)
+    /// - {artists} - Artist tags
+    /// - {characters} - Character tags
+    /// - {copyright} - Copyright tags
+    /// - {general} - General tags
+    /// - {meta} - Meta tags
+    ///
+    /// If None, uses the default format: "{rating}, {artists}, {characters}, {copyright}, {general}, {meta}".
+    pub format: Option<String>,
+    /// Optional prefix to add before artist names (default: "by ")
+    pub artist_prefix: Option<String>,
+    /// Optional suffix to add after artist names (default: None)
+    pub artist_suffix: Option<String>,
+    /// Whether to replace underscores with spaces in tags (default: true)
+    pub replace_underscores: bool,
+    /// Tags that are always dropped, regardless of category (default: empty).
+    /// Tags are matched both as written in the post and after underscore replacement.
+    pub blacklist: std::collections::HashSet<String>,
+}
+
+impl Default for DanbooruConfig {
+    fn default() -> Self {
+        let default_conversions = [
+            ("g", "general"),
+            ("s", "sensitive"),
+            ("q", "questionable"),
+            ("e", "explicit"),
+        ]
+        .into_iter()
+        .map(|(rating, name)| (rating.to_string(), name.to_string()))
+        .collect();
+
+        Self {
+            filter_tags: true,
+            rating_conversions: Some(default_conversions),
+            format: None,
+            artist_prefix: Some("by ".to_string()),
+            artist_suffix: None,
+            replace_underscores: true,
+            blacklist: std::collections::HashSet::new(),
+        }
+    }
+}
+
+impl DanbooruConfig {
+    /// Creates a new configuration with default values
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether to filter tags
+    #[must_use]
+    pub fn with_filter_tags(mut self, filter_tags: bool) -> Self {
+        self.filter_tags = filter_tags;
+        self
+    }
+
+    /// Sets custom rating conversions
+    #[must_use]
+    pub fn with_rating_conversions(
+        mut self,
+        conversions: Option<std::collections::HashMap<String, String>>,
+    ) -> Self {
+        self.rating_conversions = conversions;
+        self
+    }
+
+    /// Sets a custom format string
+    #[must_use]
+    pub fn with_format(mut self, format: Option<String>) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the artist name prefix (default: "by ")
+    #[must_use]
+    pub fn with_artist_prefix(mut self, prefix: Option<String>) -> Self {
+        self.artist_prefix = prefix;
+        self
+    }
+
+    /// Sets the artist name suffix (default: None)
+    #[must_use]
+    pub fn with_artist_suffix(mut self, suffix: Option<String>) -> Self {
+        self.artist_suffix = suffix;
+        self
+    }
+
+    /// Sets whether to replace underscores with spaces in tags (default: true)
+    #[must_use]
+    pub fn with_replace_underscores(mut self, replace_underscores: bool) -> Self {
+        self.replace_underscores = replace_underscores;
+        self
+    }
+
+    /// Sets the tags that are always dropped (default: empty)
+    #[must_use]
+    pub fn with_blacklist(mut self, blacklist: std::collections::HashSet<String>) -> Self {
+        self.blacklist = blacklist;
+        self
+    }
+
+    /// Gets the format string to use
+    fn get_format(&self) -> String {
+        self.format.clone().unwrap_or_else(|| {
+            std::iter::once("rating")
+                .chain(
+                    DANBOORU_CATEGORIES
+                        .iter()
+                        .map(|(_, placeholder)| *placeholder),
+                )
+                .map(|name| format!("{{{name}}}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        })
+    }
+
+    /// Converts a rating using the configured conversions
+    fn convert_rating(&self, rating: &str) -> String {
+        self.rating_conversions
+            .as_ref()
+            .and_then(|conversions| conversions.get(rating))
+            .cloned()
+            .unwrap_or_else(|| rating.to_string())
+    }
+
+    /// Normalizes a single tag of the given category.
+    ///
+    /// Returns None if the tag is filtered out or blacklisted.
+    fn process_tag(&self, category: &str, tag: &str) -> Option<String> {
+        if self.filter_tags && should_ignore_e621_tag(tag) {
+            return None;
+        }
 
-        if let Some(prefix) = &self.artist_prefix {
-            formatted.push_str(prefix);
+        let normalized = underscores_to_spaces(tag, self.replace_underscores);
+        if self.blacklist.contains(tag) || self.blacklist.contains(&normalized) {
+            return None;
         }
 
-        formatted.push_str(&name);
+        if category == "artist" {
+            Some(format_artist_name(
+                &normalized,
+                self.replace_underscores,
+                self.artist_prefix.as_deref(),
+                self.artist_suffix.as_deref(),
+            ))
+        } else {
+            Some(normalized)
+        }
+    }
+}
+
+/// Formats an artist name with an optional prefix and suffix, optionally replacing
+/// underscores and dropping a trailing " (artist)" disambiguator.
+fn format_artist_name(
+    name: &str,
+    replace_underscores: bool,
+    prefix: Option<&str>,
+    suffix: Option<&str>,
+) -> String {
+    let name = underscores_to_spaces(name, replace_underscores)
+        .replace(" (artist)", "")
+        .replace("_(artist)", "");
+    let mut formatted = String::new();
 
-        if let Some(suffix) = &self.artist_suffix {
-            formatted.push_str(suffix);
+    if let Some(prefix) = prefix {
+        formatted.push_str(prefix);
+    }
+
+    formatted.push_str(&name);
+
+    if let Some(suffix) = suffix {
+        formatted.push_str(suffix);
+    }
+
+    formatted
+}
+
+/// Removes the separators left behind by empty format placeholders.
+///
+/// The replacements are repeated until nothing changes, so runs of several empty
+/// placeholders collapse to a single separator.
+fn tidy_caption(caption: &str) -> String {
+    let mut caption = caption.to_string();
+    loop {
+        let tidied = caption
+            .replace(", ,", ",")
+            .replace(",,", ",")
+            .replace(" ,", ",");
+        if tidied == caption {
+            break;
         }
+        caption = tidied;
+    }
+    caption.trim_matches(&[' ', ','][..]).to_string()
+}
+
+/// Gets the format placeholder name of an e621 category (e.g. "artist" -> "artists")
+fn category_placeholder(category: &str) -> &'static str {
+    E621_CATEGORIES
+        .iter()
+        .find(|(name, _)| *name == category)
+        .map_or("", |(_, placeholder)| *placeholder)
+}
 
-        formatted
+/// Second-level labels that form a multi-part suffix under a country code TLD,
+/// as in `example.co.uk` or `example.com.au`
+const COUNTRY_SECOND_LEVEL_LABELS: [&str; 8] = ["ac", "co", "com", "edu", "gov", "ne", "net", "or"];
+
+/// Hosting domains whose subdomains are separate sites, as in `user.github.io`
+const HOSTED_SITE_SUFFIXES: [&str; 4] = ["blogspot.com", "github.io", "gitlab.io", "neocities.org"];
+
+/// Extracts the site name from a source URL (e.g. `https://www.pixiv.net/...` -> "pixiv").
+///
+/// The site name is the label before the public suffix. Besides plain TLDs, this
+/// recognizes country code suffixes such as `.co.uk` and `.com.au` and a few
+/// hosting domains such as `github.io`, but it doesn't consult the full Public
+/// Suffix List, so other multi-part suffixes yield their second-to-last label.
+///
+/// Returns None for URLs without a scheme or without a domain name.
+fn source_domain(url: &str) -> Option<String> {
+    let (_, rest) = url.trim().split_once("://")?;
+    let host = rest.split(['/', '?', '#']).next()?;
+    let host = host.rsplit('@').next()?.split(':').next()?.to_lowercase();
+    let labels: Vec<&str> = host.split('.').collect();
+    if labels.iter().any(|label| label.is_empty()) {
+        return None;
     }
+
+    let [.., second_level, top_level] = labels[..] else {
+        return None;
+    };
+    let suffix_len = if (top_level.len() == 2
+        && COUNTRY_SECOND_LEVEL_LABELS.contains(&second_level))
+        || HOSTED_SITE_SUFFIXES.contains(&format!("{second_level}.{top_level}").as_str())
+    {
+        2
+    } else {
+        1
+    };
+    labels
+        .len()
+        .checked_sub(suffix_len + 1)
+        .map(|index| labels[index].to_string())
 }
 
 /// Processes a caption file by reading its contents and interpreting them as either JSON or plain text.
 ///
 /// This function attempts to read the file contents and first tries to parse them as JSON.
-/// If JSON parsing succeeds, it processes the content as a JSON caption. If parsing fails,
-/// it falls back to treating the content as plain text.
+/// If JSON parsing succeeds, it processes the content as a JSON caption. With the `yaml`
+/// feature enabled, it then tries YAML (including `---` front matter) with a `caption`
+/// or `text` field. If parsing fails, it falls back to treating the content as plain text.
 ///
 /// # Arguments
 /// * `path` - A reference to the Path of the caption file to process
@@ -204,6 +834,16 @@ pub async fn process_file(path: &Path) -> anyhow::Result<()> {
             return Ok(());
         }
 
+        // Then as YAML, which accepts almost any text, so require a caption field
+        #[cfg(feature = "yaml")]
+        if let Ok(yaml) = serde_yaml::from_str::<serde_yaml::Value>(yaml_front_matter(&content))
+            && yaml.is_mapping()
+            && let Ok(caption) = yaml_to_text(&yaml)
+        {
+            log::info!("YAML caption for {}: {}", path.display(), caption);
+            return Ok(());
+        }
+
         // If not JSON, treat as plain text
         log::info!(
             "Plain text caption for {}: {}",
@@ -261,29 +901,205 @@ pub fn json_to_text(json: &Value) -> anyhow::Result<String> {
     }
 }
 
-/// Checks if a caption file exists and contains non-whitespace content.
+/// Returns the front matter between leading `---` lines, or the whole content.
+#[cfg(feature = "yaml")]
+fn yaml_front_matter(content: &str) -> &str {
+    let Some(rest) = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))
+    else {
+        return content;
+    };
+    rest.find("\n---").map_or(content, |end| &rest[..end])
+}
+
+/// Converts a YAML value into plain text by extracting the caption content.
+///
+/// Works like [`json_to_text`]: strings are returned directly, and mappings
+/// must have a string `caption` field, or failing that a string `text` field.
+///
+/// Requires the `yaml` feature.
 ///
 /// # Arguments
-/// * `path` - A reference to the Path of the caption file to check
+/// * `yaml` - A reference to a `serde_yaml` Value containing the caption data
 ///
 /// # Returns
-/// * `true` if the file exists and contains non-whitespace content
-/// * `false` if the file doesn't exist, can't be read, or is empty/whitespace-only
+/// * `Ok(String)` - The extracted caption text
+/// * `Err` - If the YAML format is not supported or missing required fields
+///
+/// # Errors
+/// Returns an error if:
+/// * The YAML value is neither a string nor a mapping
+/// * The mapping has no string `caption` or `text` field
 ///
 /// # Example
-/// ```no_run
-/// use std::path::Path;
-/// use dset::caption::caption_file_exists_and_not_empty;
+/// ```
+/// use dset::caption::yaml_to_text;
 ///
-/// async fn example() -> bool {
-///     let path = Path::new("caption.txt");
-///     caption_file_exists_and_not_empty(&path).await
+/// # fn main() -> anyhow::Result<()> {
+/// let yaml: serde_yaml::Value = serde_yaml::from_str("caption: Hello world")?;
+/// assert_eq!(yaml_to_text(&yaml)?, "Hello world");
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "yaml")]
+pub fn yaml_to_text(yaml: &serde_yaml::Value) -> anyhow::Result<String> {
+    match yaml {
+        serde_yaml::Value::String(s) => Ok(s.clone()),
+        serde_yaml::Value::Mapping(map) => ["caption", "text"]
+            .iter()
+            .find_map(|key| map.get(*key).and_then(serde_yaml::Value::as_str))
+            .map(String::from)
+            .ok_or_else(|| anyhow::anyhow!("No caption or text field found in YAML mapping")),
+        _ => Err(anyhow::anyhow!("Unsupported YAML format")),
+    }
+}
+
+/// Converts a plain-text caption file into a JSON caption file.
+///
+/// The text is split with [`crate::split_content`] and written to `out_path` as
+/// `{"caption": "...", "tags": [...]}`. Content without the `"., "` delimiter is
+/// all tags, with an empty caption. [`json_to_txt`] converts back.
+///
+/// # Arguments
+/// * `txt_path` - Path of the plain-text caption file
+/// * `out_path` - Path of the JSON file to write
+///
+/// # Returns
+/// * `anyhow::Result<()>` - Success or failure of the operation
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The file cannot be read or decoded as UTF-8 text
+/// * The JSON file cannot be written
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::txt_to_json;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     txt_to_json(Path::new("image.txt"), Path::new("image.json")).await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn txt_to_json(txt_path: &Path, out_path: &Path) -> anyhow::Result<()> {
+    let content = tokio::fs::read_to_string(txt_path).await?;
+    let (tags, caption) = crate::split_content(content.trim());
+    let tags: Vec<String> = tags.into_iter().filter(|tag| !tag.is_empty()).collect();
+
+    let json = serde_json::json!({
+        "caption": caption.trim(),
+        "tags": tags,
+    });
+    tokio::fs::write(out_path, serde_json::to_string_pretty(&json)?).await?;
+    Ok(())
+}
+
+/// Converts a JSON caption file back into a plain-text caption file.
+///
+/// This is the inverse of [`txt_to_json`]: the `tags` array and `caption` field
+/// are joined as `tags., caption`, dropping whichever part is empty. JSON without
+/// a `tags` array is read with [`json_to_text`].
+///
+/// # Arguments
+/// * `json_path` - Path of the JSON caption file
+/// * `out_path` - Path of the plain-text file to write
+///
+/// # Returns
+/// * `anyhow::Result<()>` - Success or failure of the operation
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The file cannot be read or parsed as JSON
+/// * The JSON has no tags and is not supported by [`json_to_text`]
+/// * The text file cannot be written
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::json_to_txt;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     json_to_txt(Path::new("image.json"), Path::new("image.txt")).await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn json_to_txt(json_path: &Path, out_path: &Path) -> anyhow::Result<()> {
+    let content = tokio::fs::read_to_string(json_path).await?;
+    let json: Value = serde_json::from_str(&content)?;
+    let tags: Vec<&str> = json
+        .get("tags")
+        .and_then(Value::as_array)
+        .map(|tags| tags.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+
+    let caption = if tags.is_empty() {
+        json_to_text(&json)?
+    } else {
+        json.get("caption")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string()
+    };
+    let text = crate::concat::combine_tags_and_caption(&tags.join(", "), caption.trim(), "., ");
+    tokio::fs::write(out_path, text).await?;
+    Ok(())
+}
+
+/// Checks if a caption file exists and contains non-whitespace content.
+///
+/// # Arguments
+/// * `path` - A reference to the Path of the caption file to check
+///
+/// # Returns
+/// * `true` if the file exists and contains non-whitespace content
+/// * `false` if the file doesn't exist, can't be read, or is empty/whitespace-only
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::caption_file_exists_and_not_empty;
+///
+/// async fn example() -> bool {
+///     let path = Path::new("caption.txt");
+///     caption_file_exists_and_not_empty(&path).await
 /// }
 /// ```
 pub async fn caption_file_exists_and_not_empty(path: &Path) -> bool {
+    caption_file_meets_min_length(path, 1).await
+}
+
+/// Checks if a caption file exists and contains at least `min_chars` characters.
+///
+/// Leading and trailing whitespace is ignored and characters are counted as
+/// Unicode scalar values, so this can be used to flag stub captions that are
+/// technically non-empty.
+///
+/// # Arguments
+/// * `path` - A reference to the Path of the caption file to check
+/// * `min_chars` - Minimum number of characters the trimmed content must have
+///
+/// # Returns
+/// * `true` if the file exists and its trimmed content has at least `min_chars` characters
+/// * `false` if the file doesn't exist, can't be read, or is too short
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::caption_file_meets_min_length;
+///
+/// async fn example() -> bool {
+///     let path = Path::new("caption.txt");
+///     caption_file_meets_min_length(&path, 20).await
+/// }
+/// ```
+pub async fn caption_file_meets_min_length(path: &Path, min_chars: usize) -> bool {
     if path.exists() {
         match tokio::fs::read_to_string(path).await {
-            Ok(content) => !content.trim().is_empty(),
+            Ok(content) => content.trim().chars().count() >= min_chars,
             Err(_) => false,
         }
     } else {
@@ -291,6 +1107,102 @@ pub async fn caption_file_exists_and_not_empty(path: &Path) -> bool {
     }
 }
 
+/// Finds images that have no usable caption file.
+///
+/// Walks `dir` recursively and returns every file with one of `image_exts` whose
+/// sibling `stem.caption_ext` is missing or empty, as determined by
+/// [`caption_file_exists_and_not_empty`]. Extensions are compared
+/// case-insensitively and may be given with or without a leading dot.
+///
+/// # Arguments
+/// * `dir` - Directory to search recursively
+/// * `image_exts` - Extensions of the image files (e.g. `["png", "jpg"]`)
+/// * `caption_ext` - Extension of the caption files (e.g. "txt")
+///
+/// # Returns
+/// * `anyhow::Result<Vec<PathBuf>>` - Sorted paths of the images without a caption
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be walked
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::find_missing_captions;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     for image in find_missing_captions(Path::new("dataset"), &["png", "jpg"], "txt").await? {
+///         println!("No caption: {}", image.display());
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn find_missing_captions(
+    dir: &Path,
+    image_exts: &[&str],
+    caption_ext: &str,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let caption_ext = caption_ext.trim_start_matches('.');
+    let mut missing = Vec::new();
+    for image in files_with_extensions(dir, image_exts).await? {
+        if !caption_file_exists_and_not_empty(&image.with_extension(caption_ext)).await {
+            missing.push(image);
+        }
+    }
+    Ok(missing)
+}
+
+/// Finds caption files that have no matching image.
+///
+/// Walks `dir` recursively and returns every file with one of `caption_exts` that
+/// has no sibling file with the same stem and one of `image_exts`, e.g. a leftover
+/// `image.txt` after `image.png` was culled. Extensions are compared
+/// case-insensitively and may be given with or without a leading dot.
+///
+/// # Arguments
+/// * `dir` - Directory to search recursively
+/// * `image_exts` - Extensions of the image files (e.g. `["png", "jpg"]`)
+/// * `caption_exts` - Extensions of the caption files (e.g. `["txt", "caption"]`)
+///
+/// # Returns
+/// * `anyhow::Result<Vec<PathBuf>>` - Sorted paths of the orphaned caption files
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be walked
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::find_orphan_captions;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let orphans = find_orphan_captions(Path::new("dataset"), &["png", "jpg"], &["txt"]).await?;
+///     for caption in orphans {
+///         tokio::fs::remove_file(caption).await?;
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn find_orphan_captions(
+    dir: &Path,
+    image_exts: &[&str],
+    caption_exts: &[&str],
+) -> anyhow::Result<Vec<PathBuf>> {
+    let images: std::collections::HashSet<PathBuf> = files_with_extensions(dir, image_exts)
+        .await?
+        .iter()
+        .map(|image| image.with_extension(""))
+        .collect();
+
+    Ok(files_with_extensions(dir, caption_exts)
+        .await?
+        .into_iter()
+        .filter(|caption| !images.contains(&caption.with_extension("")))
+        .collect())
+}
+
 /// Patterns of tags to be ignored during e621 tag processing.
 pub const IGNORED_E621_TAGS: [&str; 3] = [
     r"^conditional_dnp$",
@@ -298,6 +1210,14 @@ pub const IGNORED_E621_TAGS: [&str; 3] = [
     r"^\d+:\d+$", // Aspect ratio
 ];
 
+/// `IGNORED_E621_TAGS` compiled once on first use.
+static IGNORED_E621_REGEXES: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    IGNORED_E621_TAGS
+        .iter()
+        .map(|pattern| Regex::new(pattern).unwrap())
+        .collect()
+});
+
 /// Checks if a tag should be ignored based on predefined patterns.
 ///
 /// # Arguments
@@ -312,13 +1232,13 @@ pub const IGNORED_E621_TAGS: [&str; 3] = [
 ///
 /// This function will panic if:
 /// * Any of the predefined patterns in `IGNORED_E621_TAGS` cannot be compiled into a valid regular expression
+///   (the patterns are compiled once, on the first call)
 /// * Pattern matching fails due to regex engine errors
 #[must_use]
 pub fn should_ignore_e621_tag(tag: &str) -> bool {
-    IGNORED_E621_TAGS.iter().any(|&ignored_tag_pattern| {
-        let pattern = Regex::new(ignored_tag_pattern).unwrap();
-        pattern.is_match(tag).unwrap_or(false)
-    })
+    IGNORED_E621_REGEXES
+        .iter()
+        .any(|pattern| pattern.is_match(tag).unwrap_or(false))
 }
 
 /// Processes and formats e621 tags from the JSON data.
@@ -331,253 +1251,2512 @@ pub fn should_ignore_e621_tag(tag: &str) -> bool {
 /// # Returns
 ///
 /// * `Vec<String>` - A vector of strings containing processed and formatted tags.
+///
+/// Categories are emitted in `config.category_order`. Since this function cannot
+/// fail, an invalid `category_order` is logged and the default order is used instead.
+/// Likewise, invalid `config.ignored_patterns` are logged and only the built-in
+/// patterns are applied.
 #[must_use]
 pub fn process_e621_tags(tags_dict: &Value, config: Option<&E621Config>) -> Vec<String> {
     let default_config = E621Config::default();
     let config = config.unwrap_or(&default_config);
     let mut processed_tags = Vec::new();
+    let ignored_patterns = config.compile_ignored_patterns().unwrap_or_else(|err| {
+        log::warn!("{err}; using the built-in ignored tag patterns");
+        Vec::new()
+    });
+
+    if let Value::Object(tags) = tags_dict {
+        // Process each tag category
+        let process_category = |category: &str| {
+            tags.get(category)
+                .and_then(|t| t.as_array())
+                .map(|tags| {
+                    tags.iter()
+                        .filter_map(|tag| tag.as_str())
+                        .filter(|&tag| !config.is_ignored(tag, &ignored_patterns))
+                        .filter_map(|tag| config.process_tag(category, tag))
+                        .collect::<Vec<String>>()
+                })
+                .unwrap_or_default()
+        };
+
+        // Process each category in order
+        let categories = config.categories().unwrap_or_else(|err| {
+            log::warn!("{err}; using the default category order");
+            E621_CATEGORIES.iter().map(|(name, _)| *name).collect()
+        });
+        let mut seen = std::collections::HashSet::new();
+        for category in categories {
+            let mut tags = process_category(category);
+            if config.dedupe_across_categories {
+                tags.retain(|tag| seen.insert(tag.clone()));
+            }
+            processed_tags.extend(tags);
+        }
+    }
+
+    processed_tags
+}
+
+/// Processes JSON data from e621 and creates a caption file.
+///
+/// Accepts either a single post (`{"post": {...}}`) or an export containing several
+/// posts (`{"posts": [...]}`). In the latter case one caption is written per post,
+/// named after each post's file URL stem. Every post is processed even if an earlier
+/// one fails, and all failures are reported together in the returned error.
+///
+/// # Arguments
+///
+/// * `data` - A reference to the JSON Value containing e621 post data
+/// * `file_path` - A reference to an Arc<PathBuf> representing the target file path
+/// * `config` - Optional configuration for processing. If None, uses default settings.
+///
+/// # Returns
+///
+/// * `anyhow::Result<()>` - Success or failure of the operation
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The caption file cannot be written to disk
+/// * The JSON data structure doesn't match the expected format
+/// * `config.category_order` contains an unknown category name
+/// * `config.ignored_patterns` contains an invalid regex
+/// * A post's file URL has no valid file stem
+/// * Any post of a multi-post export fails to process
+///
+/// # Example
+/// ```no_run
+/// use std::path::PathBuf;
+/// use std::sync::Arc;
+/// use serde_json::json;
+/// use dset::caption::process_e621_json_data;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let data = json!({
+///         "post": {
+///             "file": {
+///                 "url": "https://example.com/image.jpg"
+///             },
+///             "rating": "s",
+///             "tags": {}
+///         }
+///     });
+///     let path = Arc::new(PathBuf::from("output.txt"));
+///     process_e621_json_data(&data, &path, None).await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn process_e621_json_data(
+    data: &Value,
+    file_path: &Arc<PathBuf>,
+    config: Option<E621Config>,
+) -> anyhow::Result<()> {
+    let config = config.unwrap_or_default();
+    let rules = config.rules()?;
+
+    if let Some(post) = data.get("post") {
+        process_e621_post(post, file_path, &config, &rules).await?;
+    } else if let Some(Value::Array(posts)) = data.get("posts") {
+        // Process every post and report all failures together
+        let mut errors = Vec::new();
+        for (index, post) in posts.iter().enumerate() {
+            if let Err(err) = process_e621_post(post, file_path, &config, &rules).await {
+                errors.push(format!("post {index}: {err}"));
+            }
+        }
+
+        if !errors.is_empty() {
+            anyhow::bail!(
+                "Failed to process {} of {} e621 posts: {}",
+                errors.len(),
+                posts.len(),
+                errors.join("; ")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Parts of an `E621Config` that are validated once per call and shared by all posts.
+struct E621Rules {
+    /// Categories to process, in order
+    categories: Vec<&'static str>,
+    /// Compiled `ignored_patterns`
+    ignored_patterns: Vec<Regex>,
+}
+
+/// Builds the caption for an e621 post without writing anything to disk.
+///
+/// This produces exactly the string [`process_e621_json_data`] writes to the caption
+/// file, which makes the formatting easy to test or to feed into an in-memory pipeline.
+/// With `ArtifactPosition::Sidecar`, artifact tags are left out just as they are left
+/// out of the written caption.
+///
+/// Only single-post data is accepted. Use [`build_e621_captions`] for multi-post
+/// exports (`{"posts": [...]}`), which write one caption per post.
+///
+/// # Arguments
+///
+/// * `data` - A reference to the JSON Value containing a single e621 post (`{"post": {...}}`)
+/// * `config` - Configuration for processing
+///
+/// # Returns
+///
+/// * `anyhow::Result<Option<String>>` - The caption, or None when no caption file would be
+///   written (no post, no file URL, no tags, or the post is filtered out by the config)
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * `data` is a multi-post export (`{"posts": [...]}`)
+/// * `config.category_order` contains an unknown category name
+/// * `config.ignored_patterns` contains an invalid regex
+/// * The post's file URL has no valid file stem
+///
+/// # Example
+/// ```
+/// use serde_json::json;
+/// use dset::caption::{E621Config, build_e621_caption};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let data = json!({
+///     "post": {
+///         "file": {"url": "https://example.com/image.jpg"},
+///         "rating": "s",
+///         "tags": {"general": ["solo"], "species": ["wolf"]}
+///     }
+/// });
+/// let config = E621Config::new().with_format(Some("{rating}, {species}, {general}".to_string()));
+/// let caption = build_e621_caption(&data, &config)?;
+/// assert_eq!(caption.as_deref(), Some("safe, wolf, solo"));
+/// # Ok(())
+/// # }
+/// ```
+pub fn build_e621_caption(data: &Value, config: &E621Config) -> anyhow::Result<Option<String>> {
+    let rules = config.rules()?;
+    let Some(post) = data.get("post") else {
+        if data.get("posts").is_some_and(Value::is_array) {
+            anyhow::bail!("Multi-post e621 exports need build_e621_captions");
+        }
+        return Ok(None);
+    };
+
+    let Some((caption_content, _)) = build_post_caption(post, config, &rules) else {
+        return Ok(None);
+    };
+    Ok(post_file_stem(post)?.map(|_| caption_content))
+}
+
+/// Builds the captions of every post in e621 JSON data without writing anything to disk.
+///
+/// Accepts the same single-post (`{"post": {...}}`) and multi-post (`{"posts": [...]}`)
+/// data as [`process_e621_json_data`] and returns, for each caption file it would
+/// write, the file stem and the caption, in post order. Posts that would not be
+/// written are left out.
+///
+/// # Arguments
+///
+/// * `data` - A reference to the JSON Value containing one or more e621 posts
+/// * `config` - Configuration for processing
+///
+/// # Returns
+///
+/// * `anyhow::Result<Vec<(String, String)>>` - The file stem and caption of each post
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * `config.category_order` contains an unknown category name
+/// * `config.ignored_patterns` contains an invalid regex
+/// * A post's file URL has no valid file stem
+///
+/// # Example
+/// ```
+/// use serde_json::json;
+/// use dset::caption::{E621Config, build_e621_captions};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let data = json!({
+///     "posts": [
+///         {"file": {"url": "https://example.com/a.jpg"}, "rating": "s", "tags": {"general": ["solo"]}},
+///         {"file": {"url": "https://example.com/b.jpg"}, "rating": "e", "tags": {"general": ["duo"]}}
+///     ]
+/// });
+/// let config = E621Config::new().with_format(Some("{rating}, {general}".to_string()));
+/// let captions = build_e621_captions(&data, &config)?;
+/// assert_eq!(captions[1], ("b".to_string(), "explicit, duo".to_string()));
+/// # Ok(())
+/// # }
+/// ```
+pub fn build_e621_captions(
+    data: &Value,
+    config: &E621Config,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let rules = config.rules()?;
+    let posts: Vec<&Value> = if let Some(post) = data.get("post") {
+        vec![post]
+    } else if let Some(Value::Array(posts)) = data.get("posts") {
+        posts.iter().collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut captions = Vec::new();
+    for (index, post) in posts.into_iter().enumerate() {
+        let Some((caption_content, _)) = build_post_caption(post, config, &rules) else {
+            continue;
+        };
+        if let Some(stem) = post_file_stem(post).with_context(|| format!("post {index}"))? {
+            captions.push((stem.to_string(), caption_content));
+        }
+    }
+    Ok(captions)
+}
+
+/// Gets the file stem of a post's file URL, which names its caption file.
+///
+/// Returns None if the post has no file URL.
+fn post_file_stem(post: &Value) -> anyhow::Result<Option<&str>> {
+    let Some(url) = post
+        .get("file")
+        .and_then(|file_data| file_data.get("url"))
+        .and_then(|u| u.as_str())
+    else {
+        return Ok(None);
+    };
+
+    Path::new(url)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .map(Some)
+        .ok_or_else(|| anyhow::anyhow!("Invalid file URL in e621 post: {url}"))
+}
+
+/// Builds the caption and, for `ArtifactPosition::Sidecar`, the artifacts sidecar content
+/// of a single post. Returns None when no caption should be written.
+fn build_post_caption(
+    post: &Value,
+    config: &E621Config,
+    rules: &E621Rules,
+) -> Option<(String, Option<String>)> {
+    // Skip low-quality posts entirely
+    if config.is_below_min_score(post) {
+        return None;
+    }
+
+    let rating = post.get("rating").and_then(|r| r.as_str()).unwrap_or("q");
+    let rating = config.convert_rating(rating);
+
+    let mut tag_groups = std::collections::HashMap::new();
+    tag_groups.insert("rating", rating);
+
+    let Some(Value::Object(tags)) = post.get("tags") else {
+        return None;
+    };
+
+    // Skip posts without any of the required tags
+    if !config.has_required_tag(tags) {
+        return None;
+    }
+
+    // Process each category
+    let process_category = |category: &str| {
+        tags.get(category)
+            .and_then(|t| t.as_array())
+            .map(|tags| {
+                tags.iter()
+                    .filter_map(|tag| tag.as_str())
+                    .filter(|&tag| !config.is_ignored(tag, &rules.ignored_patterns))
+                    .filter_map(|tag| config.process_tag(category, tag))
+                    .take(config.category_limit(category))
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default()
+    };
+
+    // Process each category in the configured order
+    let mut artifacts = Vec::new();
+    let mut seen = std::collections::HashSet::new();
+    for &category in &rules.categories {
+        let mut category_tags = process_category(category);
+        if config.dedupe_across_categories {
+            category_tags.retain(|tag| seen.insert(tag.clone()));
+        }
+        if category == "meta" {
+            (artifacts, category_tags) = config.partition_artifacts(category_tags);
+        }
+
+        // Only add non-empty categories
+        if !category_tags.is_empty() {
+            tag_groups.insert(category_placeholder(category), category_tags.join(", "));
+        }
+    }
+
+    // Add source domains after the tag categories
+    let sources = config.source_tags(post);
+    if !sources.is_empty() {
+        tag_groups.insert("sources", sources.join(", "));
+    }
+
+    // Apply the format
+    let mut caption_content = config.get_format(&rules.categories);
+    for (key, value) in &tag_groups {
+        caption_content = caption_content.replace(&format!("{{{key}}}"), value);
+    }
+
+    if config.include_sources {
+        caption_content = caption_content.replace("{sources}", "");
+    }
+
+    // Drop the placeholders of categories left out of the order
+    for (category, placeholder) in E621_CATEGORIES {
+        if !rules.categories.contains(&category) {
+            caption_content = caption_content.replace(&format!("{{{placeholder}}}"), "");
+        }
+    }
+
+    // Clean up empty placeholders
+    caption_content = tidy_caption(&caption_content);
+
+    // Route artifact tags to the configured position
+    let (caption_content, sidecar_content) = config.place_artifacts(caption_content, &artifacts);
+
+    // Only write if we have content and either filtering is disabled or we have non-rating tags
+    (!caption_content.trim().is_empty() && (!config.filter_tags || tag_groups.len() > 1))
+        .then_some((caption_content, sidecar_content))
+}
+
+/// Processes a single e621 post and writes its caption file next to `file_path`.
+async fn process_e621_post(
+    post: &Value,
+    file_path: &Arc<PathBuf>,
+    config: &E621Config,
+    rules: &E621Rules,
+) -> anyhow::Result<()> {
+    use crate::xio::write_to_file;
+
+    let Some((caption_content, sidecar_content)) = build_post_caption(post, config, rules) else {
+        return Ok(());
+    };
+    let Some(filename) = post_file_stem(post)? else {
+        return Ok(());
+    };
+
+    let caption_path = file_path.with_file_name(format!("{filename}.txt"));
+    write_to_file(&caption_path, &caption_content).await?;
+    if let Some(sidecar_content) = sidecar_content {
+        let sidecar_path = file_path.with_file_name(format!("{filename}.artifacts"));
+        write_to_file(&sidecar_path, &sidecar_content).await?;
+    }
+    Ok(())
+}
+
+/// Processes Danbooru JSON data and creates caption files.
+///
+/// Danbooru's API returns posts with space-separated `tag_string_artist`,
+/// `tag_string_character`, `tag_string_copyright`, `tag_string_general`, and
+/// `tag_string_meta` fields. `data` may be a single post object or an array of posts,
+/// as returned by `/posts/{id}.json` and `/posts.json`. Each post's caption is written
+/// next to `file_path` as `{stem}.txt`, where the stem comes from the post's `file_url`,
+/// falling back to its `md5`. Posts without either, or without any tags left after
+/// filtering, are skipped.
+///
+/// # Arguments
+///
+/// * `data` - A reference to the JSON Value containing one Danbooru post or an array of posts
+/// * `file_path` - Path to the JSON file, whose directory receives the caption files
+/// * `config` - Optional configuration for processing. If None, uses default settings.
+///
+/// # Returns
+///
+/// * `anyhow::Result<()>` - Success or failure of the operation
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The caption file cannot be written to disk
+/// * A post's file URL has no valid file stem
+/// * Any post of an array fails to process
+///
+/// # Example
+/// ```no_run
+/// use std::path::PathBuf;
+/// use std::sync::Arc;
+/// use serde_json::json;
+/// use dset::caption::process_danbooru_json_data;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let data = json!({
+///         "file_url": "https://example.com/image.jpg",
+///         "rating": "g",
+///         "tag_string_artist": "some_artist",
+///         "tag_string_general": "1girl solo smile"
+///     });
+///     let path = Arc::new(PathBuf::from("output.json"));
+///     process_danbooru_json_data(&data, &path, None).await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn process_danbooru_json_data(
+    data: &Value,
+    file_path: &Arc<PathBuf>,
+    config: Option<DanbooruConfig>,
+) -> anyhow::Result<()> {
+    let config = config.unwrap_or_default();
+
+    if let Value::Array(posts) = data {
+        // Process every post and report all failures together
+        let mut errors = Vec::new();
+        for (index, post) in posts.iter().enumerate() {
+            if let Err(err) = process_danbooru_post(post, file_path, &config).await {
+                errors.push(format!("post {index}: {err}"));
+            }
+        }
+
+        if !errors.is_empty() {
+            anyhow::bail!(
+                "Failed to process {} of {} Danbooru posts: {}",
+                errors.len(),
+                posts.len(),
+                errors.join("; ")
+            );
+        }
+    } else {
+        process_danbooru_post(data, file_path, &config).await?;
+    }
+    Ok(())
+}
+
+/// Builds the caption of a single Danbooru post. Returns None when no caption should be written.
+fn build_danbooru_caption(post: &Value, config: &DanbooruConfig) -> Option<String> {
+    let rating = post.get("rating").and_then(Value::as_str).unwrap_or("q");
+
+    let mut tag_groups = std::collections::HashMap::new();
+    tag_groups.insert("rating", config.convert_rating(rating));
+
+    for (category, placeholder) in DANBOORU_CATEGORIES {
+        let tags: Vec<String> = post
+            .get(format!("tag_string_{category}"))
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .split_whitespace()
+            .filter_map(|tag| config.process_tag(category, tag))
+            .collect();
+
+        // Only add non-empty categories
+        if !tags.is_empty() {
+            tag_groups.insert(placeholder, tags.join(", "));
+        }
+    }
+    if tag_groups.len() == 1 {
+        return None;
+    }
+
+    // Apply the format and clean up empty placeholders
+    let mut caption_content = config.get_format();
+    for (key, value) in &tag_groups {
+        caption_content = caption_content.replace(&format!("{{{key}}}"), value);
+    }
+    for (_, placeholder) in DANBOORU_CATEGORIES {
+        caption_content = caption_content.replace(&format!("{{{placeholder}}}"), "");
+    }
+    let caption_content = tidy_caption(&caption_content);
+
+    (!caption_content.is_empty()).then_some(caption_content)
+}
+
+/// Processes a single Danbooru post and writes its caption file next to `file_path`.
+async fn process_danbooru_post(
+    post: &Value,
+    file_path: &Arc<PathBuf>,
+    config: &DanbooruConfig,
+) -> anyhow::Result<()> {
+    use crate::xio::write_to_file;
+
+    let Some(caption_content) = build_danbooru_caption(post, config) else {
+        return Ok(());
+    };
+
+    let filename = if let Some(url) = post.get("file_url").and_then(Value::as_str) {
+        Path::new(url)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| anyhow::anyhow!("Invalid file URL in Danbooru post: {url}"))?
+    } else if let Some(md5) = post.get("md5").and_then(Value::as_str) {
+        md5
+    } else {
+        return Ok(());
+    };
+
+    let caption_path = file_path.with_file_name(format!("{filename}.txt"));
+    write_to_file(&caption_path, &caption_content).await?;
+    Ok(())
+}
+
+/// Formats text content by removing excessive whitespace and newlines.
+///
+/// This function cleans up text content by:
+/// - Trimming leading/trailing whitespace
+/// - Replacing multiple consecutive spaces with a single space
+/// - Normalizing line endings
+///
+/// # Arguments
+/// * `content` - A string slice containing the text to format
+///
+/// # Returns
+/// * `anyhow::Result<String>` - The formatted text content
+///
+/// # Errors
+///
+/// This function currently does not return any errors, but returns Result
+/// for consistency with other functions in the module and to allow for
+/// future error handling.
+///
+/// # Example
+/// ```
+/// use dset::caption::format_text_content;
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let text = "  This   has  extra   spaces \n\n and newlines  ";
+/// let formatted = format_text_content(text)?;
+/// assert_eq!(formatted, "This has extra spaces and newlines");
+/// # Ok(())
+/// # }
+/// ```
+pub fn format_text_content(content: &str) -> anyhow::Result<String> {
+    // Trim and normalize content
+    let content = content.trim();
+
+    // Replace multiple spaces with a single space
+    let content = content.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    Ok(content)
+}
+
+/// Formats text content like [`format_text_content`] while keeping paragraph breaks.
+///
+/// Runs of spaces and tabs within a line are collapsed to a single space and every
+/// line is trimmed, but line structure is kept: consecutive non-empty lines stay on
+/// separate lines and any run of blank lines becomes a single `\n\n` paragraph break.
+/// Leading and trailing blank lines are removed.
+///
+/// # Arguments
+/// * `content` - A string slice containing the text to format
+///
+/// # Returns
+/// * `anyhow::Result<String>` - The formatted text content
+///
+/// # Errors
+///
+/// This function currently does not return any errors, but returns Result
+/// for consistency with [`format_text_content`].
+///
+/// # Example
+/// ```
+/// use dset::caption::format_text_content_preserving_paragraphs;
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let text = "  First   paragraph. \n\n\n\tSecond  paragraph.  ";
+/// let formatted = format_text_content_preserving_paragraphs(text)?;
+/// assert_eq!(formatted, "First paragraph.\n\nSecond paragraph.");
+/// # Ok(())
+/// # }
+/// ```
+pub fn format_text_content_preserving_paragraphs(content: &str) -> anyhow::Result<String> {
+    let mut formatted = String::new();
+    let mut paragraph_break = false;
+
+    for line in content.lines() {
+        // Collapse whitespace within the line
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if line.is_empty() {
+            paragraph_break = true;
+            continue;
+        }
+
+        if !formatted.is_empty() {
+            formatted.push_str(if paragraph_break { "\n\n" } else { "\n" });
+        }
+        formatted.push_str(&line);
+        paragraph_break = false;
+    }
+
+    Ok(formatted)
+}
+
+/// Replaces all instances of a search string with a replacement string in a file.
+///
+/// This function reads a file, replaces all occurrences of a search string with
+/// a replacement string, and writes the result back to the file if changes were made.
+///
+/// # Arguments
+/// * `path` - A reference to the Path of the file to process
+/// * `search` - The string to search for
+/// * `replace` - The string to replace with
+///
+/// # Returns
+/// * `anyhow::Result<()>` - Success or failure of the operation
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The file cannot be read from the filesystem
+/// * The file contents cannot be decoded as UTF-8 text
+/// * The modified content cannot be written back to the file
+/// * The text content formatting fails when the replacement string is empty
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::replace_string;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let path = Path::new("caption.txt");
+///     replace_string(path, "old text", "new text").await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn replace_string(path: &Path, search: &str, replace: &str) -> anyhow::Result<()> {
+    // Skip if search string is empty
+    if search.is_empty() {
+        return Ok(());
+    }
+
+    // Read the file content
+    let content = tokio::fs::read_to_string(path).await?;
+
+    // Replace the search string with the replacement string
+    let new_content = replace_in_content(&content, search, replace)?;
+
+    // Only write back if there were changes
+    if content != new_content {
+        tokio::fs::write(path, new_content).await?;
+    }
+
+    Ok(())
+}
+
+/// Applies the [`replace_string`] replacement rules to already-read content.
+fn replace_in_content(content: &str, search: &str, replace: &str) -> anyhow::Result<String> {
+    let mut new_content = content.replace(search, replace);
+
+    // If the replacement string is empty, format the text content
+    if replace.is_empty() {
+        new_content = format_text_content(&new_content)?;
+    }
+
+    Ok(new_content)
+}
+
+/// Replaces all instances of a search string in every matching file below a directory.
+///
+/// Walks `dir` recursively with `xio::walk_directory` and applies the same replacement
+/// rules as [`replace_string`] to every file whose name matches `glob`. Files that cannot
+/// be read as UTF-8 text (e.g. binary files) are skipped with a warning instead of aborting
+/// the walk.
+///
+/// # Arguments
+/// * `dir` - Directory to search recursively
+/// * `glob` - File name pattern supporting `*` and `?` (e.g. "*.txt" or "img_*.txt"),
+///   matched case-insensitively. A bare extension without wildcards or a dot, such as
+///   "txt", is treated as "*.txt"
+/// * `search` - The string to search for
+/// * `replace` - The string to replace with
+///
+/// # Returns
+/// * `anyhow::Result<usize>` - Number of files that were actually modified
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The directory cannot be walked
+/// * A modified file cannot be written back
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::replace_string_in_dir;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let modified = replace_string_in_dir(Path::new("dataset"), "*.txt", "old tag", "new tag").await?;
+///     println!("Modified {modified} files");
+///     Ok(())
+/// }
+/// ```
+pub async fn replace_string_in_dir(
+    dir: &Path,
+    glob: &str,
+    search: &str,
+    replace: &str,
+) -> anyhow::Result<usize> {
+    // Skip if search string is empty
+    if search.is_empty() {
+        return Ok(0);
+    }
+
+    let pattern = if glob.contains(['*', '?', '.']) {
+        glob.to_string()
+    } else {
+        format!("*.{glob}")
+    };
+
+    let mut modified = 0;
+    for path in files_with_extensions(dir, &["*"]).await? {
+        let name_matches = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| glob_matches(&pattern, name));
+        if !name_matches {
+            continue;
+        }
+
+        let content = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => content,
+            Err(err) => {
+                log::warn!("Skipping unreadable file {}: {}", path.display(), err);
+                continue;
+            }
+        };
+
+        let new_content = replace_in_content(&content, search, replace)?;
+        if content != new_content {
+            tokio::fs::write(&path, new_content).await?;
+            modified += 1;
+        }
+    }
+
+    Ok(modified)
+}
+
+/// Matches a file name against a glob pattern, ignoring case.
+///
+/// `*` matches any run of characters (including none) and `?` matches exactly one;
+/// every other character matches itself.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name index it currently absorbs up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, absorbed)) = backtrack {
+            // Let the last `*` swallow one more character and retry
+            backtrack = Some((star, absorbed + 1));
+            p = star + 1;
+            n = absorbed + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Replaces all instances of a search string with a replacement string in a file, ignoring case.
+///
+/// This behaves like [`replace_string`], except that "Photo", "photo" and "PHOTO" are all
+/// matched by a single call. The search string is matched literally and the replacement
+/// is inserted as-is; surrounding text keeps its original casing.
+///
+/// # Arguments
+/// * `path` - A reference to the Path of the file to process
+/// * `search` - The string to search for, regardless of case
+/// * `replace` - The string to replace with
+///
+/// # Returns
+/// * `anyhow::Result<()>` - Success or failure of the operation
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The file cannot be read from the filesystem
+/// * The file contents cannot be decoded as UTF-8 text
+/// * The modified content cannot be written back to the file
+/// * The text content formatting fails when the replacement string is empty
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::replace_string_case_insensitive;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let path = Path::new("caption.txt");
+///     replace_string_case_insensitive(path, "photo", "picture").await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn replace_string_case_insensitive(
+    path: &Path,
+    search: &str,
+    replace: &str,
+) -> anyhow::Result<()> {
+    // Skip if search string is empty
+    if search.is_empty() {
+        return Ok(());
+    }
+
+    let regex = Regex::new(&format!("(?i){}", fancy_regex::escape(search)))
+        .map_err(|e| anyhow::anyhow!("Failed to build search pattern for '{search}': {e}"))?;
+
+    // Read the file content
+    let content = tokio::fs::read_to_string(path).await?;
+
+    // Replace the search string with the replacement string, without expanding `$` references
+    let mut new_content = regex
+        .try_replacen(&content, 0, fancy_regex::NoExpand(replace))
+        .map_err(|e| anyhow::anyhow!("Failed to replace '{search}': {e}"))?
+        .into_owned();
+
+    // If the replacement string is empty, format the text content
+    if replace.is_empty() {
+        new_content = format_text_content(&new_content)?;
+    }
+
+    // Only write back if there were changes
+    if content != new_content {
+        tokio::fs::write(path, new_content).await?;
+    }
+
+    Ok(())
+}
+
+/// Replaces all matches of a regular expression with a replacement string in a file.
+///
+/// The pattern is compiled once with `fancy_regex`, so lookarounds and backreferences
+/// are supported. The replacement may reference capture groups using `$1` or `${name}`
+/// syntax. Like [`replace_string`], the content is reformatted when the replacement is
+/// empty and the file is only written back when the content actually changes.
+///
+/// # Arguments
+/// * `path` - A reference to the Path of the file to process
+/// * `pattern` - The regular expression to search for
+/// * `replacement` - The replacement string, optionally containing capture group references
+///
+/// # Returns
+/// * `anyhow::Result<()>` - Success or failure of the operation
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The pattern fails to compile
+/// * The file cannot be read from the filesystem
+/// * The file contents cannot be decoded as UTF-8 text
+/// * The regex engine fails while matching (e.g. backtrack limit exceeded)
+/// * The modified content cannot be written back to the file
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::replace_regex;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let path = Path::new("caption.txt");
+///     // Turn "by artist_name" into "artist_name (artist)"
+///     replace_regex(path, r"by (\w+)", "$1 (artist)").await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn replace_regex(path: &Path, pattern: &str, replacement: &str) -> anyhow::Result<()> {
+    let regex = Regex::new(pattern)
+        .map_err(|e| anyhow::anyhow!("Invalid regex pattern '{pattern}': {e}"))?;
+
+    // Read the file content
+    let content = tokio::fs::read_to_string(path).await?;
+
+    // Replace all matches, expanding capture group references
+    let mut new_content = regex
+        .try_replacen(&content, 0, replacement)
+        .map_err(|e| anyhow::anyhow!("Failed to apply regex '{pattern}': {e}"))?
+        .into_owned();
+
+    // If the replacement string is empty, format the text content
+    if replacement.is_empty() {
+        new_content = format_text_content(&new_content)?;
+    }
+
+    // Only write back if there were changes
+    if content != new_content {
+        tokio::fs::write(path, new_content).await?;
+    }
+
+    Ok(())
+}
+
+/// Replaces special characters with their keyboard-friendly versions in a file.
+///
+/// This function reads a file, replaces special characters (like smart quotes) with
+/// standard ASCII equivalents, and writes the result back to the file if changes were made.
+///
+/// # Arguments
+/// * `path` - A `PathBuf` to the file to process
+///
+/// # Returns
+/// * `anyhow::Result<()>` - Success or failure of the operation
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The file cannot be read from the filesystem
+/// * The file contents cannot be decoded as UTF-8 text
+/// * The modified content cannot be written back to the file
+///
+/// # Example
+/// ```no_run
+/// use std::path::PathBuf;
+/// use dset::caption::replace_special_chars;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let path = PathBuf::from("caption.txt");
+///     replace_special_chars(path).await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn replace_special_chars(path: PathBuf) -> anyhow::Result<()> {
+    // Read the file content
+    let content = tokio::fs::read_to_string(&path).await?;
+
+    // Replace special characters with their keyboard-friendly versions
+    let new_content = content.replace(['"', '"'], "\"");
+
+    // Only write back if there were changes
+    if content != new_content {
+        tokio::fs::write(&path, new_content).await?;
+    }
+
+    Ok(())
+}
+
+/// Windows-1252 characters for the bytes `0x80..=0x9F`, indexed by `byte - 0x80`.
+/// Bytes Windows-1252 leaves undefined map to the C1 control character, as in Latin-1.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+/// Gets the byte a character was decoded from if UTF-8 was read as Windows-1252 or Latin-1.
+fn mojibake_byte(c: char) -> Option<u8> {
+    if let Some(index) = WINDOWS_1252_HIGH.iter().position(|&high| high == c) {
+        return u8::try_from(index + 0x80).ok();
+    }
+    u8::try_from(u32::from(c)).ok().filter(|byte| *byte >= 0x80)
+}
+
+/// Decodes the mis-decoded UTF-8 sequence at the start of `chars`, if there is one.
+///
+/// Returns the repaired character and the number of characters it replaces.
+fn decode_mojibake_sequence(chars: &[char]) -> Option<(char, usize)> {
+    let len = match mojibake_byte(*chars.first()?)? {
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => return None,
+    };
+    let bytes = chars
+        .get(..len)?
+        .iter()
+        .map(|&c| mojibake_byte(c))
+        .collect::<Option<Vec<u8>>>()?;
+    let decoded = std::str::from_utf8(&bytes).ok()?.chars().next()?;
+    Some((decoded, len))
+}
+
+/// Repairs one layer of UTF-8 that was decoded as Windows-1252 or Latin-1.
+///
+/// Only complete, valid UTF-8 sequences are replaced, so a lone accented character
+/// such as the `é` in `café` is never touched.
+fn repair_mojibake(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut repaired = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if let Some((decoded, len)) = decode_mojibake_sequence(&chars[i..]) {
+            repaired.push(decoded);
+            i += len;
+        } else {
+            repaired.push(chars[i]);
+            i += 1;
+        }
+    }
+    repaired
+}
+
+/// Repairs mojibake in a caption file, such as `Ã©` in place of `é`.
+///
+/// Text that was UTF-8 but got decoded as Windows-1252 or Latin-1 turns each
+/// multi-byte character into a run of two to four characters like `Ã©` or `â€™`.
+/// Such runs are mapped back to their bytes and replaced only when those bytes form
+/// a single valid UTF-8 character. Anything else, including legitimately accented
+/// text, is left as it is. Text that was mis-decoded more than once is repaired
+/// layer by layer, up to three times.
+///
+/// # Arguments
+/// * `path` - Path to the caption file
+///
+/// # Returns
+/// * `anyhow::Result<bool>` - Whether the file was changed
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The file cannot be read or decoded as UTF-8 text
+/// * The repaired content cannot be written back to the file
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::fix_mojibake;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     // "cafÃ©, itâ€™s" becomes "café, it’s"
+///     if fix_mojibake(Path::new("caption.txt")).await? {
+///         println!("Repaired caption.txt");
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn fix_mojibake(path: &Path) -> anyhow::Result<bool> {
+    let content = tokio::fs::read_to_string(path).await?;
+
+    let mut repaired = content.clone();
+    for _ in 0..3 {
+        let next = repair_mojibake(&repaired);
+        if next == repaired {
+            break;
+        }
+        repaired = next;
+    }
+
+    // Only write back if there were changes
+    if repaired == content {
+        return Ok(false);
+    }
+    tokio::fs::write(path, repaired).await?;
+    Ok(true)
+}
+
+/// Escapes parentheses in a tag with backslashes for Stable Diffusion prompts.
+///
+/// Prompt parsers treat bare `(` and `)` as emphasis syntax, so tags such as
+/// `reflector (object)` must be written as `reflector \(object\)`.
+///
+/// # Example
+/// ```
+/// use dset::caption::escape_parens;
+///
+/// assert_eq!(escape_parens("tag (with parens)"), "tag \\(with parens\\)");
+/// ```
+#[must_use]
+pub fn escape_parens(tag: &str) -> String {
+    tag.replace('(', "\\(").replace(')', "\\)")
+}
+
+/// Removes the backslash escaping added by [`escape_parens`].
+#[must_use]
+pub fn unescape_parens(tag: &str) -> String {
+    tag.replace("\\(", "(").replace("\\)", ")")
+}
+
+/// Returns true if every parenthesis in the tag is escaped with a backslash.
+///
+/// A tag is considered correctly escaped when escaping its unescaped form
+/// yields the tag itself.
+#[must_use]
+pub fn is_correctly_escaped(tag: &str) -> bool {
+    escape_parens(&unescape_parens(tag)) == tag
+}
+
+/// Collects all files below `dir` whose extension matches one of `extensions`.
+///
+/// Extensions are compared case-insensitively and may be given with or
+/// without a leading dot; "*" matches every file. The returned paths are sorted
+/// for deterministic output.
+pub(crate) async fn files_with_extensions<S: AsRef<str>>(
+    dir: &Path,
+    extensions: &[S],
+) -> anyhow::Result<Vec<PathBuf>> {
+    collect_files(dir, extensions, true, None).await
+}
+
+/// Collects the files in `dir` whose extension matches one of `extensions`.
+///
+/// Extensions are matched as in [`files_with_extensions`]. Subdirectories are
+/// only walked when `recursive` is set; otherwise just the entries of `dir`
+/// itself are read. Once `cancel` is set, no further files are collected.
+pub(crate) async fn collect_files<S: AsRef<str>>(
+    dir: &Path,
+    extensions: &[S],
+    recursive: bool,
+    cancel: Option<Arc<AtomicBool>>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let is_cancelled = move || {
+        cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    };
+    let extensions: std::collections::HashSet<String> = extensions
+        .iter()
+        .map(|ext| ext.as_ref().trim_start_matches('.').to_lowercase())
+        .collect();
+    let matches = move |path: &Path| {
+        extensions.contains("*")
+            || path
+                .extension()
+                .is_some_and(|ext| extensions.contains(&ext.to_string_lossy().to_lowercase()))
+    };
+
+    if !recursive {
+        let mut files = Vec::new();
+        let mut entries = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if is_cancelled() {
+                break;
+            }
+            let path = entry.path();
+            if entry.file_type().await?.is_file() && matches(&path) {
+                files.push(path);
+            }
+        }
+        files.sort();
+        return Ok(files);
+    }
+
+    let matches = Arc::new(matches);
+    let is_cancelled = Arc::new(is_cancelled);
+    let files: Arc<tokio::sync::Mutex<Vec<PathBuf>>> =
+        Arc::new(tokio::sync::Mutex::new(Vec::new()));
+    let files_clone = files.clone();
+
+    xio::walk_directory(dir, "*", move |path| {
+        let path = path.to_path_buf();
+        let matches = matches.clone();
+        let is_cancelled = is_cancelled.clone();
+        let files = files_clone.clone();
+
+        async move {
+            if !is_cancelled() && matches(&path) {
+                files.lock().await.push(path);
+            }
+            Ok(())
+        }
+    })
+    .await?;
+
+    let mut files = std::mem::take(&mut *files.lock().await);
+    files.sort();
+    Ok(files)
+}
+
+/// Finds caption files containing tags with unescaped parentheses.
+///
+/// Walks `dir` for files with the given extension, splits each caption on commas
+/// and reports every tag that is not correctly escaped according to
+/// [`is_correctly_escaped`]. This catches manual edits that break the escaping
+/// produced by [`crate::process_json_to_caption`].
+///
+/// # Arguments
+/// * `dir` - Directory to search recursively
+/// * `ext` - Extension of the caption files to check (e.g. "txt")
+///
+/// # Returns
+/// * `anyhow::Result<Vec<(PathBuf, Vec<String>)>>` - Each offending file with its offending tags
+///
+/// # Errors
+/// Returns an error if:
+/// * The directory cannot be walked
+/// * A caption file cannot be read
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::find_unescaped_parens;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     for (path, tags) in find_unescaped_parens(Path::new("dataset"), "txt").await? {
+///         println!("{}: {}", path.display(), tags.join(", "));
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn find_unescaped_parens(
+    dir: &Path,
+    ext: &str,
+) -> anyhow::Result<Vec<(PathBuf, Vec<String>)>> {
+    let mut results = Vec::new();
+
+    for path in files_with_extensions(dir, &[ext]).await? {
+        let content = tokio::fs::read_to_string(&path).await?;
+        let offending: Vec<String> = content
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty() && !is_correctly_escaped(tag))
+            .map(String::from)
+            .collect();
+
+        if !offending.is_empty() {
+            results.push((path, offending));
+        }
+    }
+
+    Ok(results)
+}
+
+/// Splits caption content into trimmed, non-empty tags.
+///
+/// The separator is trimmed before splitting, so ", " and "," split
+/// `"tag1,tag2, tag3"` into the same three tags. Tags are compared as whole
+/// strings by the trigger and tag helpers built on top of this function.
+///
+/// # Example
+/// ```
+/// use dset::caption::split_tags;
+///
+/// assert_eq!(split_tags("tag1,tag2, tag3 ,", ", "), vec!["tag1", "tag2", "tag3"]);
+/// ```
+#[must_use]
+pub fn split_tags(content: &str, separator: &str) -> Vec<String> {
+    let trimmed = separator.trim();
+    let separator = if trimmed.is_empty() {
+        separator
+    } else {
+        trimmed
+    };
+
+    if separator.is_empty() {
+        let content = content.trim();
+        return if content.is_empty() {
+            Vec::new()
+        } else {
+            vec![content.to_string()]
+        };
+    }
+
+    content
+        .split(separator)
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Normalizes a tag for comparison across taggers.
+///
+/// The tag is lowercased, trimmed and runs of internal whitespace are collapsed
+/// into a single space. When `replace_underscores` is true, underscores are
+/// converted to spaces first, so `Long_Hair` and `long  hair` both become
+/// `long hair`.
+///
+/// # Example
+/// ```
+/// use dset::caption::normalize_tag;
+///
+/// assert_eq!(normalize_tag("  Long_Hair ", true), "long hair");
+/// assert_eq!(normalize_tag("Blue   Eyes", false), "blue eyes");
+/// ```
+#[must_use]
+pub fn normalize_tag(tag: &str, replace_underscores: bool) -> String {
+    underscores_to_spaces(tag, replace_underscores)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Converts the underscores of a tag to spaces when `replace_underscores` is set.
+///
+/// This is the underscore handling of [`normalize_tag`], without the lowercasing
+/// and whitespace cleanup, for the e621 and Danbooru tags that keep their case.
+fn underscores_to_spaces(tag: &str, replace_underscores: bool) -> String {
+    if replace_underscores {
+        tag.replace('_', " ")
+    } else {
+        tag.to_string()
+    }
+}
+
+/// Normalizes every tag in a caption file and removes duplicates.
+///
+/// The caption is split into tags and a trailing sentence with
+/// [`crate::split_content`], each tag is normalized with [`normalize_tag`], and
+/// only the first occurrence of each normalized tag is kept. The tags are joined
+/// with `separator`, the sentence and any trailing newline are kept as-is, and
+/// the file is only rewritten when its content changes.
+///
+/// # Arguments
+/// * `path` - A reference to the Path of the caption file
+/// * `separator` - The separator placed between tags (e.g. ", ")
+/// * `replace_underscores` - Whether to convert underscores to spaces
+///
+/// # Returns
+/// * `anyhow::Result<bool>` - Whether the file was modified
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The file cannot be read from the filesystem
+/// * The file contents cannot be decoded as UTF-8 text
+/// * The modified content cannot be written back to the file
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::normalize_tags_in_file;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     normalize_tags_in_file(Path::new("caption.txt"), ", ", true).await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn normalize_tags_in_file(
+    path: &Path,
+    separator: &str,
+    replace_underscores: bool,
+) -> anyhow::Result<bool> {
+    let content = tokio::fs::read_to_string(path).await?;
+    let (tags, _) = split_caption(&content, separator);
+    let mut seen = std::collections::HashSet::new();
+    let tags: Vec<String> = tags
+        .iter()
+        .map(|tag| normalize_tag(tag, replace_underscores))
+        .filter(|tag| !tag.is_empty() && seen.insert(tag.clone()))
+        .collect();
+
+    let new_content = replace_caption_tags(&content, &tags, separator);
+    if new_content == content {
+        return Ok(false);
+    }
+
+    tokio::fs::write(path, new_content).await?;
+    Ok(true)
+}
+
+/// Size statistics of a caption file, as returned by [`caption_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CaptionStats {
+    /// Number of characters in the trimmed caption
+    pub char_count: usize,
+    /// Number of whitespace-separated words
+    pub word_count: usize,
+    /// Number of non-empty comma-separated tags
+    pub tag_count: usize,
+}
+
+/// Computes character, word and tag counts for a caption file.
+///
+/// Words are split on whitespace and tags on commas; tags are trimmed and empty
+/// entries are skipped before counting. The file is never modified.
+///
+/// # Arguments
+/// * `path` - A reference to the Path of the caption file
+///
+/// # Returns
+/// * `anyhow::Result<CaptionStats>` - The counts for the caption
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The file cannot be read from the filesystem
+/// * The file contents cannot be decoded as UTF-8 text
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::caption_stats;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let stats = caption_stats(Path::new("caption.txt")).await?;
+///     println!("{} words, {} tags", stats.word_count, stats.tag_count);
+///     Ok(())
+/// }
+/// ```
+pub async fn caption_stats(path: &Path) -> anyhow::Result<CaptionStats> {
+    let content = tokio::fs::read_to_string(path).await?;
+    let content = content.trim();
+
+    Ok(CaptionStats {
+        char_count: content.chars().count(),
+        word_count: content.split_whitespace().count(),
+        tag_count: split_tags(content, ",").len(),
+    })
+}
+
+/// Summary of one measurement across caption files, as used by [`LengthStats`].
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct LengthSummary {
+    /// Smallest value
+    pub min: usize,
+    /// Largest value
+    pub max: usize,
+    /// Arithmetic mean
+    pub mean: f64,
+    /// Median, averaging the two middle values for an even number of files
+    pub median: f64,
+}
+
+impl LengthSummary {
+    /// Summarizes a list of values, returning all zeros when it is empty
+    #[allow(clippy::cast_precision_loss)]
+    fn from_values(mut values: Vec<usize>) -> Self {
+        if values.is_empty() {
+            return Self::default();
+        }
+        values.sort_unstable();
+        let len = values.len();
+        let median = if len % 2 == 1 {
+            values[len / 2] as f64
+        } else {
+            (values[len / 2 - 1] + values[len / 2]) as f64 / 2.0
+        };
+        Self {
+            min: values[0],
+            max: values[len - 1],
+            mean: values.iter().sum::<usize>() as f64 / len as f64,
+            median,
+        }
+    }
+}
+
+/// Caption length statistics across a dataset, as returned by [`length_histogram`].
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct LengthStats {
+    /// Number of caption files measured
+    pub files: usize,
+    /// Character counts of the trimmed captions
+    pub chars: LengthSummary,
+    /// Tag counts of the captions
+    pub tags: LengthSummary,
+}
+
+/// Computes caption length statistics across every caption file below a directory.
+///
+/// Each file with the given extension is measured with [`caption_stats`], and the
+/// minimum, maximum, mean and median character and tag counts are reported. This
+/// makes truncated or runaway captions easy to spot before training. The result
+/// can be serialized, e.g. to dump the report as JSON.
+///
+/// # Arguments
+/// * `dir` - Directory to search recursively
+/// * `extension` - Extension of the caption files to read (e.g. "txt")
+///
+/// # Returns
+/// * `anyhow::Result<LengthStats>` - The statistics, all zeros when no file was found
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The directory cannot be walked
+/// * A caption file cannot be read or decoded as UTF-8 text
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::length_histogram;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let stats = length_histogram(Path::new("dataset"), "txt").await?;
+///     println!("{}", serde_json::to_string_pretty(&stats)?);
+///     Ok(())
+/// }
+/// ```
+pub async fn length_histogram(dir: &Path, extension: &str) -> anyhow::Result<LengthStats> {
+    let mut chars = Vec::new();
+    let mut tags = Vec::new();
+    for path in files_with_extensions(dir, &[extension]).await? {
+        let stats = caption_stats(&path).await?;
+        chars.push(stats.char_count);
+        tags.push(stats.tag_count);
+    }
+
+    Ok(LengthStats {
+        files: chars.len(),
+        chars: LengthSummary::from_values(chars),
+        tags: LengthSummary::from_values(tags),
+    })
+}
+
+/// Finds pairs of caption files with nearly identical tag sets.
+///
+/// Every file with the given extension below `dir` is split on commas into a set
+/// of tags, and each pair of files is compared with the Jaccard similarity
+/// (shared tags divided by distinct tags across both files). Pairs at or above
+/// `jaccard_threshold` are returned, most similar first. Files without any tags
+/// are skipped.
+///
+/// Every pair is compared, so the run time grows quadratically with the number
+/// of files; expect this to be slow beyond a few tens of thousands of captions.
+///
+/// # Arguments
+/// * `dir` - Directory to search recursively
+/// * `extension` - Extension of the caption files to read (e.g. "txt")
+/// * `jaccard_threshold` - Minimum similarity between 0.0 and 1.0 for a pair to be reported
+///
+/// # Returns
+/// * `anyhow::Result<Vec<(PathBuf, PathBuf, f64)>>` - Similar file pairs and their similarity
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The directory cannot be walked
+/// * A caption file cannot be read or decoded as UTF-8 text
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::find_near_duplicates;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     for (a, b, similarity) in find_near_duplicates(Path::new("dataset"), "txt", 0.9).await? {
+///         println!("{} ~ {} ({similarity:.2})", a.display(), b.display());
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn find_near_duplicates(
+    dir: &Path,
+    extension: &str,
+    jaccard_threshold: f64,
+) -> anyhow::Result<Vec<(PathBuf, PathBuf, f64)>> {
+    let mut captions = Vec::new();
+    for path in files_with_extensions(dir, &[extension]).await? {
+        let content = tokio::fs::read_to_string(&path).await?;
+        let tags: std::collections::HashSet<String> =
+            split_tags(&content, ",").into_iter().collect();
+        if !tags.is_empty() {
+            captions.push((path, tags));
+        }
+    }
+
+    let mut pairs = Vec::new();
+    for (i, (path_a, tags_a)) in captions.iter().enumerate() {
+        for (path_b, tags_b) in &captions[i + 1..] {
+            let shared = tags_a.intersection(tags_b).count();
+            #[allow(clippy::cast_precision_loss)]
+            let similarity = shared as f64 / (tags_a.len() + tags_b.len() - shared) as f64;
+            if similarity >= jaccard_threshold {
+                pairs.push((path_a.clone(), path_b.clone(), similarity));
+            }
+        }
+    }
+
+    pairs.sort_by(|a, b| b.2.total_cmp(&a.2));
+    Ok(pairs)
+}
+
+/// Counts how many caption files below a directory contain each tag.
+///
+/// Walks `dir` recursively with `xio::walk_directory`, reads every file with the
+/// given extension and splits it into tags with [`split_tags`]. Each tag is counted
+/// at most once per file, so the result is the number of files containing the tag.
+///
+/// # Arguments
+/// * `dir` - Directory to search recursively
+/// * `extension` - Extension of the caption files to read (e.g. "txt")
+/// * `separator` - The separator placed between tags (e.g. ", ")
+///
+/// # Returns
+/// * `anyhow::Result<HashMap<String, usize>>` - Number of files containing each tag
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The directory cannot be walked
+/// * A caption file cannot be read or decoded as UTF-8 text
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::tag_frequency;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let counts = tag_frequency(Path::new("dataset"), "txt", ", ").await?;
+///     println!("{} distinct tags", counts.len());
+///     Ok(())
+/// }
+/// ```
+pub async fn tag_frequency(
+    dir: &Path,
+    extension: &str,
+    separator: &str,
+) -> anyhow::Result<std::collections::HashMap<String, usize>> {
+    let mut counts = std::collections::HashMap::new();
+
+    for path in files_with_extensions(dir, &[extension]).await? {
+        let content = tokio::fs::read_to_string(&path).await?;
+        let tags: std::collections::HashSet<String> =
+            split_tags(&content, separator).into_iter().collect();
+        for tag in tags {
+            *counts.entry(tag).or_insert(0) += 1;
+        }
+    }
+
+    Ok(counts)
+}
+
+/// Writes tag counts to a CSV file with a `tag,count` header.
+///
+/// Rows are sorted by descending count, with ties broken alphabetically, so the
+/// most dominant tags of a [`tag_frequency`] report come first.
+///
+/// # Arguments
+/// * `counts` - Tag counts, e.g. as returned by [`tag_frequency`]
+/// * `path` - Path of the CSV file to write
+///
+/// # Returns
+/// * `anyhow::Result<()>` - Success or failure of the operation
+///
+/// # Errors
+///
+/// Returns an error if the CSV file cannot be written.
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::{tag_frequency, write_tag_frequency_csv};
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let counts = tag_frequency(Path::new("dataset"), "txt", ", ").await?;
+///     write_tag_frequency_csv(&counts, Path::new("tag_counts.csv")).await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn write_tag_frequency_csv<S: std::hash::BuildHasher>(
+    counts: &std::collections::HashMap<String, usize, S>,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let mut sorted: Vec<_> = counts.iter().collect();
+    sorted.sort_by(|(tag_a, count_a), (tag_b, count_b)| {
+        count_b.cmp(count_a).then_with(|| tag_a.cmp(tag_b))
+    });
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["tag", "count"])?;
+    for (tag, count) in sorted {
+        writer.write_record([tag.as_str(), &count.to_string()])?;
+    }
+    let csv = writer
+        .into_inner()
+        .map_err(csv::IntoInnerError::into_error)?;
+
+    tokio::fs::write(path, csv).await?;
+    Ok(())
+}
+
+/// Counts how many caption files below a directory contain each pair of tags.
+///
+/// Every file with the given extension is split into tags with [`split_tags`],
+/// and each unordered pair of distinct tags is counted at most once per file.
+/// Keys are ordered so the first tag sorts before the second, e.g.
+/// `("forest", "wolf")`.
+///
+/// # Arguments
+/// * `dir` - Directory to search recursively
+/// * `extension` - Extension of the caption files to read (e.g. "txt")
+/// * `separator` - The separator placed between tags (e.g. ", ")
+///
+/// # Returns
+/// * `anyhow::Result<HashMap<(String, String), usize>>` - Number of files containing each pair
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The directory cannot be walked
+/// * A caption file cannot be read or decoded as UTF-8 text
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::cooccurrence;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let pairs = cooccurrence(Path::new("dataset"), "txt", ", ").await?;
+///     let together = pairs.get(&("forest".to_string(), "wolf".to_string()));
+///     println!("wolf and forest appear together in {} files", together.unwrap_or(&0));
+///     Ok(())
+/// }
+/// ```
+pub async fn cooccurrence(
+    dir: &Path,
+    extension: &str,
+    separator: &str,
+) -> anyhow::Result<std::collections::HashMap<(String, String), usize>> {
+    let mut counts = std::collections::HashMap::new();
+
+    for path in files_with_extensions(dir, &[extension]).await? {
+        let content = tokio::fs::read_to_string(&path).await?;
+        let tags: std::collections::BTreeSet<String> =
+            split_tags(&content, separator).into_iter().collect();
+        let tags: Vec<&String> = tags.iter().collect();
+        for (i, a) in tags.iter().enumerate() {
+            for b in &tags[i + 1..] {
+                *counts.entry(((*a).clone(), (*b).clone())).or_insert(0) += 1;
+            }
+        }
+    }
+
+    Ok(counts)
+}
+
+/// Writes tag pair counts to a sparse CSV file with a `tag_a,tag_b,count` header.
+///
+/// Only pairs that occur are written. Rows are sorted by descending count, with
+/// ties broken alphabetically.
+///
+/// # Arguments
+/// * `counts` - Tag pair counts, e.g. as returned by [`cooccurrence`]
+/// * `path` - Path of the CSV file to write
+///
+/// # Returns
+/// * `anyhow::Result<()>` - Success or failure of the operation
+///
+/// # Errors
+///
+/// Returns an error if the CSV file cannot be written.
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::{cooccurrence, write_cooccurrence_csv};
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let pairs = cooccurrence(Path::new("dataset"), "txt", ", ").await?;
+///     write_cooccurrence_csv(&pairs, Path::new("cooccurrence.csv")).await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn write_cooccurrence_csv<S: std::hash::BuildHasher>(
+    counts: &std::collections::HashMap<(String, String), usize, S>,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let mut sorted: Vec<_> = counts.iter().collect();
+    sorted.sort_by(|(pair_a, count_a), (pair_b, count_b)| {
+        count_b.cmp(count_a).then_with(|| pair_a.cmp(pair_b))
+    });
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(["tag_a", "tag_b", "count"])?;
+    for ((a, b), count) in sorted {
+        writer.write_record([a.as_str(), b.as_str(), &count.to_string()])?;
+    }
+    let csv = writer
+        .into_inner()
+        .map_err(csv::IntoInnerError::into_error)?;
+
+    tokio::fs::write(path, csv).await?;
+    Ok(())
+}
+
+/// Adds a tag to caption content unless it is already one of its tags.
+///
+/// The tag is inserted into the tag portion found by [`split_caption`], so a
+/// trailing sentence and newline stay where they are.
+fn add_tag_to_content(
+    content: &str,
+    tag: &str,
+    position: TagPosition,
+    separator: &str,
+) -> Option<String> {
+    let (mut tags, _) = split_caption(content, separator);
+    if tags.iter().any(|existing| existing == tag) {
+        return None;
+    }
+
+    match position {
+        TagPosition::Prepend => tags.insert(0, tag.to_string()),
+        TagPosition::Append => tags.push(tag.to_string()),
+    }
+    Some(replace_caption_tags(content, &tags, separator))
+}
+
+/// Prepends a trigger word to a caption file.
+///
+/// The trigger is inserted at the front of the caption, followed by `separator`,
+/// unless it is already present as a whole tag anywhere in the caption. This
+/// makes the operation idempotent: running it twice never produces
+/// `trigger, trigger, ...`. Whitespace around `trigger` is ignored, as in
+/// [`strip_trigger`].
+///
+/// # Arguments
+/// * `path` - A reference to the Path of the caption file
+/// * `trigger` - The trigger word to insert
+/// * `separator` - The separator placed between tags (e.g. ", ")
+///
+/// # Returns
+/// * `anyhow::Result<()>` - Success or failure of the operation
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The file cannot be read from the filesystem
+/// * The file contents cannot be decoded as UTF-8 text
+/// * The modified content cannot be written back to the file
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::prepend_trigger;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     prepend_trigger(Path::new("caption.txt"), "my_lora", ", ").await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn prepend_trigger(path: &Path, trigger: &str, separator: &str) -> anyhow::Result<()> {
+    // Skip if trigger is empty
+    let trigger = trigger.trim();
+    if trigger.is_empty() {
+        return Ok(());
+    }
+
+    let content = tokio::fs::read_to_string(path).await?;
+    if let Some(new_content) =
+        add_tag_to_content(&content, trigger, TagPosition::Prepend, separator)
+    {
+        tokio::fs::write(path, new_content).await?;
+    }
+
+    Ok(())
+}
+
+/// Prepends a trigger word to every caption file below a directory.
+///
+/// Applies [`prepend_trigger`] to all files with the given extension found by
+/// walking `dir` recursively. Files that already contain the trigger are left untouched.
+///
+/// # Arguments
+/// * `dir` - Directory to search recursively
+/// * `ext` - Extension of the caption files to process (e.g. "txt")
+/// * `trigger` - The trigger word to insert
+/// * `separator` - The separator placed between tags (e.g. ", ")
+///
+/// # Returns
+/// * `anyhow::Result<usize>` - Number of files that were modified
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The directory cannot be walked
+/// * A caption file cannot be read or written
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::prepend_trigger_in_dir;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let modified = prepend_trigger_in_dir(Path::new("dataset"), "txt", "my_lora", ", ").await?;
+///     println!("Added trigger to {modified} captions");
+///     Ok(())
+/// }
+/// ```
+pub async fn prepend_trigger_in_dir(
+    dir: &Path,
+    ext: &str,
+    trigger: &str,
+    separator: &str,
+) -> anyhow::Result<usize> {
+    ensure_tag(dir, ext, trigger, TagPosition::Prepend, separator).await
+}
+
+/// Where [`ensure_tag`] inserts a missing tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TagPosition {
+    /// Insert the tag before all other tags
+    #[default]
+    Prepend,
+    /// Insert the tag after all other tags, before any trailing sentence
+    Append,
+}
+
+/// Adds a tag to every caption file below a directory that doesn't contain it yet.
+///
+/// The tag is only added when no whole tag of the caption exactly matches it, so
+/// running this twice changes nothing and files already containing the tag are
+/// left untouched. Captions are split into tags and a trailing sentence with
+/// [`crate::split_content`]; the tag goes first or last among the tags, before
+/// the sentence.
+///
+/// # Arguments
+/// * `dir` - Directory to search recursively
+/// * `extension` - Extension of the caption files to process (e.g. "txt")
+/// * `tag` - The tag to add, e.g. a class token
+/// * `position` - Whether to insert the tag at the start or the end of the tags
+/// * `separator` - The separator placed between tags (e.g. ", ")
+///
+/// # Returns
+/// * `anyhow::Result<usize>` - Number of files that were modified
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The directory cannot be walked
+/// * A caption file cannot be read or written
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::{TagPosition, ensure_tag};
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let modified =
+///         ensure_tag(Path::new("dataset"), "txt", "1girl", TagPosition::Append, ", ").await?;
+///     println!("Added the tag to {modified} captions");
+///     Ok(())
+/// }
+/// ```
+pub async fn ensure_tag(
+    dir: &Path,
+    extension: &str,
+    tag: &str,
+    position: TagPosition,
+    separator: &str,
+) -> anyhow::Result<usize> {
+    // Skip if tag is empty
+    let tag = tag.trim();
+    if tag.is_empty() {
+        return Ok(0);
+    }
+
+    let mut modified = 0;
+    for path in files_with_extensions(dir, &[extension]).await? {
+        let content = tokio::fs::read_to_string(&path).await?;
+        if let Some(new_content) = add_tag_to_content(&content, tag, position, separator) {
+            tokio::fs::write(&path, new_content).await?;
+            modified += 1;
+        }
+    }
+
+    Ok(modified)
+}
+
+/// Removes a trigger word from a caption file.
+///
+/// The caption is split into tags and a trailing sentence with
+/// [`crate::split_content`], and every tag that exactly matches `trigger` is
+/// removed, so stripping `cat` leaves `cathedral` untouched. Whitespace around
+/// `trigger` is ignored, as in [`prepend_trigger`]. The remaining tags are joined
+/// with `separator`, the sentence and trailing newline are kept, and the file is
+/// only rewritten when something was removed.
+///
+/// # Arguments
+/// * `path` - A reference to the Path of the caption file
+/// * `trigger` - The trigger word to remove
+/// * `separator` - The separator placed between tags (e.g. ", ")
+///
+/// # Returns
+/// * `anyhow::Result<bool>` - Whether the trigger was found and removed
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The file cannot be read from the filesystem
+/// * The file contents cannot be decoded as UTF-8 text
+/// * The modified content cannot be written back to the file
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::strip_trigger;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     if strip_trigger(Path::new("caption.txt"), "my_lora", ", ").await? {
+///         println!("Trigger removed");
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn strip_trigger(path: &Path, trigger: &str, separator: &str) -> anyhow::Result<bool> {
+    let trigger = trigger.trim();
+    let content = tokio::fs::read_to_string(path).await?;
+    let (tags, _) = split_caption(&content, separator);
+    let remaining: Vec<&str> = tags
+        .iter()
+        .map(String::as_str)
+        .filter(|tag| *tag != trigger)
+        .collect();
+
+    if remaining.len() == tags.len() {
+        return Ok(false);
+    }
+
+    tokio::fs::write(path, replace_caption_tags(&content, &remaining, separator)).await?;
+    Ok(true)
+}
+
+/// Removes every tag matching a regular expression across a directory.
+///
+/// The pattern must match a whole tag, so `\d{4}` removes `2023` but not
+/// `2023_art`; use `.*_\(artist\)` to remove anything ending in `_(artist)`.
+/// Every file with the given extension below `dir` is split into tags and a
+/// trailing sentence with [`crate::split_content`]; only the tags are matched,
+/// and files that lose at least one tag are rewritten with `separator`, keeping
+/// the sentence. The pattern is compiled once for the whole run.
+///
+/// # Arguments
+/// * `dir` - Directory to search recursively
+/// * `extension` - Extension of the caption files to process (e.g. "txt")
+/// * `pattern` - The regular expression a tag must fully match to be removed
+/// * `separator` - The separator placed between tags (e.g. ", ")
+///
+/// # Returns
+/// * `anyhow::Result<usize>` - Number of files that were modified
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The pattern fails to compile
+/// * The directory cannot be walked
+/// * A caption file cannot be read or written
+/// * The regex engine fails while matching (e.g. backtrack limit exceeded)
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::remove_matching_tags;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let modified = remove_matching_tags(Path::new("dataset"), "txt", r"\d{4}", ", ").await?;
+///     println!("Removed year tags from {modified} captions");
+///     Ok(())
+/// }
+/// ```
+pub async fn remove_matching_tags(
+    dir: &Path,
+    extension: &str,
+    pattern: &str,
+    separator: &str,
+) -> anyhow::Result<usize> {
+    let regex = Regex::new(&format!("^(?:{pattern})$"))
+        .map_err(|e| anyhow::anyhow!("Invalid regex pattern '{pattern}': {e}"))?;
+
+    let mut modified = 0;
+    for path in files_with_extensions(dir, &[extension]).await? {
+        let content = tokio::fs::read_to_string(&path).await?;
+        let (tags, _) = split_caption(&content, separator);
+        let mut remaining = Vec::with_capacity(tags.len());
+        for tag in &tags {
+            let matches = regex
+                .is_match(tag)
+                .map_err(|e| anyhow::anyhow!("Failed to apply regex '{pattern}': {e}"))?;
+            if !matches {
+                remaining.push(tag.as_str());
+            }
+        }
+
+        if remaining.len() != tags.len() {
+            tokio::fs::write(&path, replace_caption_tags(&content, &remaining, separator)).await?;
+            modified += 1;
+        }
+    }
+
+    Ok(modified)
+}
+
+/// Renames tags across a directory using an `old,new` CSV mapping file.
+///
+/// Each non-blank line of `map_csv` maps a tag to its replacement; fields may be
+/// quoted as in [`write_tag_frequency_csv`], and there is no header row. An empty
+/// `new` column deletes the tag. Every file with the given extension below `dir`
+/// is split into tags and a trailing sentence with [`crate::split_content`], whole
+/// tags are substituted, duplicates created by the renames are removed, and the
+/// file is rewritten with `separator` if anything changed, keeping the sentence.
+///
+/// # Arguments
+/// * `dir` - Directory to search recursively
+/// * `map_csv` - Path of the `old,new` CSV file
+/// * `extension` - Extension of the caption files to process (e.g. "txt")
+/// * `separator` - The separator placed between tags (e.g. ", ")
+///
+/// # Returns
+/// * `anyhow::Result<usize>` - Number of files that were modified
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The mapping file cannot be read or a line doesn't have exactly two columns
+/// * The directory cannot be walked
+/// * A caption file cannot be read or written
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::apply_tag_map_file;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let changed =
+///         apply_tag_map_file(Path::new("dataset"), Path::new("renames.csv"), "txt", ", ").await?;
+///     println!("Updated {changed} captions");
+///     Ok(())
+/// }
+/// ```
+pub async fn apply_tag_map_file(
+    dir: &Path,
+    map_csv: &Path,
+    extension: &str,
+    separator: &str,
+) -> anyhow::Result<usize> {
+    let content = tokio::fs::read(map_csv)
+        .await
+        .with_context(|| format!("Failed to read tag map {}", map_csv.display()))?;
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(content.as_slice());
+
+    let mut map = std::collections::HashMap::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record.with_context(|| format!("Failed to parse {}", map_csv.display()))?;
+        if record.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        let line = record.position().map_or(i as u64 + 1, csv::Position::line);
+        let fields: Vec<&str> = record.iter().collect();
+        let [old, new] = fields.as_slice() else {
+            anyhow::bail!(
+                "Line {line} of {} should have 2 columns, found {}",
+                map_csv.display(),
+                fields.len()
+            );
+        };
+        map.insert(old.trim().to_string(), new.trim().to_string());
+    }
+
+    let mut modified = 0;
+    for path in files_with_extensions(dir, &[extension]).await? {
+        let content = tokio::fs::read_to_string(&path).await?;
+        let (tags, _) = split_caption(&content, separator);
+        let mut seen = std::collections::HashSet::new();
+        let mapped: Vec<&str> = tags
+            .iter()
+            .map(|tag| map.get(tag).unwrap_or(tag).as_str())
+            .filter(|tag| !tag.is_empty() && seen.insert(*tag))
+            .collect();
+
+        if mapped != tags {
+            tokio::fs::write(&path, replace_caption_tags(&content, &mapped, separator)).await?;
+            modified += 1;
+        }
+    }
+
+    Ok(modified)
+}
+
+/// Writes one caption file per row of a `filename,caption` CSV file.
+///
+/// Each row produces `out_dir/filename.extension` containing the caption text.
+/// Fields may be quoted to contain commas, quotes, or newlines. An optional
+/// `filename,caption` header row is skipped, as are rows where both fields are empty.
+/// `out_dir` is created if it doesn't exist.
+///
+/// # Arguments
+/// * `csv_path` - Path of the `filename,caption` CSV file
+/// * `out_dir` - Directory to write the caption files into
+/// * `extension` - Extension of the caption files to write (e.g. "txt")
+/// * `overwrite` - Whether duplicate filenames and existing caption files may be overwritten
+///
+/// # Returns
+/// * `anyhow::Result<usize>` - Number of files written
+///
+/// # Errors
+///
+/// Returns an error if:
+/// * The CSV file cannot be read or parsed, or a row doesn't have exactly two columns
+/// * A filename is empty or contains a path separator
+/// * A filename appears more than once, or its caption file already exists, and `overwrite` is false
+/// * The output directory or a caption file cannot be written
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::import_from_csv;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let written =
+///         import_from_csv(Path::new("captions.csv"), Path::new("dataset"), "txt", false).await?;
+///     println!("Wrote {written} captions");
+///     Ok(())
+/// }
+/// ```
+pub async fn import_from_csv(
+    csv_path: &Path,
+    out_dir: &Path,
+    extension: &str,
+    overwrite: bool,
+) -> anyhow::Result<usize> {
+    let content = tokio::fs::read(csv_path)
+        .await
+        .with_context(|| format!("Failed to read {}", csv_path.display()))?;
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(content.as_slice());
 
-    if let Value::Object(tags) = tags_dict {
-        // Process each tag category
-        let process_category = |category: &str| {
-            tags.get(category)
-                .and_then(|t| t.as_array())
-                .map(|tags| {
-                    tags.iter()
-                        .filter_map(|tag| tag.as_str())
-                        .filter(|&tag| !config.filter_tags || !should_ignore_e621_tag(tag))
-                        .map(|tag| {
-                            if category == "artist" {
-                                config.format_artist_name(tag)
-                            } else if config.replace_underscores {
-                                tag.replace('_', " ")
-                            } else {
-                                tag.to_string()
-                            }
-                        })
-                        .collect::<Vec<String>>()
-                })
-                .unwrap_or_default()
+    let mut rows = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record.with_context(|| format!("Failed to parse {}", csv_path.display()))?;
+        let line = record.position().map_or(i as u64 + 1, csv::Position::line);
+        let fields: Vec<&str> = record.iter().collect();
+        let [filename, caption] = fields.as_slice() else {
+            anyhow::bail!(
+                "Line {line} of {} should have 2 columns, found {}",
+                csv_path.display(),
+                fields.len()
+            );
         };
+        let filename = filename.trim();
+        if i == 0
+            && filename.eq_ignore_ascii_case("filename")
+            && caption.trim().eq_ignore_ascii_case("caption")
+        {
+            continue;
+        }
+        if filename.is_empty() && caption.trim().is_empty() {
+            continue;
+        }
+        if filename.is_empty() || Path::new(filename).file_name() != Some(filename.as_ref()) {
+            anyhow::bail!(
+                "Line {line} of {} has an invalid filename '{filename}'",
+                csv_path.display()
+            );
+        }
+        rows.push((line, filename.to_string(), caption.to_string()));
+    }
 
-        // Process each category in order
-        let categories = [
-            "artist",
-            "character",
-            "species",
-            "copyright",
-            "general",
-            "meta",
-        ];
-        for category in categories {
-            let tags = process_category(category);
-            processed_tags.extend(tags);
+    if !overwrite {
+        let mut seen = std::collections::HashSet::new();
+        for (line, filename, _) in &rows {
+            if !seen.insert(filename.as_str()) {
+                anyhow::bail!(
+                    "Line {line} of {} repeats filename '{filename}'",
+                    csv_path.display()
+                );
+            }
         }
     }
 
-    processed_tags
+    tokio::fs::create_dir_all(out_dir).await?;
+    let extension = extension.trim_start_matches('.');
+    let mut written = std::collections::HashSet::new();
+    for (_, filename, caption) in &rows {
+        let path = out_dir.join(format!("{filename}.{extension}"));
+        if !overwrite && tokio::fs::try_exists(&path).await? {
+            anyhow::bail!("Caption file {} already exists", path.display());
+        }
+        tokio::fs::write(&path, caption).await?;
+        written.insert(path);
+    }
+
+    Ok(written.len())
 }
 
-/// Processes JSON data from e621 and creates a caption file.
+/// Reads a caption file as text, trimming surrounding whitespace.
+async fn read_caption(path: &Path) -> anyhow::Result<String> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read caption {}", path.display()))?;
+    Ok(content.trim().to_string())
+}
+
+/// Exports every caption file in a directory to a single JSONL file.
 ///
-/// # Arguments
+/// Each caption file with the given extension below `dir` becomes one
+/// `{"file": ..., "caption": ...}` line, where `file` is the path relative to
+/// `dir` using `/` separators and `caption` is the trimmed file content. Records
+/// are written in sorted path order and `out` is overwritten.
 ///
-/// * `data` - A reference to the JSON Value containing e621 post data
-/// * `file_path` - A reference to an Arc<PathBuf> representing the target file path
-/// * `config` - Optional configuration for processing. If None, uses default settings.
+/// # Arguments
+/// * `dir` - Directory to search recursively
+/// * `extension` - Extension of the caption files to export (e.g. "txt")
+/// * `out` - Path of the JSONL file to write
 ///
 /// # Returns
-///
-/// * `anyhow::Result<()>` - Success or failure of the operation
-///
-/// # Panics
-///
-/// This function will panic if:
-/// * The URL in the file data cannot be parsed into a valid file stem
-/// * The file stem cannot be converted to a string
+/// * `anyhow::Result<usize>` - Number of records written
 ///
 /// # Errors
 ///
 /// Returns an error if:
-/// * The caption file cannot be written to disk
-/// * The JSON data structure doesn't match the expected format
+/// * The directory cannot be walked
+/// * A caption file cannot be read or decoded as UTF-8 text
+/// * The output file cannot be written
 ///
 /// # Example
 /// ```no_run
-/// use std::path::PathBuf;
-/// use std::sync::Arc;
-/// use serde_json::json;
-/// use dset::caption::process_e621_json_data;
+/// use std::path::Path;
+/// use dset::caption::export_dir_to_jsonl;
 ///
 /// async fn example() -> anyhow::Result<()> {
-///     let data = json!({
-///         "post": {
-///             "file": {
-///                 "url": "https://example.com/image.jpg"
-///             },
-///             "rating": "s",
-///             "tags": {}
-///         }
-///     });
-///     let path = Arc::new(PathBuf::from("output.txt"));
-///     process_e621_json_data(&data, &path, None).await?;
+///     let records =
+///         export_dir_to_jsonl(Path::new("dataset"), "txt", Path::new("captions.jsonl")).await?;
+///     println!("Exported {records} captions");
 ///     Ok(())
 /// }
 /// ```
-pub async fn process_e621_json_data(
-    data: &Value,
-    file_path: &Arc<PathBuf>,
-    config: Option<E621Config>,
-) -> anyhow::Result<()> {
-    let config = config.unwrap_or_default();
+pub async fn export_dir_to_jsonl(dir: &Path, extension: &str, out: &Path) -> anyhow::Result<usize> {
+    let mut output = String::new();
+    let mut records = 0;
+    for path in files_with_extensions(dir, &[extension]).await? {
+        let relative = path.strip_prefix(dir).unwrap_or(&path);
+        let file = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let record = serde_json::json!({
+            "file": file,
+            "caption": read_caption(&path).await?,
+        });
+        output.push_str(&serde_json::to_string(&record)?);
+        output.push('\n');
+        records += 1;
+    }
 
-    if let Some(post) = data.get("post") {
-        if let Some(file_data) = post.get("file") {
-            if let Some(url) = file_data.get("url").and_then(|u| u.as_str()) {
-                use crate::xio::write_to_file;
-                use std::path::Path;
-
-                let filename = Path::new(url).file_stem().unwrap().to_str().unwrap();
-                let caption_path = file_path.with_file_name(format!("{filename}.txt"));
-
-                let rating = post.get("rating").and_then(|r| r.as_str()).unwrap_or("q");
-                let rating = config.convert_rating(rating);
-
-                let mut tag_groups = std::collections::HashMap::new();
-                tag_groups.insert("rating", rating);
-
-                if let Some(Value::Object(tags)) = post.get("tags") {
-                    // Process each category
-                    let process_category = |category: &str| {
-                        tags.get(category)
-                            .and_then(|t| t.as_array())
-                            .map(|tags| {
-                                tags.iter()
-                                    .filter_map(|tag| tag.as_str())
-                                    .filter(|&tag| {
-                                        !config.filter_tags || !should_ignore_e621_tag(tag)
-                                    })
-                                    .map(|tag| {
-                                        let tag = if config.replace_underscores {
-                                            tag.replace('_', " ")
-                                        } else {
-                                            tag.to_string()
-                                        };
-                                        if category == "artist" {
-                                            config.format_artist_name(&tag)
-                                        } else {
-                                            tag
-                                        }
-                                    })
-                                    .collect::<Vec<String>>()
-                            })
-                            .unwrap_or_default()
-                    };
-
-                    // Process each category
-                    let artists = process_category("artist");
-                    let characters = process_category("character");
-                    let species = process_category("species");
-                    let copyright = process_category("copyright");
-                    let general = process_category("general");
-                    let meta = process_category("meta");
-
-                    // Only add non-empty categories
-                    if !artists.is_empty() {
-                        tag_groups.insert("artists", artists.join(", "));
-                    }
-                    if !characters.is_empty() {
-                        tag_groups.insert("characters", characters.join(", "));
-                    }
-                    if !species.is_empty() {
-                        tag_groups.insert("species", species.join(", "));
-                    }
-                    if !copyright.is_empty() {
-                        tag_groups.insert("copyright", copyright.join(", "));
-                    }
-                    if !general.is_empty() {
-                        tag_groups.insert("general", general.join(", "));
-                    }
-                    if !meta.is_empty() {
-                        tag_groups.insert("meta", meta.join(", "));
-                    }
-
-                    // Apply the format
-                    let mut caption_content = config.get_format().to_string();
-                    for (key, value) in &tag_groups {
-                        caption_content = caption_content.replace(&format!("{{{key}}}"), value);
-                    }
-
-                    // Clean up empty placeholders
-                    caption_content = caption_content
-                        .replace(", ,", ",")
-                        .replace(",,", ",")
-                        .replace(" ,", ",")
-                        .trim_matches(&[' ', ','][..])
-                        .to_string();
-
-                    // Only write if we have content and either filtering is disabled or we have non-rating tags
-                    if !caption_content.trim().is_empty()
-                        && (!config.filter_tags || tag_groups.len() > 1)
-                    {
-                        write_to_file(&caption_path, &caption_content).await?;
-                    }
-                }
-            }
-        }
+    tokio::fs::write(out, output)
+        .await
+        .with_context(|| format!("Failed to write {}", out.display()))?;
+    Ok(records)
+}
+
+/// Splits caption content into tags and its trailing sentence with [`crate::split_content`].
+///
+/// The tag portion is further split on `separator`, and empty tags are dropped.
+fn split_caption(content: &str, separator: &str) -> (Vec<String>, String) {
+    let (tags, sentence) = crate::split_content(content.trim());
+    let tags = tags
+        .iter()
+        .flat_map(|tag| split_tags(tag, separator))
+        .collect();
+    (tags, sentence.trim().to_string())
+}
+
+/// Rebuilds caption content with new tags, keeping the sentence found by
+/// [`split_caption`] and any trailing newline of `content`.
+fn replace_caption_tags<S: AsRef<str>>(content: &str, tags: &[S], separator: &str) -> String {
+    let body = content.trim_end_matches(['\r', '\n']);
+    let (_, sentence) = split_caption(body, separator);
+    let tags: Vec<&str> = tags.iter().map(AsRef::as_ref).collect();
+    crate::concat::combine_tags_and_caption(&tags.join(separator), &sentence, "., ")
+        + &content[body.len()..]
+}
+
+/// Reads a caption file as tags and a trailing sentence, treating `.caption` and
+/// `.florence` files as pure prose like [`crate::concat`] does.
+async fn read_tags_and_caption(
+    path: &Path,
+    separator: &str,
+) -> anyhow::Result<(Vec<String>, String)> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let is_caption_file = path.extension().is_some_and(|ext| {
+        crate::concat::CAPTION_EXTENSIONS
+            .iter()
+            .any(|caption_ext| ext.eq_ignore_ascii_case(caption_ext))
+    });
+    if is_caption_file {
+        Ok((Vec::new(), content.trim().to_string()))
+    } else {
+        Ok(split_caption(&content, separator))
     }
-    Ok(())
 }
 
-/// Formats text content by removing excessive whitespace and newlines.
+/// Merges two caption files into one, combining their tags and free-text captions.
 ///
-/// This function cleans up text content by:
-/// - Trimming leading/trailing whitespace
-/// - Replacing multiple consecutive spaces with a single space
-/// - Normalizing line endings
+/// Files are read the way [`crate::concat`] reads them: a `.caption` or `.florence`
+/// file is pure prose, so one file can be tags only while the other is prose only.
+/// Any other file is split into tags and a trailing sentence with
+/// [`crate::split_content`]. Tags and captions are then combined the same way
+/// [`crate::concat`] joins tag files with caption files.
+///
+/// Ordering is guaranteed: all tags from `a` come first in their original order,
+/// followed by the tags from `b`, and the sentence from `a` precedes the sentence
+/// from `b`. When `dedupe` is true only the first occurrence of each tag is kept,
+/// and an identical sentence in both files is written once.
 ///
 /// # Arguments
-/// * `content` - A string slice containing the text to format
+/// * `a` - The first caption file, whose tags come first
+/// * `b` - The second caption file
+/// * `out` - Path of the merged caption file to write
+/// * `separator` - The separator placed between tags (e.g. ", ")
+/// * `dedupe` - Whether to remove duplicate tags
 ///
 /// # Returns
-/// * `anyhow::Result<String>` - The formatted text content
+/// * `anyhow::Result<()>` - Success or failure of the operation
 ///
 /// # Errors
 ///
-/// This function currently does not return any errors, but returns Result
-/// for consistency with other functions in the module and to allow for
-/// future error handling.
+/// Returns an error if:
+/// * Either input file cannot be read or decoded as UTF-8 text
+/// * The merged content cannot be written to `out`
 ///
 /// # Example
-/// ```
-/// use dset::caption::format_text_content;
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::merge_caption_files;
 ///
-/// # fn main() -> anyhow::Result<()> {
-/// let text = "  This   has  extra   spaces \n\n and newlines  ";
-/// let formatted = format_text_content(text)?;
-/// assert_eq!(formatted, "This has extra spaces and newlines");
-/// # Ok(())
-/// # }
+/// async fn example() -> anyhow::Result<()> {
+///     merge_caption_files(
+///         Path::new("image.wd"),
+///         Path::new("image.tags"),
+///         Path::new("image.txt"),
+///         ", ",
+///         true,
+///     )
+///     .await?;
+///     Ok(())
+/// }
 /// ```
-pub fn format_text_content(content: &str) -> anyhow::Result<String> {
-    // Trim and normalize content
-    let content = content.trim();
+pub async fn merge_caption_files(
+    a: &Path,
+    b: &Path,
+    out: &Path,
+    separator: &str,
+    dedupe: bool,
+) -> anyhow::Result<()> {
+    let (tags_a, sentence_a) = read_tags_and_caption(a, separator).await?;
+    let (tags_b, sentence_b) = read_tags_and_caption(b, separator).await?;
 
-    // Replace multiple spaces with a single space
-    let content = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut seen = std::collections::HashSet::new();
+    let tags: Vec<String> = tags_a
+        .into_iter()
+        .chain(tags_b)
+        .filter(|tag| !dedupe || seen.insert(tag.clone()))
+        .collect();
 
-    Ok(content)
+    let sentence = if sentence_a.is_empty() || (dedupe && sentence_a == sentence_b) {
+        sentence_b
+    } else if sentence_b.is_empty() {
+        sentence_a
+    } else {
+        format!("{sentence_a} {sentence_b}")
+    };
+
+    let merged =
+        crate::concat::combine_tags_and_caption(&tags.join(separator), &sentence, separator);
+    tokio::fs::write(out, merged).await?;
+
+    Ok(())
 }
 
-/// Replaces all instances of a search string with a replacement string in a file.
+/// Splits a combined caption file into separate tag and prose sidecar files.
 ///
-/// This function reads a file, replaces all occurrences of a search string with
-/// a replacement string, and writes the result back to the file if changes were made.
+/// This is the inverse of [`crate::concat`]: the content of `txt_path` is split
+/// with [`crate::split_content`], the tags are written to `stem.tags_ext` joined
+/// with ", ", and the trailing sentence to `stem.caption_ext`. When there is no
+/// sentence, the caption sidecar is written empty if `write_empty_caption` is
+/// true and left alone otherwise.
 ///
 /// # Arguments
-/// * `path` - A reference to the Path of the file to process
-/// * `search` - The string to search for
-/// * `replace` - The string to replace with
+/// * `txt_path` - Path of the combined caption file
+/// * `tags_ext` - Extension of the tag sidecar (e.g. "tags")
+/// * `caption_ext` - Extension of the prose sidecar (e.g. "caption")
+/// * `write_empty_caption` - Whether to write an empty caption sidecar when there is no sentence
 ///
 /// # Returns
 /// * `anyhow::Result<()>` - Success or failure of the operation
@@ -585,89 +3764,289 @@ pub fn format_text_content(content: &str) -> anyhow::Result<String> {
 /// # Errors
 ///
 /// Returns an error if:
-/// * The file cannot be read from the filesystem
-/// * The file contents cannot be decoded as UTF-8 text
-/// * The modified content cannot be written back to the file
-/// * The text content formatting fails when the replacement string is empty
+/// * Either sidecar would overwrite `txt_path`, or both use the same extension
+/// * The file cannot be read or decoded as UTF-8 text
+/// * A sidecar cannot be written
 ///
 /// # Example
 /// ```no_run
 /// use std::path::Path;
-/// use dset::caption::replace_string;
+/// use dset::caption::split_to_sidecars;
 ///
 /// async fn example() -> anyhow::Result<()> {
-///     let path = Path::new("caption.txt");
-///     replace_string(path, "old text", "new text").await?;
+///     // image.txt -> image.tags + image.caption
+///     split_to_sidecars(Path::new("image.txt"), "tags", "caption", false).await?;
 ///     Ok(())
 /// }
 /// ```
-pub async fn replace_string(path: &Path, search: &str, replace: &str) -> anyhow::Result<()> {
-    // Skip if search string is empty
-    if search.is_empty() {
-        return Ok(());
+pub async fn split_to_sidecars(
+    txt_path: &Path,
+    tags_ext: &str,
+    caption_ext: &str,
+    write_empty_caption: bool,
+) -> anyhow::Result<()> {
+    let tags_path = txt_path.with_extension(tags_ext.trim_start_matches('.'));
+    let caption_path = txt_path.with_extension(caption_ext.trim_start_matches('.'));
+    if tags_path == txt_path || caption_path == txt_path || tags_path == caption_path {
+        anyhow::bail!(
+            "Sidecar extensions '{tags_ext}' and '{caption_ext}' must differ from each other and from {}",
+            txt_path.display()
+        );
     }
 
-    // Read the file content
-    let content = tokio::fs::read_to_string(path).await?;
-
-    // Replace the search string with the replacement string
-    let mut new_content = content.replace(search, replace);
+    let content = tokio::fs::read_to_string(txt_path).await?;
+    let (tags, sentence) = crate::split_content(content.trim());
+    let tags: Vec<String> = tags.into_iter().filter(|tag| !tag.is_empty()).collect();
+    let sentence = sentence.trim();
 
-    // If the replacement string is empty, format the text content
-    if replace.is_empty() {
-        new_content = format_text_content(&new_content)?;
+    tokio::fs::write(&tags_path, tags.join(", ")).await?;
+    if !sentence.is_empty() || write_empty_caption {
+        tokio::fs::write(&caption_path, sentence).await?;
     }
 
-    // Only write back if there were changes
-    if content != new_content {
-        tokio::fs::write(path, new_content).await?;
+    Ok(())
+}
+
+/// Differences between the tags of two captions, as returned by [`diff_tags`].
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct TagDiff {
+    /// Tags only in the second caption, in their order there
+    pub added: Vec<String>,
+    /// Tags only in the first caption, in their order there
+    pub removed: Vec<String>,
+    /// Tags in both captions, in their order in the first caption
+    pub unchanged: Vec<String>,
+    /// Whether the trailing sentences differ
+    pub sentence_changed: bool,
+}
+
+impl TagDiff {
+    /// Compares the tags and trailing sentences of two caption strings.
+    ///
+    /// Each caption is split with [`crate::split_content`]: everything before the first
+    /// `"., "` delimiter is tags, split on `separator`, and everything after it is the
+    /// sentence. Content without the delimiter is all tags. Duplicate tags are reported once.
+    ///
+    /// # Example
+    /// ```
+    /// use dset::caption::TagDiff;
+    ///
+    /// let diff = TagDiff::between("wolf, forest, night", "wolf, snow, night", ", ");
+    /// assert_eq!(diff.added, vec!["snow"]);
+    /// assert_eq!(diff.removed, vec!["forest"]);
+    /// assert_eq!(diff.unchanged, vec!["wolf", "night"]);
+    /// assert!(!diff.sentence_changed);
+    /// ```
+    #[must_use]
+    pub fn between(a: &str, b: &str, separator: &str) -> Self {
+        let (a_tags, a_sentence) = split_caption(a, separator);
+        let (b_tags, b_sentence) = split_caption(b, separator);
+        let a_set: std::collections::HashSet<&String> = a_tags.iter().collect();
+        let b_set: std::collections::HashSet<&String> = b_tags.iter().collect();
+
+        let mut seen = std::collections::HashSet::new();
+        let (unchanged, removed) = a_tags
+            .iter()
+            .filter(|tag| seen.insert(*tag))
+            .cloned()
+            .partition(|tag| b_set.contains(tag));
+        let added = b_tags
+            .iter()
+            .filter(|tag| !a_set.contains(tag) && seen.insert(*tag))
+            .cloned()
+            .collect();
+
+        Self {
+            added,
+            removed,
+            unchanged,
+            sentence_changed: a_sentence != b_sentence,
+        }
     }
 
-    Ok(())
+    /// Checks whether the captions have the same tags and sentence
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && !self.sentence_changed
+    }
 }
 
-/// Replaces special characters with their keyboard-friendly versions in a file.
+/// Compares the tags of two caption files.
 ///
-/// This function reads a file, replaces special characters (like smart quotes) with
-/// standard ASCII equivalents, and writes the result back to the file if changes were made.
+/// Reads both files and compares them with [`TagDiff::between`]: tags are compared
+/// as sets, and the trailing sentences after the `"., "` delimiter are compared
+/// separately and flagged in `sentence_changed`.
 ///
 /// # Arguments
-/// * `path` - A `PathBuf` to the file to process
+/// * `a` - Path of the original caption file
+/// * `b` - Path of the edited caption file
+/// * `separator` - The separator between tags (e.g. ", ")
 ///
 /// # Returns
-/// * `anyhow::Result<()>` - Success or failure of the operation
+/// * `anyhow::Result<TagDiff>` - The added, removed, and unchanged tags
 ///
 /// # Errors
 ///
-/// Returns an error if:
-/// * The file cannot be read from the filesystem
-/// * The file contents cannot be decoded as UTF-8 text
-/// * The modified content cannot be written back to the file
+/// Returns an error if either file cannot be read or decoded as UTF-8 text
 ///
 /// # Example
 /// ```no_run
-/// use std::path::PathBuf;
-/// use dset::caption::replace_special_chars;
+/// use std::path::Path;
+/// use dset::caption::diff_tags;
 ///
 /// async fn example() -> anyhow::Result<()> {
-///     let path = PathBuf::from("caption.txt");
-///     replace_special_chars(path).await?;
+///     let diff = diff_tags(Path::new("before.txt"), Path::new("after.txt"), ", ").await?;
+///     println!("+{:?} -{:?}", diff.added, diff.removed);
 ///     Ok(())
 /// }
 /// ```
-pub async fn replace_special_chars(path: PathBuf) -> anyhow::Result<()> {
-    // Read the file content
-    let content = tokio::fs::read_to_string(&path).await?;
+pub async fn diff_tags(a: &Path, b: &Path, separator: &str) -> anyhow::Result<TagDiff> {
+    let a_content = tokio::fs::read_to_string(a)
+        .await
+        .with_context(|| format!("Failed to read {}", a.display()))?;
+    let b_content = tokio::fs::read_to_string(b)
+        .await
+        .with_context(|| format!("Failed to read {}", b.display()))?;
+    Ok(TagDiff::between(&a_content, &b_content, separator))
+}
 
-    // Replace special characters with their keyboard-friendly versions
-    let new_content = content.replace(['"', '"'], "\"");
+/// A set operation between the tags of two captions, used by [`tag_set_op_files`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagSetOp {
+    /// Tags in either caption, see [`tag_union`]
+    Union,
+    /// Tags in both captions, see [`tag_intersection`]
+    Intersection,
+    /// Tags in the first caption but not the second, see [`tag_difference`]
+    Difference,
+}
 
-    // Only write back if there were changes
-    if content != new_content {
-        tokio::fs::write(&path, new_content).await?;
+impl TagSetOp {
+    /// Applies the operation to two tag lists
+    #[must_use]
+    pub fn apply<S: AsRef<str>>(self, a: &[S], b: &[S]) -> Vec<String> {
+        match self {
+            Self::Union => tag_union(a, b),
+            Self::Intersection => tag_intersection(a, b),
+            Self::Difference => tag_difference(a, b),
+        }
     }
+}
 
-    Ok(())
+/// Keeps the first occurrence of each tag in `tags` that satisfies `keep`.
+fn unique_tags<'a, S: AsRef<str> + 'a>(
+    tags: impl IntoIterator<Item = &'a S>,
+    keep: impl Fn(&str) -> bool,
+) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    tags.into_iter()
+        .map(AsRef::as_ref)
+        .filter(|tag| keep(tag) && seen.insert(*tag))
+        .map(String::from)
+        .collect()
+}
+
+/// Returns the tags in either list: the tags of `a` in order, then the new tags of `b`.
+///
+/// Duplicates are kept only once.
+///
+/// # Example
+/// ```
+/// use dset::caption::tag_union;
+///
+/// assert_eq!(tag_union(&["wolf", "night"], &["snow", "wolf"]), vec!["wolf", "night", "snow"]);
+/// ```
+#[must_use]
+pub fn tag_union<S: AsRef<str>>(a: &[S], b: &[S]) -> Vec<String> {
+    unique_tags(a.iter().chain(b), |_| true)
+}
+
+/// Returns the tags of `a` that are also in `b`, in their order in `a`.
+///
+/// Duplicates are kept only once.
+///
+/// # Example
+/// ```
+/// use dset::caption::tag_intersection;
+///
+/// assert_eq!(tag_intersection(&["wolf", "night", "moon"], &["moon", "wolf"]), vec!["wolf", "moon"]);
+/// ```
+#[must_use]
+pub fn tag_intersection<S: AsRef<str>>(a: &[S], b: &[S]) -> Vec<String> {
+    let b: std::collections::HashSet<&str> = b.iter().map(AsRef::as_ref).collect();
+    unique_tags(a, |tag| b.contains(tag))
+}
+
+/// Returns the tags of `a` that are not in `b`, in their order in `a`.
+///
+/// Duplicates are kept only once.
+///
+/// # Example
+/// ```
+/// use dset::caption::tag_difference;
+///
+/// // Tags the tagger found that are missing from the curated tags
+/// assert_eq!(tag_difference(&["wolf", "night", "moon"], &["moon"]), vec!["wolf", "night"]);
+/// ```
+#[must_use]
+pub fn tag_difference<S: AsRef<str>>(a: &[S], b: &[S]) -> Vec<String> {
+    let b: std::collections::HashSet<&str> = b.iter().map(AsRef::as_ref).collect();
+    unique_tags(a, |tag| !b.contains(tag))
+}
+
+/// Applies a set operation to the tags of two caption files.
+///
+/// Both files are split into tags and a trailing sentence with
+/// [`crate::split_content`], like [`diff_tags`] does, and only the tags are
+/// combined with [`TagSetOp::apply`], so the result has a deterministic order:
+/// tags from `a` come first, in file order.
+///
+/// # Arguments
+/// * `a` - Path of the first caption file
+/// * `b` - Path of the second caption file
+/// * `separator` - The separator between tags (e.g. ", ")
+/// * `op` - The set operation to apply
+///
+/// # Returns
+/// * `anyhow::Result<Vec<String>>` - The resulting tags
+///
+/// # Errors
+///
+/// Returns an error if either file cannot be read or decoded as UTF-8 text
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::caption::{TagSetOp, tag_set_op_files};
+///
+/// async fn example() -> anyhow::Result<()> {
+///     // Tags in the wd file that are missing from the tags file
+///     let missing = tag_set_op_files(
+///         Path::new("image.wd"),
+///         Path::new("image.tags"),
+///         ", ",
+///         TagSetOp::Difference,
+///     )
+///     .await?;
+///     println!("{missing:?}");
+///     Ok(())
+/// }
+/// ```
+pub async fn tag_set_op_files(
+    a: &Path,
+    b: &Path,
+    separator: &str,
+    op: TagSetOp,
+) -> anyhow::Result<Vec<String>> {
+    let a_content = tokio::fs::read_to_string(a)
+        .await
+        .with_context(|| format!("Failed to read {}", a.display()))?;
+    let b_content = tokio::fs::read_to_string(b)
+        .await
+        .with_context(|| format!("Failed to read {}", b.display()))?;
+    let (a_tags, _) = split_caption(&a_content, separator);
+    let (b_tags, _) = split_caption(&b_content, separator);
+    Ok(op.apply(&a_tags, &b_tags))
 }
 
 /// Process an e621 JSON file and generate caption files.
@@ -772,6 +4151,39 @@ mod tests {
         assert!(json_to_text(&json).is_err());
     }
 
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_yaml_to_text() -> anyhow::Result<()> {
+        let yaml: serde_yaml::Value = serde_yaml::from_str("caption: A caption\ntext: ignored")?;
+        assert_eq!(yaml_to_text(&yaml)?, "A caption");
+        let yaml: serde_yaml::Value = serde_yaml::from_str("text: Some text")?;
+        assert_eq!(yaml_to_text(&yaml)?, "Some text");
+        let yaml: serde_yaml::Value = serde_yaml::from_str("plain")?;
+        assert_eq!(yaml_to_text(&yaml)?, "plain");
+        let yaml: serde_yaml::Value = serde_yaml::from_str("other: field")?;
+        assert!(yaml_to_text(&yaml).is_err());
+        let yaml: serde_yaml::Value = serde_yaml::from_str("[1, 2]")?;
+        assert!(yaml_to_text(&yaml).is_err());
+
+        assert_eq!(
+            yaml_front_matter("---\ncaption: hi\n---\nbody"),
+            "caption: hi"
+        );
+        assert_eq!(yaml_front_matter("caption: hi"), "caption: hi");
+        Ok(())
+    }
+
+    #[cfg(feature = "yaml")]
+    #[tokio::test]
+    async fn test_process_file_yaml() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let file_path = temp_dir.path().join("test.yaml");
+        fs::write(&file_path, "---\ncaption: A test caption\n---\n")?;
+
+        process_file(&file_path).await?;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_caption_file_exists_and_not_empty() -> anyhow::Result<()> {
         let temp_dir = TempDir::new()?;
@@ -798,6 +4210,29 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_caption_file_meets_min_length() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+
+        // Test non-existent file
+        let non_existent = temp_dir.path().join("non_existent.txt");
+        assert!(!caption_file_meets_min_length(&non_existent, 0).await);
+
+        // Test stub caption, ignoring surrounding whitespace
+        let stub_file = temp_dir.path().join("stub.txt");
+        fs::write(&stub_file, "  a  \n")?;
+        assert!(caption_file_meets_min_length(&stub_file, 1).await);
+        assert!(!caption_file_meets_min_length(&stub_file, 2).await);
+
+        // Test multi-byte characters are counted once
+        let unicode_file = temp_dir.path().join("unicode.txt");
+        fs::write(&unicode_file, "猫猫猫")?;
+        assert!(caption_file_meets_min_length(&unicode_file, 3).await);
+        assert!(!caption_file_meets_min_length(&unicode_file, 4).await);
+
+        Ok(())
+    }
+
     #[test]
     fn test_e621_config_underscore_replacement() {
         let config = E621Config::new().with_replace_underscores(false);
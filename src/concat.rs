@@ -26,9 +26,19 @@ use log::{debug, info, warn};
 use tokio::fs;
 use serde::{Deserialize, Serialize};
 use std::fmt;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 use md5;
+use sha2::{Digest, Sha256};
+
+/// Extensions of free-text caption files, whose content follows the tags instead of
+/// being split into tags
+pub(crate) const CAPTION_EXTENSIONS: [&str; 2] = ["caption", "florence"];
+
+/// Image extensions used as base files by the predefined presets
+pub const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "webp", "gif", "tiff", "bmp", "jxl", "avif"];
 
 /// Predefined presets for file extension combinations
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -37,6 +47,8 @@ pub enum FileExtensionPreset {
     CaptionWdTags,
     /// Concatenates .florence, .wd, .tags files
     FlorenceWdTags,
+    /// Concatenates .wd, .tags files into .txt, without a caption
+    WdTags,
 }
 
 impl fmt::Display for FileExtensionPreset {
@@ -44,6 +56,27 @@ impl fmt::Display for FileExtensionPreset {
         match self {
             Self::CaptionWdTags => write!(f, "caption+wd+tags"),
             Self::FlorenceWdTags => write!(f, "florence+wd+tags"),
+            Self::WdTags => write!(f, "wd+tags"),
+        }
+    }
+}
+
+/// Hash algorithm used to detect files with duplicate content
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    /// MD5, fast and sufficient for collision avoidance
+    #[default]
+    Md5,
+    /// SHA-256, for when a cryptographically strong digest is needed
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// Computes the lowercase hex digest of `data`
+    fn hex_digest(self, data: &[u8]) -> String {
+        match self {
+            Self::Md5 => format!("{:x}", md5::compute(data)),
+            Self::Sha256 => format!("{:x}", Sha256::digest(data)),
         }
     }
 }
@@ -60,7 +93,8 @@ impl fmt::Display for FileExtensionPreset {
 ///
 /// # Example
 /// ```no_run
-/// use dset::concat::ConcatConfig;
+/// use std::collections::HashMap;
+/// use dset::concat::{ConcatConfig, HashAlgorithm};
 ///
 /// let config = ConcatConfig {
 ///     base_extensions: vec!["jpg".into()],
@@ -69,9 +103,19 @@ impl fmt::Display for FileExtensionPreset {
 ///     remove_duplicates: true,
 ///     tag_separator: ", ".into(),
 ///     deduplicate_files: false,
+///     concurrency: None,
+///     allow_missing: false,
+///     preserve_order: false,
+///     case_insensitive_dedup: false,
+///     hash_algorithm: HashAlgorithm::Md5,
+///     backup_existing: false,
+///     incremental: false,
+///     input_separators: HashMap::new(),
+///     lowercase_tags: false,
 /// };
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct ConcatConfig {
     /// Base file extensions to find (without the dot)
     pub base_extensions: Vec<String>,
@@ -84,7 +128,37 @@ pub struct ConcatConfig {
     /// Tag separator to use when concatenating
     pub tag_separator: String,
     /// Set to true to deduplicate files with identical content
+    #[serde(default)]
     pub deduplicate_files: bool,
+    /// Maximum number of files processed in parallel (None uses the number of CPUs)
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+    /// Set to true to concatenate whichever related files exist instead of skipping
+    /// base files with missing ones
+    #[serde(default)]
+    pub allow_missing: bool,
+    /// Set to true to keep deduplicated tags in first-seen order instead of sorting them
+    #[serde(default)]
+    pub preserve_order: bool,
+    /// Set to true to treat tags differing only in case as duplicates
+    #[serde(default)]
+    pub case_insensitive_dedup: bool,
+    /// Hash algorithm used when deduplicating files
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+    /// Set to true to rename an existing output file to `<name>.<ext>.bak` before overwriting it
+    #[serde(default)]
+    pub backup_existing: bool,
+    /// Set to true to skip base files whose output is already newer than all related files
+    #[serde(default)]
+    pub incremental: bool,
+    /// Separators used to split the tags of each input extension (without the dot),
+    /// defaulting to a comma for extensions not listed
+    #[serde(default)]
+    pub input_separators: HashMap<String, String>,
+    /// Set to true to lowercase all tags (the caption is left untouched)
+    #[serde(default)]
+    pub lowercase_tags: bool,
 }
 
 impl ConcatConfig {
@@ -104,6 +178,15 @@ impl ConcatConfig {
             remove_duplicates,
             tag_separator,
             deduplicate_files: false,
+            concurrency: None,
+            allow_missing: false,
+            preserve_order: false,
+            case_insensitive_dedup: false,
+            hash_algorithm: HashAlgorithm::Md5,
+            backup_existing: false,
+            incremental: false,
+            input_separators: HashMap::new(),
+            lowercase_tags: false,
         }
     }
 
@@ -114,27 +197,215 @@ impl ConcatConfig {
         self
     }
 
+    /// Sets the maximum number of files processed in parallel
+    #[must_use]
+    pub fn with_concurrency(mut self, concurrency: Option<usize>) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Sets whether base files with some missing related files are still concatenated
+    #[must_use]
+    pub fn with_allow_missing(mut self, allow_missing: bool) -> Self {
+        self.allow_missing = allow_missing;
+        self
+    }
+
+    /// Sets whether deduplicated tags keep their first-seen order instead of being sorted
+    #[must_use]
+    pub fn with_preserve_order(mut self, preserve_order: bool) -> Self {
+        self.preserve_order = preserve_order;
+        self
+    }
+
+    /// Sets whether tag deduplication ignores case, keeping the first-seen casing
+    #[must_use]
+    pub fn with_case_insensitive_dedup(mut self, case_insensitive_dedup: bool) -> Self {
+        self.case_insensitive_dedup = case_insensitive_dedup;
+        self
+    }
+
+    /// Sets the hash algorithm used when deduplicating files
+    #[must_use]
+    pub fn with_hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    /// Sets whether existing output files are backed up before being overwritten
+    #[must_use]
+    pub fn with_backup_existing(mut self, backup_existing: bool) -> Self {
+        self.backup_existing = backup_existing;
+        self
+    }
+
+    /// Sets whether up-to-date outputs are skipped
+    #[must_use]
+    pub fn with_incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    /// Sets the separators used to split the tags of each input extension
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashMap;
+    /// use dset::concat::{ConcatConfig, FileExtensionPreset};
+    ///
+    /// // .wd files list one tag per line
+    /// let config = ConcatConfig::from_preset(FileExtensionPreset::CaptionWdTags)
+    ///     .with_input_separators(HashMap::from([("wd".to_string(), "\n".to_string())]));
+    /// ```
+    #[must_use]
+    pub fn with_input_separators(mut self, input_separators: HashMap<String, String>) -> Self {
+        self.input_separators = input_separators;
+        self
+    }
+
+    /// Sets whether all tags are lowercased
+    #[must_use]
+    pub fn with_lowercase_tags(mut self, lowercase_tags: bool) -> Self {
+        self.lowercase_tags = lowercase_tags;
+        self
+    }
+
+    /// Gets the separator used to split the tags of an input file
+    fn input_separator(&self, path: &Path) -> &str {
+        path.extension()
+            .and_then(|ext| self.input_separators.get(ext.to_string_lossy().as_ref()))
+            .map_or(",", String::as_str)
+    }
+
+    /// Gets the number of files to process in parallel, defaulting to the number of CPUs
+    fn concurrency_limit(&self) -> usize {
+        self.concurrency
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, std::num::NonZero::get))
+            .max(1)
+    }
+
+    /// Loads a configuration from a TOML file
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read, is not valid TOML for a
+    /// `ConcatConfig`, or has an empty `extensions_to_concat` list
+    ///
+    /// # Example
+    /// ```no_run
+    /// use std::path::Path;
+    /// use dset::concat::ConcatConfig;
+    ///
+    /// let config = ConcatConfig::from_toml_file(Path::new("concat.toml"))?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn from_toml_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse TOML config: {}", path.display()))?;
+        config.ensure_extensions_to_concat(path)
+    }
+
+    /// Loads a configuration from a JSON file
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be read, is not valid JSON for a
+    /// `ConcatConfig`, or has an empty `extensions_to_concat` list
+    pub fn from_json_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        let config: Self = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse JSON config: {}", path.display()))?;
+        config.ensure_extensions_to_concat(path)
+    }
+
+    /// Checks that the configuration can't overwrite its own inputs
+    ///
+    /// # Errors
+    /// Returns an error if `output_extension` is also listed in
+    /// `extensions_to_concat` or `base_extensions`
+    pub fn validate(&self) -> Result<()> {
+        let collides = |extensions: &[String]| {
+            extensions.iter().any(|ext| ext.eq_ignore_ascii_case(&self.output_extension))
+        };
+        
+        if collides(&self.extensions_to_concat) {
+            anyhow::bail!(
+                "Output extension '{}' is also in extensions_to_concat; its files would be overwritten",
+                self.output_extension
+            );
+        }
+        if collides(&self.base_extensions) {
+            anyhow::bail!(
+                "Output extension '{}' is also in base_extensions; its files would be overwritten",
+                self.output_extension
+            );
+        }
+        Ok(())
+    }
+
+    /// Rejects configurations loaded from `path` that have nothing to concatenate
+    fn ensure_extensions_to_concat(self, path: &Path) -> Result<Self> {
+        if self.extensions_to_concat.is_empty() {
+            anyhow::bail!("Config file {} has an empty extensions_to_concat list", path.display());
+        }
+        Ok(self)
+    }
+
     /// Creates a configuration from a predefined preset
     #[must_use]
     pub fn from_preset(preset: FileExtensionPreset) -> Self {
         match preset {
             FileExtensionPreset::CaptionWdTags => Self {
-                base_extensions: vec!["png".into(), "jpg".into(), "jpeg".into(), "webp".into(), 
-                                    "gif".into(), "tiff".into(), "bmp".into(), "jxl".into(), "avif".into()],
+                base_extensions: IMAGE_EXTENSIONS.iter().map(|&ext| ext.into()).collect(),
                 extensions_to_concat: vec!["caption".into(), "wd".into(), "tags".into()],
                 output_extension: "txt".into(),
                 remove_duplicates: true,
                 tag_separator: ", ".into(),
                 deduplicate_files: false,
+                concurrency: None,
+                allow_missing: false,
+                preserve_order: false,
+                case_insensitive_dedup: false,
+                hash_algorithm: HashAlgorithm::Md5,
+                backup_existing: false,
+                incremental: false,
+                input_separators: HashMap::new(),
+                lowercase_tags: false,
             },
             FileExtensionPreset::FlorenceWdTags => Self {
-                base_extensions: vec!["png".into(), "jpg".into(), "jpeg".into(), "webp".into(), 
-                                    "gif".into(), "tiff".into(), "bmp".into(), "jxl".into(), "avif".into()],
+                base_extensions: IMAGE_EXTENSIONS.iter().map(|&ext| ext.into()).collect(),
                 extensions_to_concat: vec!["florence".into(), "wd".into(), "tags".into()],
                 output_extension: "txt".into(),
                 remove_duplicates: true,
                 tag_separator: ", ".into(),
                 deduplicate_files: false,
+                concurrency: None,
+                allow_missing: false,
+                preserve_order: false,
+                case_insensitive_dedup: false,
+                hash_algorithm: HashAlgorithm::Md5,
+                backup_existing: false,
+                incremental: false,
+                input_separators: HashMap::new(),
+                lowercase_tags: false,
+            },
+            FileExtensionPreset::WdTags => Self {
+                base_extensions: IMAGE_EXTENSIONS.iter().map(|&ext| ext.into()).collect(),
+                extensions_to_concat: vec!["wd".into(), "tags".into()],
+                output_extension: "txt".into(),
+                remove_duplicates: true,
+                tag_separator: ", ".into(),
+                deduplicate_files: false,
+                concurrency: None,
+                allow_missing: false,
+                preserve_order: false,
+                case_insensitive_dedup: false,
+                hash_algorithm: HashAlgorithm::Md5,
+                backup_existing: false,
+                incremental: false,
+                input_separators: HashMap::new(),
+                lowercase_tags: false,
             },
         }
     }
@@ -152,8 +423,10 @@ async fn read_file_content(path: &Path) -> Result<String> {
 ///
 /// This function processes tag files and caption files differently:
 /// 1. Identifies which file is the caption file based on extension (.caption or .florence)
-/// 2. Extracts and optionally deduplicates tags from all non-caption files
-/// 3. Appends the caption content after the deduplicated tags
+/// 2. Splits each non-caption file using its extension's input separator (a comma by default)
+/// 3. Extracts and optionally deduplicates tags from all non-caption files, sorting
+///    them unless `preserve_order` is set
+/// 4. Appends the caption content after the deduplicated tags
 ///
 /// The resulting format is: "tag1, tag2, tag3, `caption_content`"
 ///
@@ -163,77 +436,86 @@ async fn read_file_content(path: &Path) -> Result<String> {
 /// * `file_paths` - Paths of files being concatenated (used to identify the caption file)
 ///
 /// # Returns
-/// A string containing the concatenated content
-fn concat_tags(contents: &[String], config: &ConcatConfig, file_paths: &[std::path::PathBuf]) -> String {
+/// The concatenated content and the number of tags in its tag portion
+fn concat_tags(contents: &[String], config: &ConcatConfig, file_paths: &[std::path::PathBuf]) -> (String, usize) {
     if contents.is_empty() {
-        return String::new();
+        return (String::new(), 0);
     }
     
-    // Find which file is the caption file based on extension
-    let caption_ext = if config.extensions_to_concat.contains(&"caption".to_string()) {
-        "caption"
-    } else if config.extensions_to_concat.contains(&"florence".to_string()) {
-        "florence"
-    } else {
-        // If there's no caption or florence extension, use the last one
-        config.extensions_to_concat.last().unwrap()
-    };
+    // Find which file is the caption file based on extension. Without a caption or
+    // florence extension every file is treated as a tag file.
+    let caption_ext = CAPTION_EXTENSIONS
+        .into_iter()
+        .find(|ext| config.extensions_to_concat.iter().any(|e| e == ext));
     
-    // Find the index of the caption file 
+    // Find the index of the caption file, which may be absent when missing files are allowed
     let mut caption_index = None;
-    for (i, path) in file_paths.iter().enumerate() {
-        if let Some(ext) = path.extension() {
-            if ext == caption_ext {
-                caption_index = Some(i);
-                break;
+    if let Some(caption_ext) = caption_ext {
+        for (i, path) in file_paths.iter().enumerate() {
+            if let Some(ext) = path.extension() {
+                if ext == caption_ext {
+                    caption_index = Some(i);
+                    break;
+                }
             }
         }
     }
     
-    // Default to the last file if we couldn't determine which is the caption
-    let caption_index = caption_index.unwrap_or(contents.len() - 1);
-    let caption_content = &contents[caption_index];
+    let caption_content = caption_index.map_or("", |i| contents[i].as_str());
     
     // Process all tag file contents (all except the caption file)
-    let mut unique_tags = HashSet::new();
+    let mut seen_tags = HashSet::new();
     let mut all_tags = Vec::new();
     
     for (i, content) in contents.iter().enumerate() {
         // Skip the caption file
-        if i == caption_index {
+        if Some(i) == caption_index {
             continue;
         }
         
-        // Split by comma and trim each tag
-        let tags = content.split(',')
-            .map(str::trim)
-            .filter(|&tag| !tag.is_empty());
+        // Split by the separator configured for this file's extension and trim each tag
+        let separator = file_paths.get(i).map_or(",", |path| config.input_separator(path));
+        let tags = crate::caption::split_tags(content, separator);
             
         for tag in tags {
+            let tag = if config.lowercase_tags { tag.to_lowercase() } else { tag };
             if config.remove_duplicates {
-                unique_tags.insert(tag.to_string());
-            } else {
-                all_tags.push(tag.to_string());
+                let key = if config.case_insensitive_dedup {
+                    tag.to_lowercase()
+                } else {
+                    tag.clone()
+                };
+                if !seen_tags.insert(key) {
+                    continue;
+                }
             }
+            all_tags.push(tag);
         }
     }
     
+    if config.remove_duplicates && !config.preserve_order {
+        all_tags.sort_unstable();
+    }
+    
     // Format the tag portion
-    let tags_portion = if config.remove_duplicates {
-        let mut sorted_tags: Vec<_> = unique_tags.into_iter().collect();
-        sorted_tags.sort();
-        sorted_tags.join(&config.tag_separator)
-    } else {
-        all_tags.join(&config.tag_separator)
-    };
+    let tags_portion = all_tags.join(&config.tag_separator);
+    let tag_count = all_tags.len();
     
     // Combine tags with caption
+    (combine_tags_and_caption(&tags_portion, caption_content, &config.tag_separator), tag_count)
+}
+
+/// Joins a tag portion and a caption portion with the tag separator.
+///
+/// Either portion may be empty, in which case the other one is returned as-is
+/// without a dangling separator.
+pub(crate) fn combine_tags_and_caption(tags_portion: &str, caption_content: &str, separator: &str) -> String {
     if tags_portion.is_empty() {
-        caption_content.clone()
+        caption_content.to_string()
     } else if caption_content.is_empty() {
-        tags_portion
+        tags_portion.to_string()
     } else {
-        format!("{}{}{}", tags_portion, config.tag_separator, caption_content)
+        format!("{tags_portion}{separator}{caption_content}")
     }
 }
 
@@ -246,6 +528,7 @@ fn concat_tags(contents: &[String], config: &ConcatConfig, file_paths: &[std::pa
 ///
 /// # Returns
 /// * `Result<bool>` - Success (true) if processing occurred, false if files were missing
+///   or, with `incremental` set, the output was already up to date
 ///
 /// # Errors
 /// This function will return an error if:
@@ -258,6 +541,28 @@ pub async fn process_image_file(
     config: &ConcatConfig, 
     dry_run: bool
 ) -> Result<bool> {
+    Ok(matches!(
+        process_image_file_counted(image_path, config, dry_run).await?,
+        ImageFileOutcome::Written(_)
+    ))
+}
+
+/// Outcome of processing a single base file
+enum ImageFileOutcome {
+    /// The output was written (or would be, in dry-run mode) with this many tags
+    Written(usize),
+    /// Related files were missing
+    Missing,
+    /// The output is newer than all related files
+    UpToDate,
+}
+
+/// Same as [`process_image_file`], but reports why nothing was written
+async fn process_image_file_counted(
+    image_path: &Path, 
+    config: &ConcatConfig, 
+    dry_run: bool
+) -> Result<ImageFileOutcome> {
     // Get the stem of the image file (filename without extension)
     let stem = image_path.file_stem()
         .with_context(|| format!("Failed to get file stem from: {}", image_path.display()))?
@@ -280,8 +585,19 @@ pub async fn process_image_file(
     }
     
     if !missing_files.is_empty() {
-        warn!("Skipping {}: Missing files: {}", image_path.display(), missing_files.join(", "));
-        return Ok(false);
+        if !config.allow_missing || file_paths.is_empty() {
+            warn!("Skipping {}: Missing files: {}", image_path.display(), missing_files.join(", "));
+            return Ok(ImageFileOutcome::Missing);
+        }
+        debug!("Concatenating {} without missing files: {}", image_path.display(), missing_files.join(", "));
+    }
+    
+    // Create the output file path
+    let output_path = parent.join(format!("{}.{}", stem, config.output_extension));
+    
+    if config.incremental && crate::is_output_up_to_date(&output_path, &file_paths).await {
+        debug!("Skipping {}: {} is up to date", image_path.display(), output_path.display());
+        return Ok(ImageFileOutcome::UpToDate);
     }
     
     // Read content from all files
@@ -292,24 +608,139 @@ pub async fn process_image_file(
     }
     
     // Concatenate contents
-    let concatenated = concat_tags(&contents, config, &file_paths);
-    
-    // Create the output file path
-    let output_path = parent.join(format!("{}.{}", stem, config.output_extension));
+    let (concatenated, tag_count) = concat_tags(&contents, config, &file_paths);
     
     if dry_run {
         info!("Would write to {}: {}", output_path.display(), concatenated);
     } else {
+        if config.backup_existing && output_path.exists() {
+            let backup_path = parent.join(format!("{}.{}.bak", stem, config.output_extension));
+            fs::rename(&output_path, &backup_path)
+                .await
+                .with_context(|| format!("Failed to back up {} to {}", output_path.display(), backup_path.display()))?;
+            debug!("Backed up {} to {}", output_path.display(), backup_path.display());
+        }
         fs::write(&output_path, &concatenated)
             .await
             .with_context(|| format!("Failed to write to: {}", output_path.display()))?;
         debug!("Wrote {}", output_path.display());
     }
     
-    Ok(true)
+    Ok(ImageFileOutcome::Written(tag_count))
+}
+
+/// Statistics collected by [`concat_files_with_stats`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ConcatStats {
+    /// Number of base files whose output was written (or would be, in dry-run mode)
+    pub processed: usize,
+    /// Number of base files skipped because related files were missing
+    pub skipped_missing: usize,
+    /// Number of base files skipped because their content duplicates another file
+    pub skipped_duplicate: usize,
+    /// Number of base files skipped because their output was already up to date
+    pub skipped_up_to_date: usize,
+    /// Total number of tags in the tag portions of all outputs
+    pub total_tags_written: usize,
+}
+
+/// Callback receiving the number of base files finished so far and the total number found
+pub type ProgressCallback = Arc<dyn Fn(usize, usize) + Send + Sync>;
+
+/// Optional runtime hooks for [`concat_files_with_hooks`]
+///
+/// Unlike [`ConcatConfig`], hooks hold closures and shared state, so they can't
+/// be loaded from a config file.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+/// use dset::concat::ConcatHooks;
+///
+/// let hooks = ConcatHooks::new()
+///     .with_progress(Arc::new(|done, total| eprintln!("{done}/{total}")));
+/// ```
+#[derive(Clone, Default)]
+pub struct ConcatHooks {
+    /// Called with `(finished, total)` after each base file is handled, whatever the
+    /// outcome, and once with `(0, total)` before processing starts
+    pub progress: Option<ProgressCallback>,
+    /// When set to true, no further base files are started; files already in flight
+    /// finish and their outputs are kept
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+impl ConcatHooks {
+    /// Creates hooks that do nothing
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the progress callback
+    #[must_use]
+    pub fn with_progress(mut self, progress: ProgressCallback) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Sets the flag that stops the run when set to true
+    #[must_use]
+    pub fn with_cancel(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Checks whether the run has been cancelled
+    fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    /// Reports progress to the callback, if any
+    fn report_progress(&self, done: usize, total: usize) {
+        if let Some(progress) = &self.progress {
+            progress(done, total);
+        }
+    }
+}
+
+impl fmt::Debug for ConcatHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConcatHooks")
+            .field("progress", &self.progress.is_some())
+            .field("cancel", &self.cancel)
+            .finish()
+    }
+}
+
+/// Shared counters updated by concurrently processed files
+#[derive(Default)]
+struct ConcatCounters {
+    processed: AtomicUsize,
+    skipped_missing: AtomicUsize,
+    skipped_duplicate: AtomicUsize,
+    skipped_up_to_date: AtomicUsize,
+    total_tags_written: AtomicUsize,
+}
+
+impl ConcatCounters {
+    /// Takes a snapshot of the counters
+    fn stats(&self) -> ConcatStats {
+        ConcatStats {
+            processed: self.processed.load(Ordering::Relaxed),
+            skipped_missing: self.skipped_missing.load(Ordering::Relaxed),
+            skipped_duplicate: self.skipped_duplicate.load(Ordering::Relaxed),
+            skipped_up_to_date: self.skipped_up_to_date.load(Ordering::Relaxed),
+            total_tags_written: self.total_tags_written.load(Ordering::Relaxed),
+        }
+    }
 }
 
 /// Walks through a directory and concatenates files according to the configuration
+///
+/// Base files are processed in parallel, with at most `config.concurrency` files
+/// (by default the number of CPUs) in flight at once. Use [`concat_files_with_hooks`]
+/// to report progress.
 /// 
 /// # Arguments
 /// * `directory` - Base directory to search for files
@@ -321,6 +752,7 @@ pub async fn process_image_file(
 ///
 /// # Errors
 /// This function will return an error if:
+/// * The configuration is invalid (see [`ConcatConfig::validate`])
 /// * Directory traversal fails
 /// * File reading operations fail
 /// * File writing operations fail
@@ -330,94 +762,195 @@ pub async fn concat_files(
     config: &ConcatConfig,
     dry_run: bool
 ) -> Result<usize> {
+    Ok(concat_files_with_stats(directory, config, dry_run).await?.processed)
+}
+
+/// Walks through a directory and concatenates files, returning detailed statistics
+///
+/// This behaves exactly like [`concat_files`], but reports how many files were
+/// processed, skipped for missing related files or duplicate content, and how many
+/// tags were written in total.
+///
+/// # Errors
+/// This function will return an error if the configuration is invalid
+/// or directory traversal fails
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::concat::{ConcatConfig, FileExtensionPreset, concat_files_with_stats};
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let config = ConcatConfig::from_preset(FileExtensionPreset::CaptionWdTags);
+///     let stats = concat_files_with_stats(Path::new("./images"), &config, false).await?;
+///     println!("{} processed, {} tags written", stats.processed, stats.total_tags_written);
+///     Ok(())
+/// }
+/// ```
+pub async fn concat_files_with_stats(
+    directory: &Path, 
+    config: &ConcatConfig,
+    dry_run: bool
+) -> Result<ConcatStats> {
+    concat_files_with_hooks(directory, config, dry_run, &ConcatHooks::default()).await
+}
+
+/// Walks through a directory and concatenates files, reporting to the given hooks
+///
+/// This behaves exactly like [`concat_files_with_stats`]. The directory is walked
+/// first to count the base files, so the progress callback always knows the total.
+///
+/// The cancel flag is checked between files. Once it is set, no new base files are
+/// started, files already in flight finish, and the statistics of the partial run
+/// are returned.
+///
+/// # Errors
+/// This function will return an error if the configuration is invalid
+/// or directory traversal fails
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use std::sync::Arc;
+/// use std::sync::atomic::AtomicBool;
+/// use dset::concat::{ConcatConfig, ConcatHooks, FileExtensionPreset, concat_files_with_hooks};
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let config = ConcatConfig::from_preset(FileExtensionPreset::CaptionWdTags);
+///     let cancel = Arc::new(AtomicBool::new(false));
+///     let hooks = ConcatHooks::new()
+///         .with_progress(Arc::new(|done, total| eprint!("\r{done}/{total}")))
+///         .with_cancel(cancel.clone());
+///     // Setting `cancel` from another task (e.g. on Ctrl-C) stops the run early
+///     concat_files_with_hooks(Path::new("./images"), &config, false, &hooks).await?;
+///     Ok(())
+/// }
+/// ```
+pub async fn concat_files_with_hooks(
+    directory: &Path,
+    config: &ConcatConfig,
+    dry_run: bool,
+    hooks: &ConcatHooks,
+) -> Result<ConcatStats> {
+    config.validate()?;
+    
     let directory = directory.to_path_buf();
-    let config_clone = config.clone();
+    let config_clone = Arc::new(config.clone());
+    let concurrency = config.concurrency_limit();
     
     info!("Searching for files in: {}", directory.display());
     info!("Using extensions: {}", config.extensions_to_concat.join(", "));
     info!("Output extension: {}", config.output_extension);
+    info!("Processing up to {concurrency} files in parallel");
     if config.deduplicate_files {
         info!("File deduplication enabled - will check for identical file contents");
     }
     
-    let processed_count = Arc::new(AtomicUsize::new(0));
-    let skipped_duplicates = Arc::new(AtomicUsize::new(0));
-    let mut base_extensions = HashSet::new();
-    for ext in &config.base_extensions {
-        base_extensions.insert(ext.clone());
-        debug!("Added base extension: {}", ext);
-    }
+    let counters = Arc::new(ConcatCounters::default());
+    debug!("Base extensions: {:?}", config.base_extensions);
+    
+    // Collect the base files first so progress can be reported against a total
+    // Stop collecting once cancelled rather than finishing a long walk first
+    let base_files = crate::caption::collect_files(&directory, &config.base_extensions, true, hooks.cancel.clone()).await?;
+    let total = base_files.len();
+    hooks.report_progress(0, total);
     
     // Track file content hashes for deduplication
     let content_hashes: Arc<tokio::sync::Mutex<HashMap<String, String>>> = 
         Arc::new(tokio::sync::Mutex::new(HashMap::new()));
     
-    let processed_count_clone = processed_count.clone();
-    let skipped_duplicates_clone = skipped_duplicates.clone();
-    let content_hashes_clone = content_hashes.clone();
+    // Bound the number of files processed at once
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let finished = Arc::new(AtomicUsize::new(0));
+    let mut tasks = JoinSet::new();
     
-    xio::walk_directory(&directory, "*", move |path| {
-        let path = path.to_path_buf();
-        let base_exts = base_extensions.clone();
+    for path in base_files {
+        if hooks.is_cancelled() {
+            break;
+        }
         let config = config_clone.clone();
-        let dry_run = dry_run;
-        let count = processed_count_clone.clone();
-        let skipped = skipped_duplicates_clone.clone();
-        let hashes = content_hashes_clone.clone();
-        
-        async move {
-            if let Some(ext) = path.extension() {
-                let ext_str = ext.to_string_lossy().to_lowercase();
-                debug!("Checking file: {} with extension: {}", path.display(), ext_str);
-                debug!("Base extensions: {:?}", base_exts);
-                if base_exts.contains(&ext_str) {
-                    debug!("Found base extension match: {}", path.display());
-                    // Check for duplicate content if enabled
-                    if config.deduplicate_files {
-                        debug!("Checking for duplicate content: {}", path.display());
-                        let is_duplicate = check_duplicate_content(&path, &config, hashes.clone()).await;
-                        if is_duplicate {
-                            debug!("Skipping duplicate file: {}", path.display());
-                            skipped.fetch_add(1, Ordering::Relaxed);
-                            return Ok(());
-                        }
-                        debug!("File is not a duplicate, proceeding: {}", path.display());
-                    }
-                    
-                    // Process the image file
-                    debug!("Processing file: {}", path.display());
-                    match process_image_file(&path, &config, dry_run).await {
-                        Ok(true) => {
-                            debug!("Successfully processed: {}", path.display());
-                            count.fetch_add(1, Ordering::Relaxed);
-                        },
-                        Ok(false) => {
-                            debug!("Skipped due to missing files: {}", path.display());
-                        },
-                        Err(err) => warn!("Error processing {}: {}", path.display(), err),
-                    }
-                } else {
-                    debug!("Skipping non-base extension: {}", path.display());
-                }
+        let counters = counters.clone();
+        let hashes = content_hashes.clone();
+        let finished = finished.clone();
+        let hooks = hooks.clone();
+        
+        // Wait for a free slot so at most `concurrency` files are in flight
+        let permit = semaphore.clone().acquire_owned().await?;
+        tasks.spawn(async move {
+            let _permit = permit;
+            if hooks.is_cancelled() {
+                return;
             }
-            Ok(())
+            concat_base_file(&path, &config, dry_run, &counters, hashes).await;
+            let done = finished.fetch_add(1, Ordering::Relaxed) + 1;
+            hooks.report_progress(done, total);
+        });
+    }
+    
+    // Wait for all in-flight files to finish
+    while let Some(result) = tasks.join_next().await {
+        if let Err(err) = result {
+            warn!("Concatenation task failed: {err}");
         }
-    }).await?;
+    }
     
-    let final_count = processed_count.load(Ordering::Relaxed);
-    let final_skipped = skipped_duplicates.load(Ordering::Relaxed);
+    let stats = counters.stats();
     
+    if hooks.is_cancelled() {
+        info!("Concatenation cancelled after {} of {} files.", finished.load(Ordering::Relaxed), total);
+    }
     if dry_run {
-        info!("Dry run completed. Would have processed {} files.", final_count);
+        info!("Dry run completed. Would have processed {} files.", stats.processed);
     } else {
-        info!("Concatenation completed. Processed {} files.", final_count);
+        info!("Concatenation completed. Processed {} files.", stats.processed);
     }
     
     if config.deduplicate_files {
-        info!("Skipped {} duplicate files.", final_skipped);
+        info!("Skipped {} duplicate files.", stats.skipped_duplicate);
+    }
+    
+    Ok(stats)
+}
+
+/// Deduplicates and concatenates the files belonging to a single base file,
+/// recording the outcome in the shared counters
+async fn concat_base_file(
+    path: &Path,
+    config: &ConcatConfig,
+    dry_run: bool,
+    counters: &ConcatCounters,
+    hashes: Arc<tokio::sync::Mutex<HashMap<String, String>>>,
+) {
+    // Check for duplicate content if enabled
+    if config.deduplicate_files {
+        debug!("Checking for duplicate content: {}", path.display());
+        let is_duplicate = check_duplicate_content(path, config, hashes).await;
+        if is_duplicate {
+            debug!("Skipping duplicate file: {}", path.display());
+            counters.skipped_duplicate.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        debug!("File is not a duplicate, proceeding: {}", path.display());
     }
     
-    Ok(final_count)
+    // Process the image file
+    debug!("Processing file: {}", path.display());
+    match process_image_file_counted(path, config, dry_run).await {
+        Ok(ImageFileOutcome::Written(tag_count)) => {
+            debug!("Successfully processed: {}", path.display());
+            counters.processed.fetch_add(1, Ordering::Relaxed);
+            counters.total_tags_written.fetch_add(tag_count, Ordering::Relaxed);
+        },
+        Ok(ImageFileOutcome::Missing) => {
+            debug!("Skipped due to missing files: {}", path.display());
+            counters.skipped_missing.fetch_add(1, Ordering::Relaxed);
+        },
+        Ok(ImageFileOutcome::UpToDate) => {
+            debug!("Skipped up-to-date output: {}", path.display());
+            counters.skipped_up_to_date.fetch_add(1, Ordering::Relaxed);
+        },
+        Err(err) => warn!("Error processing {}: {}", path.display(), err),
+    }
 }
 
 /// Checks if a file has duplicate content compared to already processed files
@@ -467,8 +1000,8 @@ async fn check_duplicate_content(
         }
     }
     
-    // Create a simple hash of the content
-    let content_hash = format!("{:x}", md5::compute(combined_content.as_bytes()));
+    // Hash the content with the configured algorithm
+    let content_hash = config.hash_algorithm.hex_digest(combined_content.as_bytes());
     debug!("Generated hash for {}: {}", path.display(), content_hash);
     
     // Check if this hash already exists
@@ -500,6 +1033,15 @@ mod tests {
             remove_duplicates: true, 
             tag_separator: ", ".into(),
             deduplicate_files: false,
+            concurrency: None,
+            allow_missing: false,
+            preserve_order: false,
+            case_insensitive_dedup: false,
+            hash_algorithm: HashAlgorithm::Md5,
+            backup_existing: false,
+            incremental: false,
+            input_separators: HashMap::new(),
+            lowercase_tags: false,
         };
         
         let contents = vec![
@@ -515,7 +1057,7 @@ mod tests {
             std::path::PathBuf::from("test.caption"),
         ];
         
-        let result = concat_tags(&contents, &config, &file_paths);
+        let (result, _) = concat_tags(&contents, &config, &file_paths);
         
         // Tags should be deduplicated and caption should be appended
         assert_eq!(result, "tag1, tag2, tag3, tag4, tag5, a photo of a person");
@@ -523,6 +1065,117 @@ mod tests {
         Ok(())
     }
     
+    /// Config deduplicating wd, tags and caption files in first-seen order
+    fn ordered_dedup_config() -> ConcatConfig {
+        ConcatConfig::new(
+            vec!["jpg".into()],
+            vec!["wd".into(), "tags".into(), "caption".into()],
+            "txt".into(),
+            true,
+            ", ".into(),
+        )
+        .with_preserve_order(true)
+    }
+    
+    #[test]
+    fn test_concat_tags_preserve_order() {
+        let contents = vec![
+            "tag3, tag1, tag2".to_string(),    // wd
+            "tag2, tag5, tag4".to_string(),    // tags
+            "a photo of a person".to_string(), // caption
+        ];
+        let file_paths = vec![
+            std::path::PathBuf::from("test.wd"),
+            std::path::PathBuf::from("test.tags"),
+            std::path::PathBuf::from("test.caption"),
+        ];
+        
+        // By default unique tags are sorted
+        let config = ordered_dedup_config().with_preserve_order(false);
+        let (result, _) = concat_tags(&contents, &config, &file_paths);
+        assert_eq!(result, "tag1, tag2, tag3, tag4, tag5, a photo of a person");
+        
+        // With preserve_order, tags keep their first-seen order
+        let (result, _) = concat_tags(&contents, &ordered_dedup_config(), &file_paths);
+        assert_eq!(result, "tag3, tag1, tag2, tag5, tag4, a photo of a person");
+    }
+    
+    #[test]
+    fn test_concat_tags_case_insensitive_dedup() {
+        let contents = vec![
+            "Cat, tag1".to_string(),       // wd
+            "cat, TAG1, Dog".to_string(),  // tags
+            "A Cat and a cat".to_string(), // caption
+        ];
+        let file_paths = vec![
+            std::path::PathBuf::from("test.wd"),
+            std::path::PathBuf::from("test.tags"),
+            std::path::PathBuf::from("test.caption"),
+        ];
+        
+        let (result, _) = concat_tags(&contents, &ordered_dedup_config(), &file_paths);
+        assert_eq!(result, "Cat, tag1, cat, TAG1, Dog, A Cat and a cat");
+        
+        // Keeps the first-seen casing and leaves the caption alone
+        let config = ordered_dedup_config().with_case_insensitive_dedup(true);
+        let (result, _) = concat_tags(&contents, &config, &file_paths);
+        assert_eq!(result, "Cat, tag1, Dog, A Cat and a cat");
+    }
+    
+    #[test]
+    fn test_concat_tags_lowercase_tags() {
+        let contents = vec![
+            "Cat, tag1".to_string(),       // wd
+            "cat, TAG1, Dog".to_string(),  // tags
+            "A Cat and a cat".to_string(), // caption
+        ];
+        let file_paths = vec![
+            std::path::PathBuf::from("test.wd"),
+            std::path::PathBuf::from("test.tags"),
+            std::path::PathBuf::from("test.caption"),
+        ];
+        
+        // Lowercasing collapses case variants and leaves the caption alone
+        let config = ordered_dedup_config().with_lowercase_tags(true);
+        let (result, _) = concat_tags(&contents, &config, &file_paths);
+        assert_eq!(result, "cat, tag1, dog, A Cat and a cat");
+        
+        let contents = vec![
+            "Cat, DOG".to_string(),
+            String::new(),
+            String::new(),
+        ];
+        let (result, _) = concat_tags(&contents, &config, &file_paths);
+        assert_eq!(result, "cat, dog");
+    }
+    
+    #[test]
+    fn test_concat_tags_input_separators() {
+        let config = ConcatConfig::new(
+            vec!["jpg".into()],
+            vec!["wd".into(), "tags".into(), "caption".into()],
+            "txt".into(),
+            true,
+            ", ".into(),
+        )
+        .with_input_separators(HashMap::from([("wd".to_string(), "\n".to_string())]));
+        
+        let contents = vec![
+            "long hair\nblue eyes\nsmiling\n".to_string(), // wd
+            "tag1, long hair".to_string(),                  // tags
+            "a photo of a person".to_string(),              // caption
+        ];
+        let file_paths = vec![
+            std::path::PathBuf::from("test.wd"),
+            std::path::PathBuf::from("test.tags"),
+            std::path::PathBuf::from("test.caption"),
+        ];
+        
+        let (result, tag_count) = concat_tags(&contents, &config, &file_paths);
+        assert_eq!(result, "blue eyes, long hair, smiling, tag1, a photo of a person");
+        assert_eq!(tag_count, 4);
+    }
+    
     #[tokio::test]
     async fn test_concat_tags_without_duplicates() -> Result<()> {
         let config = ConcatConfig {
@@ -532,6 +1185,15 @@ mod tests {
             remove_duplicates: false,
             tag_separator: ", ".into(),
             deduplicate_files: false,
+            concurrency: None,
+            allow_missing: false,
+            preserve_order: false,
+            case_insensitive_dedup: false,
+            hash_algorithm: HashAlgorithm::Md5,
+            backup_existing: false,
+            incremental: false,
+            input_separators: HashMap::new(),
+            lowercase_tags: false,
         };
         
         let contents = vec![
@@ -547,7 +1209,7 @@ mod tests {
             std::path::PathBuf::from("test.caption"),
         ];
         
-        let result = concat_tags(&contents, &config, &file_paths);
+        let (result, _) = concat_tags(&contents, &config, &file_paths);
         
         // Should preserve order and duplicates for tags, then append caption
         assert_eq!(result, "tag1, tag2, tag3, tag2, tag4, tag5, a photo of a person");
@@ -586,6 +1248,15 @@ mod tests {
             remove_duplicates: true,
             tag_separator: ", ".into(),
             deduplicate_files: false,
+            concurrency: None,
+            allow_missing: false,
+            preserve_order: false,
+            case_insensitive_dedup: false,
+            hash_algorithm: HashAlgorithm::Md5,
+            backup_existing: false,
+            incremental: false,
+            input_separators: HashMap::new(),
+            lowercase_tags: false,
         };
         
         // Process the image in dry-run mode
@@ -605,6 +1276,114 @@ mod tests {
         Ok(())
     }
     
+    #[tokio::test]
+    async fn test_process_image_file_allow_missing() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path = temp_dir.path();
+        
+        // No .wd file for this image
+        let image_path = temp_path.join("test.jpg");
+        File::create(&image_path).await?.sync_all().await?;
+        fs::write(temp_path.join("test.caption"), "a cat sitting").await?;
+        fs::write(temp_path.join("test.tags"), "tag1, tag2").await?;
+        
+        let config = ConcatConfig::new(
+            vec!["jpg".into()],
+            vec!["caption".into(), "wd".into(), "tags".into()],
+            "txt".into(),
+            true,
+            ", ".into(),
+        );
+        
+        // Without allow_missing the image is skipped
+        assert!(!process_image_file(&image_path, &config, false).await?);
+        assert!(!temp_path.join("test.txt").exists());
+        
+        // With allow_missing the remaining files are concatenated
+        let config = config.with_allow_missing(true);
+        assert!(process_image_file(&image_path, &config, false).await?);
+        let output_content = fs::read_to_string(temp_path.join("test.txt")).await?;
+        assert_eq!(output_content, "tag1, tag2, a cat sitting");
+        
+        // A missing caption file doesn't turn a tag file into the caption
+        fs::remove_file(temp_path.join("test.caption")).await?;
+        fs::write(temp_path.join("test.wd"), "wd1").await?;
+        assert!(process_image_file(&image_path, &config, false).await?);
+        let output_content = fs::read_to_string(temp_path.join("test.txt")).await?;
+        assert_eq!(output_content, "tag1, tag2, wd1");
+        
+        // Nothing at all to concatenate
+        let lonely_path = temp_path.join("lonely.jpg");
+        File::create(&lonely_path).await?.sync_all().await?;
+        assert!(!process_image_file(&lonely_path, &config, false).await?);
+        
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_image_file_backup_existing() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path = temp_dir.path();
+        
+        let image_path = temp_path.join("test.jpg");
+        File::create(&image_path).await?.sync_all().await?;
+        fs::write(temp_path.join("test.tags"), "tag1, tag2").await?;
+        fs::write(temp_path.join("test.txt"), "hand-edited caption").await?;
+        
+        let config = ConcatConfig::new(
+            vec!["jpg".into()],
+            vec!["tags".into()],
+            "txt".into(),
+            true,
+            ", ".into(),
+        )
+        .with_backup_existing(true);
+        
+        // Dry run leaves everything untouched
+        assert!(process_image_file(&image_path, &config, true).await?);
+        assert!(!temp_path.join("test.txt.bak").exists());
+        
+        assert!(process_image_file(&image_path, &config, false).await?);
+        assert_eq!(fs::read_to_string(temp_path.join("test.txt.bak")).await?, "hand-edited caption");
+        assert_eq!(fs::read_to_string(temp_path.join("test.txt")).await?, "tag1, tag2");
+        
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_image_file_incremental() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let temp_path = temp_dir.path();
+        
+        let image_path = temp_path.join("test.jpg");
+        let tags_path = temp_path.join("test.tags");
+        File::create(&image_path).await?.sync_all().await?;
+        fs::write(&tags_path, "tag1, tag2").await?;
+        
+        let config = ConcatConfig::new(
+            vec!["jpg".into()],
+            vec!["tags".into()],
+            "txt".into(),
+            true,
+            ", ".into(),
+        )
+        .with_incremental(true);
+        
+        assert!(process_image_file(&image_path, &config, false).await?);
+        
+        // The output is now newer than its source, so it is skipped
+        assert!(!process_image_file(&image_path, &config, false).await?);
+        
+        // Touching the source makes it newer than the output again
+        fs::write(&tags_path, "tag3").await?;
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+        std::fs::File::options().write(true).open(&tags_path)?.set_modified(later)?;
+        assert!(process_image_file(&image_path, &config, false).await?);
+        assert_eq!(fs::read_to_string(temp_path.join("test.txt")).await?, "tag3");
+        
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_file_deduplication() -> Result<()> {
         // Initialize the logger for debugging
@@ -701,6 +1480,15 @@ mod tests {
             remove_duplicates: true,
             tag_separator: ", ".into(),
             deduplicate_files: true, // Enable deduplication
+            concurrency: None,
+            allow_missing: false,
+            preserve_order: false,
+            case_insensitive_dedup: false,
+            hash_algorithm: HashAlgorithm::Md5,
+            backup_existing: false,
+            incremental: false,
+            input_separators: HashMap::new(),
+            lowercase_tags: false,
         };
         
         // Debug paths to make sure they're correct
@@ -770,6 +1558,39 @@ mod tests {
         Ok(())
     }
     
+    #[tokio::test]
+    async fn test_sha256_deduplication() -> Result<()> {
+        assert_eq!(HashAlgorithm::Md5.hex_digest(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            HashAlgorithm::Sha256.hex_digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        
+        let temp_dir = TempDir::new()?;
+        let temp_path = temp_dir.path();
+        for name in ["a", "b"] {
+            File::create(temp_path.join(format!("{name}.jpg"))).await?.sync_all().await?;
+            fs::write(temp_path.join(format!("{name}.tags")), "tag1, tag2").await?;
+        }
+        
+        let config = ConcatConfig::new(
+            vec!["jpg".into()],
+            vec!["tags".into()],
+            "txt".into(),
+            true,
+            ", ".into(),
+        )
+        .with_deduplication(true)
+        .with_hash_algorithm(HashAlgorithm::Sha256);
+        
+        let hashes = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
+        assert!(!check_duplicate_content(&temp_path.join("a.jpg"), &config, hashes.clone()).await);
+        assert!(check_duplicate_content(&temp_path.join("b.jpg"), &config, hashes.clone()).await);
+        assert!(hashes.lock().await.keys().all(|hash| hash.len() == 64));
+        
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_concat_tags_caption_handling() -> Result<()> {
         let config = ConcatConfig {
@@ -779,6 +1600,15 @@ mod tests {
             remove_duplicates: true, 
             tag_separator: ", ".into(),
             deduplicate_files: false,
+            concurrency: None,
+            allow_missing: false,
+            preserve_order: false,
+            case_insensitive_dedup: false,
+            hash_algorithm: HashAlgorithm::Md5,
+            backup_existing: false,
+            incremental: false,
+            input_separators: HashMap::new(),
+            lowercase_tags: false,
         };
         
         // Test with tag that also appears in caption - should not deduplicate across
@@ -794,7 +1624,7 @@ mod tests {
             std::path::PathBuf::from("test.caption"),
         ];
         
-        let result = concat_tags(&contents, &config, &file_paths);
+        let (result, _) = concat_tags(&contents, &config, &file_paths);
         
         // Tags should be deduplicated among themselves, not with caption content
         assert_eq!(result, "indoor, person, photo, white background, a photo of a person");
@@ -807,6 +1637,15 @@ mod tests {
             remove_duplicates: true, 
             tag_separator: ", ".into(),
             deduplicate_files: false,
+            concurrency: None,
+            allow_missing: false,
+            preserve_order: false,
+            case_insensitive_dedup: false,
+            hash_algorithm: HashAlgorithm::Md5,
+            backup_existing: false,
+            incremental: false,
+            input_separators: HashMap::new(),
+            lowercase_tags: false,
         };
         
         let contents = vec![
@@ -821,11 +1660,199 @@ mod tests {
             std::path::PathBuf::from("test.tags"),
         ];
         
-        let result = concat_tags(&contents, &config, &file_paths);
+        let (result, _) = concat_tags(&contents, &config, &file_paths);
         
         // Caption should still be appended after deduplicated tags
         assert_eq!(result, "indoor, person, photo, white background, a photo of a person");
         
+        // Without a caption extension, every file contributes tags
+        let config = ConcatConfig::from_preset(FileExtensionPreset::WdTags);
+        let contents = vec![
+            "person, photo".to_string(),                    // wd
+            "person, indoor, white background".to_string(), // tags
+        ];
+        let file_paths = vec![
+            std::path::PathBuf::from("test.wd"),
+            std::path::PathBuf::from("test.tags"),
+        ];
+        
+        let (result, _) = concat_tags(&contents, &config, &file_paths);
+        assert_eq!(result, "indoor, person, photo, white background");
+        
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concat_files_parallel() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        for i in 0..20 {
+            std::fs::write(temp_dir.path().join(format!("img{i}.jpg")), "")?;
+            std::fs::write(temp_dir.path().join(format!("img{i}.wd")), format!("tag{i}, shared"))?;
+            std::fs::write(temp_dir.path().join(format!("img{i}.tags")), "shared, other")?;
+            std::fs::write(temp_dir.path().join(format!("img{i}.caption")), "a photo")?;
+        }
+        // Missing .tags file, skipped
+        std::fs::write(temp_dir.path().join("lonely.jpg"), "")?;
+        std::fs::write(temp_dir.path().join("lonely.wd"), "tag")?;
+
+        let config = ConcatConfig::new(
+            vec!["jpg".into()],
+            vec!["caption".into(), "wd".into(), "tags".into()],
+            "txt".into(),
+            true,
+            ", ".into(),
+        ).with_concurrency(Some(4));
+
+        let stats = concat_files_with_stats(temp_dir.path(), &config, false).await?;
+        assert_eq!(stats, ConcatStats {
+            processed: 20,
+            skipped_missing: 1,
+            skipped_duplicate: 0,
+            skipped_up_to_date: 0,
+            total_tags_written: 60,
+        });
+        for i in 0..20 {
+            let content = std::fs::read_to_string(temp_dir.path().join(format!("img{i}.txt")))?;
+            assert_eq!(content, format!("other, shared, tag{i}, a photo"));
+        }
+        assert!(!temp_dir.path().join("lonely.txt").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concat_files_progress() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        for i in 0..10 {
+            std::fs::write(temp_dir.path().join(format!("img{i}.jpg")), "")?;
+            std::fs::write(temp_dir.path().join(format!("img{i}.wd")), "tag")?;
+            std::fs::write(temp_dir.path().join(format!("img{i}.tags")), "other")?;
+        }
+        // Skipped files still count towards progress
+        std::fs::write(temp_dir.path().join("lonely.jpg"), "")?;
+
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let calls_clone = calls.clone();
+        let hooks = ConcatHooks::new().with_progress(Arc::new(move |done, total| {
+            calls_clone.lock().unwrap().push((done, total));
+        }));
+        let config = ConcatConfig::from_preset(FileExtensionPreset::WdTags).with_concurrency(Some(3));
+
+        let stats = concat_files_with_hooks(temp_dir.path(), &config, false, &hooks).await?;
+        assert_eq!(stats.processed, 10);
+
+        let mut calls = calls.lock().unwrap().clone();
+        assert_eq!(calls.first(), Some(&(0, 11)));
+        calls.sort_unstable();
+        assert_eq!(calls, (0..=11).map(|done| (done, 11)).collect::<Vec<_>>());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concat_files_cancel() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        for i in 0..10 {
+            std::fs::write(temp_dir.path().join(format!("img{i}.jpg")), "")?;
+            std::fs::write(temp_dir.path().join(format!("img{i}.wd")), "tag")?;
+            std::fs::write(temp_dir.path().join(format!("img{i}.tags")), "other")?;
+        }
+
+        // Cancel as soon as the third file finishes
+        let cancel = Arc::new(AtomicBool::new(false));
+        let cancel_clone = cancel.clone();
+        let hooks = ConcatHooks::new()
+            .with_cancel(cancel.clone())
+            .with_progress(Arc::new(move |done, _| {
+                if done == 3 {
+                    cancel_clone.store(true, Ordering::Relaxed);
+                }
+            }));
+        let config = ConcatConfig::from_preset(FileExtensionPreset::WdTags).with_concurrency(Some(1));
+
+        let stats = concat_files_with_hooks(temp_dir.path(), &config, false, &hooks).await?;
+        assert_eq!(stats.processed, 3);
+        let written = (0..10)
+            .filter(|i| temp_dir.path().join(format!("img{i}.txt")).exists())
+            .count();
+        assert_eq!(written, 3);
+
+        // A flag that is already set processes nothing
+        let stats = concat_files_with_hooks(temp_dir.path(), &config, false, &hooks).await?;
+        assert_eq!(stats.processed, 0);
+
+        // ...and stops collecting files during the walk
+        let totals = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let totals_clone = totals.clone();
+        let hooks = ConcatHooks::new()
+            .with_cancel(cancel)
+            .with_progress(Arc::new(move |_, total| totals_clone.lock().unwrap().push(total)));
+        concat_files_with_hooks(temp_dir.path(), &config, false, &hooks).await?;
+        assert_eq!(*totals.lock().unwrap(), [0]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validate_output_extension() -> Result<()> {
+        let config = ConcatConfig::from_preset(FileExtensionPreset::WdTags);
+        assert!(config.validate().is_ok());
+        
+        let temp_dir = TempDir::new()?;
+        fs::write(temp_dir.path().join("test.tags"), "tag1").await?;
+        
+        let mut config = ConcatConfig::from_preset(FileExtensionPreset::WdTags);
+        config.output_extension = "tags".into();
+        let err = concat_files(temp_dir.path(), &config, false).await.unwrap_err();
+        assert!(err.to_string().contains("extensions_to_concat"));
+        assert_eq!(fs::read_to_string(temp_dir.path().join("test.tags")).await?, "tag1");
+        
+        config.output_extension = "PNG".into();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("base_extensions"));
+        
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_from_file() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+
+        // TOML config; deduplicate_files may be omitted
+        let toml_path = temp_dir.path().join("concat.toml");
+        std::fs::write(&toml_path, r#"
+base_extensions = ["png", "jpg"]
+extensions_to_concat = ["florence", "wd"]
+output_extension = "txt"
+remove_duplicates = true
+tag_separator = ", "
+"#)?;
+        let config = ConcatConfig::from_toml_file(&toml_path)?;
+        assert_eq!(config, ConcatConfig::new(
+            vec!["png".into(), "jpg".into()],
+            vec!["florence".into(), "wd".into()],
+            "txt".into(),
+            true,
+            ", ".into(),
+        ));
+
+        // JSON config round-trips through serde
+        let json_path = temp_dir.path().join("concat.json");
+        let preset = ConcatConfig::from_preset(FileExtensionPreset::CaptionWdTags);
+        std::fs::write(&json_path, serde_json::to_string(&preset)?)?;
+        assert_eq!(ConcatConfig::from_json_file(&json_path)?, preset);
+
+        // An empty extension list is rejected
+        std::fs::write(&json_path, r#"{
+            "base_extensions": ["png"],
+            "extensions_to_concat": [],
+            "output_extension": "txt",
+            "remove_duplicates": true,
+            "tag_separator": ", "
+        }"#)?;
+        let err = ConcatConfig::from_json_file(&json_path).unwrap_err();
+        assert!(err.to_string().contains("empty extensions_to_concat"));
+
         Ok(())
     }
 } 
\ No newline at end of file
@@ -40,6 +40,7 @@ use tokio::fs;
 mod tests {
     pub mod e621_tests;
     pub mod text_tests;
+    pub mod utils_tests;
 }
 
 /// Extracts and parses JSON metadata from a safetensors file.
@@ -110,6 +111,51 @@ pub async fn process_safetensors_file(path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Processes a safetensors file like [`process_safetensors_file`], unless its JSON
+/// output is already up to date.
+///
+/// The file is skipped when the `.json` sidecar exists and was modified at or after
+/// the safetensors file, which makes repeated batch runs over unchanged datasets cheap.
+///
+/// # Arguments
+/// * `path` - Path to the safetensors file to process
+///
+/// # Returns
+/// * `Result<bool>` - `true` if the metadata was extracted, `false` if it was skipped
+///
+/// # Errors
+/// Returns an error if:
+/// * Metadata extraction fails
+/// * JSON formatting fails
+/// * Writing the output file fails
+pub async fn process_safetensors_file_incremental(path: &Path) -> Result<bool> {
+    if is_output_up_to_date(&path.with_extension("json"), &[path.to_path_buf()]).await {
+        info!("Skipping {}: metadata is up to date", path.display());
+        return Ok(false);
+    }
+    process_safetensors_file(path).await?;
+    Ok(true)
+}
+
+/// Checks whether `output_path` was modified at or after every one of `sources`.
+///
+/// Returns false if the output or any source is missing or has no modification
+/// time, so callers regenerate the output rather than skip it.
+pub(crate) async fn is_output_up_to_date(output_path: &Path, sources: &[PathBuf]) -> bool {
+    let Ok(output_modified) = fs::metadata(output_path).await.and_then(|m| m.modified()) else {
+        return false;
+    };
+
+    for source in sources {
+        match fs::metadata(source).await.and_then(|m| m.modified()) {
+            Ok(source_modified) if output_modified >= source_modified => {}
+            _ => return false,
+        }
+    }
+
+    true
+}
+
 /// Processes a caption file using the functionality in the caption module.
 ///
 /// This is a wrapper function that delegates the actual processing to the
@@ -127,12 +173,39 @@ pub async fn process_caption_file(path: &Path) -> Result<()> {
     caption::process_file(path).await
 }
 
+/// Reads and parses a JSON file, decompressing it first if it is gzip-compressed.
+///
+/// A file is treated as gzip when it has a `.gz` extension or starts with the
+/// gzip magic bytes.
+async fn read_json_value(file_path: &Path) -> io::Result<Value> {
+    use std::io::Read;
+
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+    let bytes = fs::read(file_path).await?;
+    let is_gzip = bytes.starts_with(&GZIP_MAGIC)
+        || file_path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("gz"));
+
+    if is_gzip {
+        let mut content = String::new();
+        flate2::read::GzDecoder::new(bytes.as_slice()).read_to_string(&mut content)?;
+        Ok(serde_json::from_str(&content)?)
+    } else {
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
 /// Processes a JSON file using a provided async processor function.
 ///
 /// This function reads a JSON file, parses it, and applies a custom processor
 /// function to the parsed data. The processor function can perform any desired
 /// transformations or operations on the JSON data.
 ///
+/// Gzip-compressed files (e.g. `metadata.json.gz`) are detected by their `.gz`
+/// extension or gzip header and decompressed transparently.
+///
 /// # Type Parameters
 /// * `F` - The processor function type
 /// * `Fut` - The future type returned by the processor function
@@ -147,6 +220,7 @@ pub async fn process_caption_file(path: &Path) -> Result<()> {
 /// # Errors
 /// Returns an error if:
 /// * The file cannot be read
+/// * A gzip-compressed file cannot be decompressed
 /// * The content cannot be parsed as JSON
 /// * The processor function returns an error
 #[must_use = "Processes a JSON file and requires handling of the result to ensure proper file processing"]
@@ -155,11 +229,61 @@ where
     F: FnOnce(Value) -> Fut + Send,
     Fut: std::future::Future<Output = io::Result<()>> + Send,
 {
-    let content = fs::read_to_string(file_path).await?;
-    let data: Value = serde_json::from_str(&content)?;
+    let data = read_json_value(file_path).await?;
     processor(data).await
 }
 
+/// Processes a JSON file using a processor function that borrows the parsed data.
+///
+/// Like [`process_json_file`], but the processor receives `&Value`. The returned
+/// future can't borrow from the value, so read what you need from it before
+/// creating the future. Gzip-compressed files are decompressed transparently.
+///
+/// # Type Parameters
+/// * `F` - The processor function type
+/// * `Fut` - The future type returned by the processor function
+///
+/// # Arguments
+/// * `file_path` - Path to the JSON file to process
+/// * `processor` - Function that inspects the parsed JSON data and returns a future
+///
+/// # Returns
+/// * `io::Result<()>` - Success or failure of the operation
+///
+/// # Errors
+/// Returns an error if:
+/// * The file cannot be read
+/// * A gzip-compressed file cannot be decompressed
+/// * The content cannot be parsed as JSON
+/// * The processor function returns an error
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use serde_json::Value;
+/// use dset::process_json_file_ref;
+///
+/// async fn example() -> std::io::Result<()> {
+///     process_json_file_ref(Path::new("data.json"), |json: &Value| {
+///         let name = json["name"].as_str().unwrap_or_default().to_string();
+///         async move {
+///             println!("Name: {name}");
+///             Ok(())
+///         }
+///     })
+///     .await
+/// }
+/// ```
+#[must_use = "Processes a JSON file and requires handling of the result to ensure proper file processing"]
+pub async fn process_json_file_ref<F, Fut>(file_path: &Path, processor: F) -> io::Result<()>
+where
+    F: FnOnce(&Value) -> Fut + Send,
+    Fut: std::future::Future<Output = io::Result<()>> + Send,
+{
+    let data = read_json_value(file_path).await?;
+    processor(&data).await
+}
+
 /// Formats a JSON file by pretty-printing its contents.
 ///
 /// This function reads a JSON file, parses it, and writes it back with proper
@@ -179,13 +303,78 @@ where
 /// * The formatted JSON cannot be written back to the file
 #[must_use = "Formats a JSON file and requires handling of the result to ensure the file is properly formatted"]
 pub async fn format_json_file(path: PathBuf) -> Result<()> {
+    format_json_file_with_indent(path, "  ").await
+}
+
+/// Formats a JSON file by pretty-printing its contents with a custom indentation.
+///
+/// Like [`format_json_file`], but each nesting level is indented with `indent`,
+/// e.g. four spaces or a tab.
+///
+/// # Arguments
+/// * `path` - Path to the JSON file to format
+/// * `indent` - The string used for one level of indentation
+///
+/// # Returns
+/// * `Result<()>` - Success or failure of the operation
+///
+/// # Errors
+/// Returns an error if:
+/// * The file cannot be read
+/// * The content cannot be parsed as JSON
+/// * The formatted JSON cannot be written back to the file
+///
+/// # Example
+/// ```no_run
+/// use std::path::PathBuf;
+/// use dset::format_json_file_with_indent;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     format_json_file_with_indent(PathBuf::from("data.json"), "\t").await
+/// }
+/// ```
+#[must_use = "Formats a JSON file and requires handling of the result to ensure the file is properly formatted"]
+pub async fn format_json_file_with_indent(path: PathBuf, indent: &str) -> Result<()> {
+    rewrite_json_file(path, false, indent).await
+}
+
+/// Formats a JSON file by pretty-printing its contents with object keys sorted.
+///
+/// Like [`format_json_file`], but object keys are sorted recursively before writing,
+/// so the output is deterministic and diff-friendly regardless of the original key
+/// order. Arrays keep their order.
+///
+/// # Arguments
+/// * `path` - Path to the JSON file to format
+///
+/// # Returns
+/// * `Result<()>` - Success or failure of the operation
+///
+/// # Errors
+/// Returns an error if:
+/// * The file cannot be read
+/// * The content cannot be parsed as JSON
+/// * The formatted JSON cannot be written back to the file
+#[must_use = "Formats a JSON file and requires handling of the result to ensure the file is properly formatted"]
+pub async fn format_json_file_sorted(path: PathBuf) -> Result<()> {
+    rewrite_json_file(path, true, "  ").await
+}
+
+/// Reads, optionally key-sorts, and pretty-prints a JSON file in place
+async fn rewrite_json_file(path: PathBuf, sort_keys: bool, indent: &str) -> Result<()> {
     info!("Processing file: {}", path.display());
 
     let file_content = fs::read_to_string(path.clone())
         .await
         .context("Failed to read file content")?;
-    let json: Value = serde_json::from_str(&file_content).context("Failed to parse JSON")?;
-    let pretty_json = serde_json::to_string_pretty(&json).context("Failed to format JSON")?;
+    let mut json: Value = serde_json::from_str(&file_content).context("Failed to parse JSON")?;
+    if sort_keys {
+        json = sort_json_keys(json);
+    }
+    let mut pretty_json = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    let mut serializer = serde_json::Serializer::with_formatter(&mut pretty_json, formatter);
+    serde::Serialize::serialize(&json, &mut serializer).context("Failed to format JSON")?;
     fs::write(path.clone(), pretty_json)
         .await
         .context("Failed to write formatted JSON")?;
@@ -194,10 +383,33 @@ pub async fn format_json_file(path: PathBuf) -> Result<()> {
     Ok(())
 }
 
+/// Recursively rebuilds every object in `value` with its keys in sorted order
+///
+/// `serde_json::Map` keeps insertion order when the `preserve_order` feature is
+/// enabled anywhere in the dependency graph, so the keys are sorted explicitly.
+fn sort_json_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sort_json_keys(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(sort_json_keys).collect()),
+        other => other,
+    }
+}
+
 /// Splits a content string into tags and sentences.
 ///
 /// This function takes a string in the format "tag1, tag2, tag3., Sentence text"
-/// and splits it into a vector of tags and the remaining sentence text.
+/// and splits it into a vector of tags and the remaining sentence text. It is
+/// [`split_content_with`] using the `"., "` delimiter, so the period ending the
+/// tag portion is dropped, except that the sentence is trimmed.
 ///
 /// # Arguments
 /// * `content` - The string to split, expected to be in the format "tags., sentence"
@@ -218,14 +430,147 @@ pub async fn format_json_file(path: PathBuf) -> Result<()> {
 /// ```
 #[must_use = "Splits content into tags and sentences and the result should be checked"]
 pub fn split_content(content: &str) -> (Vec<String>, String) {
-    let split: Vec<_> = content.split("., ").collect();
-    let tags: Vec<_> = split[0]
-        .split(',')
-        .map(str::trim)
-        .map(String::from)
-        .collect();
-    let sentences = (*split.get(1).unwrap_or(&"")).to_string();
-    (tags, sentences.trim().to_string())
+    let (tags, sentence) = split_content_with(content, "., ");
+    (tags, sentence.trim().to_string())
+}
+
+/// Splits a content string into tags and sentences at a custom delimiter.
+///
+/// Only the first occurrence of `delimiter` splits: everything before it is split
+/// on commas into trimmed tags, and everything after it, including any further
+/// delimiters, is returned exactly as the sentence. Content without the delimiter
+/// (or an empty `delimiter`) is treated entirely as tags, with an empty sentence.
+///
+/// # Arguments
+/// * `content` - The string to split
+/// * `delimiter` - The string separating the tag portion from the sentence
+///
+/// # Returns
+/// * `(Vec<String>, String)` - A tuple containing:
+///   * A vector of tag strings
+///   * The sentence text following the first delimiter
+///
+/// # Examples
+/// ```
+/// use dset::split_content_with;
+///
+/// let (tags, sentence) = split_content_with("tag1, tag2 | A sentence. | Another.", " | ");
+/// assert_eq!(tags, vec!["tag1", "tag2"]);
+/// assert_eq!(sentence, "A sentence. | Another.");
+///
+/// let (tags, sentence) = split_content_with("tag1, tag2", " | ");
+/// assert_eq!(tags, vec!["tag1", "tag2"]);
+/// assert_eq!(sentence, "");
+/// ```
+#[must_use = "Splits content into tags and sentences and the result should be checked"]
+pub fn split_content_with(content: &str, delimiter: &str) -> (Vec<String>, String) {
+    let (tags, sentence) = if delimiter.is_empty() {
+        (content, "")
+    } else {
+        content.split_once(delimiter).unwrap_or((content, ""))
+    };
+    let tags: Vec<_> = tags.split(',').map(str::trim).map(String::from).collect();
+    (tags, sentence.to_string())
+}
+
+/// Configuration for converting tag-probability JSON files into captions.
+///
+/// The defaults reproduce the behavior of [`process_json_to_caption`]: a 0.2 threshold,
+/// ", " separator, escaped parentheses and tags sorted by descending probability.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonCaptionConfig {
+    /// Minimum probability for a tag to be included (default: 0.2)
+    pub threshold: f64,
+    /// Separator used to join tags in the caption (default: ", ")
+    pub separator: String,
+    /// Whether to escape parentheses with backslashes (default: true)
+    pub escape_parens: bool,
+    /// Whether to sort tags by descending probability (default: true).
+    /// When false, tags are sorted alphabetically instead, since the parsed JSON
+    /// object doesn't keep the file's key order.
+    pub sort_by_probability: bool,
+    /// Keep only the N highest-probability tags after filtering, whatever the output order (default: None)
+    pub top_k: Option<usize>,
+    /// Per-group thresholds for grouped tagger output such as
+    /// `{"rating": {...}, "general": {...}, "character": {...}}` (default: None).
+    /// Groups without an entry use `threshold`.
+    pub group_thresholds: Option<std::collections::HashMap<String, f64>>,
+}
+
+impl Default for JsonCaptionConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.2,
+            separator: ", ".to_string(),
+            escape_parens: true,
+            sort_by_probability: true,
+            top_k: None,
+            group_thresholds: None,
+        }
+    }
+}
+
+impl JsonCaptionConfig {
+    /// Creates a new configuration with default values
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the minimum probability threshold (default: 0.2)
+    #[must_use]
+    pub fn with_threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Sets the tag separator (default: ", ")
+    #[must_use]
+    pub fn with_separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = separator.into();
+        self
+    }
+
+    /// Sets whether to escape parentheses (default: true)
+    #[must_use]
+    pub fn with_escape_parens(mut self, escape_parens: bool) -> Self {
+        self.escape_parens = escape_parens;
+        self
+    }
+
+    /// Sets whether to sort tags by descending probability rather than
+    /// alphabetically (default: true)
+    #[must_use]
+    pub fn with_sort_by_probability(mut self, sort_by_probability: bool) -> Self {
+        self.sort_by_probability = sort_by_probability;
+        self
+    }
+
+    /// Sets the maximum number of tags to keep (default: None)
+    #[must_use]
+    pub fn with_top_k(mut self, top_k: Option<usize>) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// Sets per-group thresholds for grouped tagger output (default: None)
+    #[must_use]
+    pub fn with_group_thresholds(
+        mut self,
+        group_thresholds: Option<std::collections::HashMap<String, f64>>,
+    ) -> Self {
+        self.group_thresholds = group_thresholds;
+        self
+    }
+
+    /// Gets the threshold for a tag group, falling back to the global threshold
+    fn threshold_for_group(&self, group: &str) -> f64 {
+        self.group_thresholds
+            .as_ref()
+            .and_then(|thresholds| thresholds.get(group))
+            .copied()
+            .unwrap_or(self.threshold)
+    }
 }
 
 /// Converts a JSON file containing tag probabilities into a caption file.
@@ -234,6 +579,9 @@ pub fn split_content(content: &str) -> (Vec<String>, String) {
 /// tags based on a probability threshold (0.2), and writes the selected tags
 /// to a new .txt file. Tags are sorted by probability in descending order.
 ///
+/// This is equivalent to calling [`process_json_to_caption_with_config`] with
+/// `JsonCaptionConfig::default()`.
+///
 /// # Arguments
 /// * `input_path` - Path to the input JSON file
 ///
@@ -257,6 +605,49 @@ pub fn split_content(content: &str) -> (Vec<String>, String) {
 /// ```
 #[must_use = "Processes a JSON file to create a caption file and requires handling of the result to ensure proper conversion"]
 pub async fn process_json_to_caption(input_path: &Path) -> io::Result<()> {
+    process_json_to_caption_with_config(input_path, &JsonCaptionConfig::default()).await
+}
+
+/// Converts a JSON file containing tag probabilities into a caption file using a custom configuration.
+///
+/// Tags below `config.threshold` are dropped, the rest are truncated to the
+/// `config.top_k` most probable, ordered by descending probability or
+/// alphabetically, optionally escaped and joined with `config.separator`. The result is written next to the input file
+/// with a .txt extension. Non-JSON files are ignored.
+///
+/// Grouped tagger output (e.g. wd14-style `{"general": {...}, "character": {...}}`)
+/// is detected automatically: every top-level value that is itself an object is
+/// flattened into the tag list, using the group's entry in `config.group_thresholds`
+/// as its threshold when present.
+///
+/// # Arguments
+/// * `input_path` - Path to the input JSON file
+/// * `config` - Conversion settings
+///
+/// # Returns
+/// * `io::Result<()>` - Success or failure of the operation
+///
+/// # Errors
+/// Returns an error if:
+/// * The input file cannot be read
+/// * The content cannot be parsed as JSON
+/// * The output file cannot be written
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::{JsonCaptionConfig, process_json_to_caption_with_config};
+///
+/// async fn example() -> std::io::Result<()> {
+///     let config = JsonCaptionConfig::new().with_threshold(0.35).with_top_k(Some(20));
+///     process_json_to_caption_with_config(Path::new("image.json"), &config).await
+/// }
+/// ```
+#[must_use = "Processes a JSON file to create a caption file and requires handling of the result to ensure proper conversion"]
+pub async fn process_json_to_caption_with_config(
+    input_path: &Path,
+    config: &JsonCaptionConfig,
+) -> io::Result<()> {
     // Early return if not a JSON file
     if input_path.extension().and_then(|s| s.to_str()) != Some("json") {
         return Ok(());
@@ -268,27 +659,48 @@ pub async fn process_json_to_caption(input_path: &Path) -> io::Result<()> {
 
     let mut tags = Vec::new();
     if let Value::Object(map) = json {
-        for (tag, prob) in map {
-            if let Value::Number(prob) = prob {
-                if let Some(prob) = prob.as_f64() {
-                    if prob >= 0.2 {
-                        tags.push((tag, prob));
+        for (key, value) in map {
+            match value {
+                // Flatten nested tag groups using the group's threshold
+                Value::Object(group) => {
+                    let threshold = config.threshold_for_group(&key);
+                    for (tag, prob) in group {
+                        if let Some(prob) = prob.as_f64().filter(|prob| *prob >= threshold) {
+                            tags.push((tag, prob));
+                        }
+                    }
+                }
+                Value::Number(prob) => {
+                    if let Some(prob) = prob.as_f64().filter(|prob| *prob >= config.threshold) {
+                        tags.push((key, prob));
                     }
                 }
+                _ => {}
             }
         }
     }
 
+    // Top-k always keeps the most probable tags, whatever the output order
     tags.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    if let Some(top_k) = config.top_k {
+        tags.truncate(top_k);
+    }
+    if !config.sort_by_probability {
+        tags.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
     let tags: Vec<_> = tags
         .into_iter()
         .map(|(tag, _)| {
-            // Escape special characters with backslashes
-            tag.replace('(', "\\(").replace(')', "\\)")
+            if config.escape_parens {
+                // Escape special characters with backslashes
+                caption::escape_parens(&tag)
+            } else {
+                tag
+            }
         })
         .collect();
 
-    let output = tags.join(", ");
+    let output = tags.join(&config.separator);
     fs::write(input_path.with_extension("txt"), output).await?;
     Ok(())
 }
@@ -297,10 +709,14 @@ pub async fn process_json_to_caption(input_path: &Path) -> io::Result<()> {
 ///
 /// For example:
 /// - `image.jpg.toml` -> `image.toml`
-/// - `image.jpeg.json` -> `image.json`
+/// - `image.webp.json` -> `image.json`
 /// - `image.png` -> `image.png` (unchanged)
 /// - `image.png.jpg` -> `image.jpg`
 ///
+/// Image extensions are those in [`concat::IMAGE_EXTENSIONS`]; use
+/// [`rename_file_without_image_extension_with`] for a custom set. An existing file at
+/// the target path is never overwritten.
+///
 /// This is useful for cleaning up file names in datasets where image extensions might have been
 /// accidentally preserved when converting files to other formats.
 ///
@@ -312,6 +728,7 @@ pub async fn process_json_to_caption(input_path: &Path) -> io::Result<()> {
 ///
 /// # Errors
 /// Returns an error if:
+/// * The target path already exists (`io::ErrorKind::AlreadyExists`)
 /// * The file cannot be renamed
 /// * The file system operation fails
 /// * The file name is invalid UTF-8
@@ -334,6 +751,53 @@ pub async fn process_json_to_caption(input_path: &Path) -> io::Result<()> {
 /// ```
 #[must_use = "Renames a file and requires handling of the result to ensure the file is properly renamed"]
 pub async fn rename_file_without_image_extension(path: &Path) -> io::Result<()> {
+    rename_file_without_image_extension_with(path, concat::IMAGE_EXTENSIONS, false).await
+}
+
+/// Renames a file to remove any of the given image extensions that appear between the
+/// base filename and the actual extension.
+///
+/// Behaves like [`rename_file_without_image_extension`], but treats `image_exts` as the
+/// image extensions. Extensions are compared case-insensitively and should be given
+/// in lowercase, without a leading dot.
+///
+/// # Arguments
+/// * `path` - Path to the file to rename
+/// * `image_exts` - Extensions to strip from the middle of the file name
+/// * `overwrite` - Whether to replace an existing file at the target path
+///
+/// # Returns
+/// * `io::Result<()>` - Success or failure of the operation
+///
+/// # Errors
+/// Returns an error if:
+/// * The target path already exists and `overwrite` is false (`io::ErrorKind::AlreadyExists`)
+/// * The file cannot be renamed
+/// * The file system operation fails
+/// * The file name is invalid UTF-8
+///
+/// # Panics
+/// This function will panic if:
+/// * The file name has multiple extensions but `parts.last()` fails to get the last extension
+///   (this should never happen as we check `parts.len() >= 3` before accessing)
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::rename_file_without_image_extension_with;
+///
+/// async fn example() -> std::io::Result<()> {
+///     let path = Path::new("image.heic.txt");
+///     rename_file_without_image_extension_with(&path, &["heic"], false).await?;  // Will rename to "image.txt"
+///     Ok(())
+/// }
+/// ```
+#[must_use = "Renames a file and requires handling of the result to ensure the file is properly renamed"]
+pub async fn rename_file_without_image_extension_with(
+    path: &Path,
+    image_exts: &[&str],
+    overwrite: bool,
+) -> io::Result<()> {
     // Get the file stem and extension
     let file_name = path
         .file_name()
@@ -348,7 +812,7 @@ pub async fn rename_file_without_image_extension(path: &Path) -> io::Result<()>
         // Check if any middle extension is an image extension
         let mut has_image_ext = false;
         for ext in &parts[1..parts.len() - 1] {
-            if matches!(ext.to_lowercase().as_str(), "jpg" | "jpeg" | "png") {
+            if image_exts.contains(&ext.to_lowercase().as_str()) {
                 has_image_ext = true;
                 break;
             }
@@ -366,6 +830,17 @@ pub async fn rename_file_without_image_extension(path: &Path) -> io::Result<()>
             let parent = path.parent().unwrap_or_else(|| Path::new(""));
             let new_path = parent.join(new_name);
 
+            if !overwrite && fs::try_exists(&new_path).await? {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "Cannot rename {}: {} already exists",
+                        path.display(),
+                        new_path.display()
+                    ),
+                ));
+            }
+
             fs::rename(path, &new_path).await?;
             info!("Renamed {} to {}", path.display(), new_path.display());
         }
@@ -373,6 +848,163 @@ pub async fn rename_file_without_image_extension(path: &Path) -> io::Result<()>
     Ok(())
 }
 
+/// Renames caption sidecar files to a single canonical extension.
+///
+/// Datasets often mix `.txt`, `.caption` and `.cap` files for the same purpose. This
+/// function walks `dir` recursively and renames every file whose extension is listed
+/// in `from_exts` to use `to_ext` instead, e.g. `image.cap` -> `image.txt`.
+///
+/// Files are never overwritten: if the target path already exists (or another file in
+/// the same run would be renamed to it), the file is skipped with a warning.
+/// Extensions are compared case-insensitively and may be given with or without a leading dot.
+///
+/// # Arguments
+/// * `dir` - Directory to search recursively
+/// * `from_exts` - Extensions to rename (e.g. `["caption", "cap"]`)
+/// * `to_ext` - The canonical extension to rename to (e.g. "txt")
+/// * `dry_run` - If true, only logs what would be renamed without touching any files
+///
+/// # Returns
+/// * `Result<usize>` - Number of files renamed (or that would be renamed in dry-run mode)
+///
+/// # Errors
+/// Returns an error if:
+/// * The directory cannot be walked
+/// * A file cannot be renamed
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::normalize_caption_extension;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let from = vec!["caption".to_string(), "cap".to_string()];
+///     let renamed = normalize_caption_extension(Path::new("dataset"), &from, "txt", false).await?;
+///     println!("Renamed {renamed} files");
+///     Ok(())
+/// }
+/// ```
+pub async fn normalize_caption_extension(
+    dir: &Path,
+    from_exts: &[String],
+    to_ext: &str,
+    dry_run: bool,
+) -> Result<usize> {
+    let to_ext = to_ext.trim_start_matches('.');
+    let mut planned_targets = std::collections::HashSet::new();
+    let mut renamed = 0;
+
+    for path in caption::files_with_extensions(dir, from_exts).await? {
+        // Skip files that already use the canonical extension
+        if path
+            .extension()
+            .is_some_and(|ext| ext.to_string_lossy().eq_ignore_ascii_case(to_ext))
+        {
+            continue;
+        }
+
+        let new_path = path.with_extension(to_ext);
+        if new_path.exists() || planned_targets.contains(&new_path) {
+            log::warn!(
+                "Skipping {}: {} already exists",
+                path.display(),
+                new_path.display()
+            );
+            continue;
+        }
+
+        if dry_run {
+            info!("Would rename {} to {}", path.display(), new_path.display());
+        } else {
+            fs::rename(&path, &new_path)
+                .await
+                .with_context(|| format!("Failed to rename {}", path.display()))?;
+            info!("Renamed {} to {}", path.display(), new_path.display());
+        }
+        planned_targets.insert(new_path);
+        renamed += 1;
+    }
+
+    Ok(renamed)
+}
+
+/// Counts of files handled by [`process_directory`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessSummary {
+    /// Number of files the processor handled successfully
+    pub processed: usize,
+    /// Number of files for which the processor returned an error
+    pub errored: usize,
+}
+
+/// Runs an async processor on every file in a directory with one of the given extensions.
+///
+/// Extensions are compared case-insensitively and may be given with or without a
+/// leading dot; "*" matches every file. Files are processed one at a time in sorted
+/// path order. A processor error is logged and counted, and the batch continues with
+/// the next file.
+///
+/// # Type Parameters
+/// * `F` - The processor function type
+/// * `Fut` - The future type returned by the processor function
+///
+/// # Arguments
+/// * `dir` - Directory to search
+/// * `extensions` - Extensions of the files to process (e.g. `&["txt", "caption"]`)
+/// * `recursive` - Whether to also process files in subdirectories
+/// * `processor` - Async function called with the path of each matching file
+///
+/// # Returns
+/// * `Result<ProcessSummary>` - How many files were processed and how many errored
+///
+/// # Errors
+/// Returns an error if the directory cannot be walked
+///
+/// # Example
+/// ```no_run
+/// use std::path::Path;
+/// use dset::process_directory;
+///
+/// async fn example() -> anyhow::Result<()> {
+///     let summary = process_directory(Path::new("dataset"), &["txt"], true, |path| async move {
+///         let caption = tokio::fs::read_to_string(&path).await?;
+///         tokio::fs::write(&path, caption.trim()).await?;
+///         Ok(())
+///     })
+///     .await?;
+///     println!("{} trimmed, {} failed", summary.processed, summary.errored);
+///     Ok(())
+/// }
+/// ```
+pub async fn process_directory<F, Fut>(
+    dir: &Path,
+    extensions: &[&str],
+    recursive: bool,
+    mut processor: F,
+) -> Result<ProcessSummary>
+where
+    F: FnMut(PathBuf) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    let mut summary = ProcessSummary::default();
+    for path in caption::collect_files(dir, extensions, recursive, None).await? {
+        match processor(path.clone()).await {
+            Ok(()) => summary.processed += 1,
+            Err(err) => {
+                log::warn!("Error processing {}: {:#}", path.display(), err);
+                summary.errored += 1;
+            }
+        }
+    }
+    info!(
+        "Processed {} files in {} ({} errors)",
+        summary.processed,
+        dir.display(),
+        summary.errored
+    );
+    Ok(summary)
+}
+
 /// Process an e621 JSON file and generate caption files.
 ///
 /// # Arguments
@@ -1,23 +1,48 @@
 #![warn(clippy::all, clippy::pedantic)]
 
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
 
 /// Recursively decodes JSON-encoded strings within a `serde_json::Value`.
-/// If a string equals `None`, it is converted to JSON null. If a string starts with `{` or `[` and ends with `}` or `]`,
+/// If a string equals `None`, it is converted to JSON null, and `True`/`False` (or `true`/`false`)
+/// are converted to JSON booleans. If a string starts with `{` or `[` and ends with `}` or `]`,
 /// it attempts to parse it as JSON and then recursively decodes its contents.
+/// Strings that were JSON-encoded more than once (a JSON string literal wrapping an
+/// object or array) are unwrapped, up to a small fixed depth.
+#[must_use]
 pub fn decode_json_strings(value: Value) -> Value {
+    decode_json_strings_with(value, false)
+}
+
+/// Like [`decode_json_strings`], optionally converting numeric strings into JSON numbers.
+///
+/// When `coerce_numbers` is true, strings that are valid JSON numbers (such as `"0.0001"`,
+/// `"64"` or `"1e-4"`) become `Value::Number`. Strings that would lose information are
+/// left alone, including `"16:9"`, leading-zero strings like `"007"` and integers too
+/// large to represent exactly.
+#[must_use]
+pub fn decode_json_strings_with(value: Value, coerce_numbers: bool) -> Value {
     match value {
         Value::String(s) => {
+            let s = unwrap_encoded_string(s);
             let trimmed = s.trim();
             if trimmed == "None" {
                 Value::Null
+            } else if trimmed == "True" || trimmed == "true" {
+                Value::Bool(true)
+            } else if trimmed == "False" || trimmed == "false" {
+                Value::Bool(false)
             } else if (trimmed.starts_with('{') && trimmed.ends_with('}'))
                 || (trimmed.starts_with('[') && trimmed.ends_with(']'))
             {
                 match serde_json::from_str::<Value>(trimmed) {
-                    Ok(parsed) => decode_json_strings(parsed),
+                    Ok(parsed) => decode_json_strings_with(parsed, coerce_numbers),
                     Err(_) => Value::String(s),
                 }
+            } else if let Some(number) = coerce_numbers.then(|| parse_number(trimmed)).flatten() {
+                number
             } else {
                 Value::String(s)
             }
@@ -25,15 +50,55 @@ pub fn decode_json_strings(value: Value) -> Value {
         Value::Object(map) => {
             let new_map = map
                 .into_iter()
-                .map(|(k, v)| (k, decode_json_strings(v)))
+                .map(|(k, v)| (k, decode_json_strings_with(v, coerce_numbers)))
                 .collect();
             Value::Object(new_map)
         }
-        Value::Array(arr) => Value::Array(arr.into_iter().map(decode_json_strings).collect()),
+        Value::Array(arr) => Value::Array(
+            arr.into_iter()
+                .map(|v| decode_json_strings_with(v, coerce_numbers))
+                .collect(),
+        ),
         other => other,
     }
 }
 
+/// Maximum number of extra JSON string layers unwrapped by [`unwrap_encoded_string`]
+const MAX_ENCODING_DEPTH: usize = 4;
+
+/// Unwraps JSON string literals (e.g. `"\"{\\\"a\\\": 1}\""`) whose content still looks
+/// like a JSON object or array, as produced by exporters that encode metadata twice.
+fn unwrap_encoded_string(s: String) -> String {
+    let mut candidate = None;
+    for _ in 0..MAX_ENCODING_DEPTH {
+        let trimmed = candidate.as_ref().unwrap_or(&s).trim();
+        if !(trimmed.len() >= 2 && trimmed.starts_with('"') && trimmed.ends_with('"')) {
+            break;
+        }
+        match serde_json::from_str::<Value>(trimmed) {
+            Ok(Value::String(inner)) => candidate = Some(inner),
+            _ => break,
+        }
+    }
+
+    // Only unwrap when the innermost layer is an object or array
+    match candidate {
+        Some(inner) if inner.trim_start().starts_with(['{', '[']) => inner,
+        _ => s,
+    }
+}
+
+/// Parses a string that is exactly a JSON number, without losing information.
+fn parse_number(s: &str) -> Option<Value> {
+    let number = serde_json::from_str::<serde_json::Number>(s).ok()?;
+    let is_integer_syntax = !s.contains(['.', 'e', 'E']);
+    if is_integer_syntax && number.is_f64() {
+        // Integer too large for i64/u64, which would be rounded
+        return None;
+    }
+    Some(Value::Number(number))
+}
+
 /// Extracts the training metadata from the raw metadata.
 /// If the raw metadata contains a `__metadata__` field, it decodes that field.
 /// Otherwise, it decodes the entire metadata.
@@ -62,6 +127,185 @@ pub fn extract_training_metadata(raw_metadata: &Value) -> Value {
     }
 }
 
+/// Flattens `ss_tag_frequency` into tag counts summed across all datasets.
+///
+/// `ss_tag_frequency` maps each dataset directory to a `{tag: count}` map. The metadata is
+/// decoded with [`extract_training_metadata`] first, so the field may be a decoded object or
+/// a JSON-encoded string. The result is sorted by descending count, then by tag. Missing
+/// or malformed frequency data yields an empty vector.
+#[must_use]
+pub fn tag_frequency(meta: &Value) -> Vec<(String, u64)> {
+    let decoded = extract_training_metadata(meta);
+    let Some(Value::Object(datasets)) = decoded.get("ss_tag_frequency") else {
+        return Vec::new();
+    };
+
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    for tags in datasets.values().filter_map(Value::as_object) {
+        for (tag, count) in tags {
+            let count = match count {
+                Value::Number(n) => n.as_u64(),
+                Value::String(s) => s.trim().parse().ok(),
+                _ => None,
+            };
+            if let Some(count) = count {
+                *counts.entry(tag.clone()).or_default() += count;
+            }
+        }
+    }
+
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_unstable_by(|(a_tag, a), (b_tag, b)| b.cmp(a).then_with(|| a_tag.cmp(b_tag)));
+    counts
+}
+
+/// Flattens nested objects and arrays into a map of dotted keys to leaf values.
+///
+/// Object keys are joined with `.` and array elements use their index, so
+/// `{"ss_bucket_info": {"buckets": [{"count": 78}]}}` becomes
+/// `{"ss_bucket_info.buckets.0.count": 78}`. Empty objects and arrays are kept as
+/// leaves so no key is lost, and a scalar root is stored under the empty key.
+///
+/// # Example
+/// ```
+/// use dset::metadata::flatten;
+/// use serde_json::json;
+///
+/// let flat = flatten(&json!({"ss_network_args": {"network_dim": 64}, "ss_resolution": [512, 768]}));
+/// assert_eq!(flat["ss_network_args.network_dim"], json!(64));
+/// assert_eq!(flat["ss_resolution.1"], json!(768));
+/// ```
+#[must_use]
+pub fn flatten(value: &Value) -> BTreeMap<String, Value> {
+    fn walk(prefix: &str, value: &Value, out: &mut BTreeMap<String, Value>) {
+        let join = |key: &str| {
+            if prefix.is_empty() {
+                key.to_string()
+            } else {
+                format!("{prefix}.{key}")
+            }
+        };
+        match value {
+            Value::Object(map) if !map.is_empty() => {
+                for (key, child) in map {
+                    walk(&join(key), child, out);
+                }
+            }
+            Value::Array(items) if !items.is_empty() => {
+                for (i, child) in items.iter().enumerate() {
+                    walk(&join(&i.to_string()), child, out);
+                }
+            }
+            leaf => {
+                out.insert(prefix.to_string(), leaf.clone());
+            }
+        }
+    }
+
+    let mut out = BTreeMap::new();
+    walk("", value, &mut out);
+    out
+}
+
+/// Typed view of the common training parameters written by Kohya's sd-scripts.
+///
+/// Kohya stores most values as strings (e.g. `"ss_learning_rate": "0.0001"`), so every
+/// field accepts either a string or a native JSON value. Values that can't be converted
+/// are left as `None`, and keys without a dedicated field are kept in `extra`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KohyaMetadata {
+    /// Base learning rate
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_learning_rate: Option<f64>,
+    /// `UNet` learning rate
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_unet_lr: Option<f64>,
+    /// Text encoder learning rate
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_text_encoder_lr: Option<f64>,
+    /// Network module, such as `networks.lora`
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_network_module: Option<String>,
+    /// Network rank
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_network_dim: Option<u64>,
+    /// Network alpha
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_network_alpha: Option<f64>,
+    /// Number of epochs the training was configured for
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_num_epochs: Option<u64>,
+    /// Epoch at which this file was saved
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_epoch: Option<u64>,
+    /// Step at which this file was saved
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_steps: Option<u64>,
+    /// Maximum number of training steps
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_max_train_steps: Option<u64>,
+    /// Number of training images
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_num_train_images: Option<u64>,
+    /// Batch size per device
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_batch_size_per_device: Option<u64>,
+    /// Training resolution, as written by the trainer (e.g. `(1024, 1024)`)
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_resolution: Option<String>,
+    /// Optimizer name and arguments
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_optimizer: Option<String>,
+    /// Learning rate scheduler
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_lr_scheduler: Option<String>,
+    /// Mixed precision mode, such as `fp16` or `bf16`
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_mixed_precision: Option<String>,
+    /// Name of the base model
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_sd_model_name: Option<String>,
+    /// Random seed
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_seed: Option<u64>,
+    /// Output name of the trained model
+    #[serde(default, deserialize_with = "lenient")]
+    pub ss_output_name: Option<String>,
+    /// All other metadata keys
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+impl KohyaMetadata {
+    /// Builds a typed view of the training metadata in `raw_metadata`.
+    ///
+    /// The metadata is first decoded with [`extract_training_metadata`], so both the raw
+    /// `__metadata__` form and already-decoded metadata are accepted. Anything that isn't
+    /// a JSON object yields the default (empty) metadata.
+    #[must_use]
+    pub fn from_value(raw_metadata: &Value) -> Self {
+        serde_json::from_value(extract_training_metadata(raw_metadata)).unwrap_or_default()
+    }
+}
+
+/// Deserializes a value given either natively or as a string, yielding `None` when it
+/// can't be converted instead of failing the whole struct.
+fn lenient<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr + serde::de::DeserializeOwned,
+{
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Null => None,
+        Value::String(s) => s.trim().parse().ok(),
+        other => other
+            .to_string()
+            .parse()
+            .ok()
+            .or_else(|| serde_json::from_value(other).ok()),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -74,6 +318,57 @@ mod tests {
         assert_eq!(decoded, Value::Null);
     }
 
+    #[test]
+    fn test_decode_json_strings_bool() {
+        let input = json!({
+            "flip_aug": "True",
+            "color_aug": "False",
+            "shuffle_caption": "true",
+            "note": "True story"
+        });
+        let decoded = decode_json_strings(input);
+        let expected = json!({
+            "flip_aug": true,
+            "color_aug": false,
+            "shuffle_caption": true,
+            "note": "True story"
+        });
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn test_decode_json_strings_coerce_numbers() {
+        let input = json!({
+            "ss_learning_rate": "0.0001",
+            "ss_network_dim": "64",
+            "ss_min_snr_gamma": "-5",
+            "ss_text_encoder_lr": "1e-4",
+            "aspect": "16:9",
+            "seed": "007",
+            "huge": "123456789012345678901234567890",
+            "nested": "{\"lr\": \"0.5\"}"
+        });
+
+        // Disabled by default
+        assert_eq!(
+            decode_json_strings(input.clone())["ss_network_dim"],
+            json!("64")
+        );
+
+        let decoded = decode_json_strings_with(input, true);
+        let expected = json!({
+            "ss_learning_rate": 0.0001,
+            "ss_network_dim": 64,
+            "ss_min_snr_gamma": -5,
+            "ss_text_encoder_lr": 0.0001,
+            "aspect": "16:9",
+            "seed": "007",
+            "huge": "123456789012345678901234567890",
+            "nested": {"lr": 0.5}
+        });
+        assert_eq!(decoded, expected);
+    }
+
     #[test]
     fn test_decode_json_strings_object() {
         let input = json!({
@@ -118,4 +413,106 @@ mod tests {
         });
         assert_eq!(extracted, expected);
     }
+
+    #[test]
+    fn test_kohya_metadata_from_value() {
+        let raw = json!({
+            "__metadata__": "{\"ss_learning_rate\": \"0.0001\", \"ss_network_dim\": \"32\", \"ss_network_alpha\": \"16.0\", \"ss_num_epochs\": 10, \"ss_resolution\": \"(1024, 1024)\", \"ss_network_module\": \"networks.lora\", \"ss_seed\": \"not a number\", \"ss_custom\": \"value\"}"
+        });
+        let meta = KohyaMetadata::from_value(&raw);
+
+        assert_eq!(meta.ss_learning_rate, Some(0.0001));
+        assert_eq!(meta.ss_network_dim, Some(32));
+        assert_eq!(meta.ss_network_alpha, Some(16.0));
+        assert_eq!(meta.ss_num_epochs, Some(10));
+        assert_eq!(meta.ss_resolution.as_deref(), Some("(1024, 1024)"));
+        assert_eq!(meta.ss_network_module.as_deref(), Some("networks.lora"));
+        assert_eq!(meta.ss_seed, None);
+        assert_eq!(meta.ss_optimizer, None);
+        assert_eq!(meta.extra.get("ss_custom"), Some(&json!("value")));
+
+        assert_eq!(
+            KohyaMetadata::from_value(&json!(null)),
+            KohyaMetadata::default()
+        );
+    }
+
+    #[test]
+    fn test_tag_frequency() {
+        let encoded = json!({
+            "__metadata__": "{\"ss_tag_frequency\": \"{\\\"1_cats\\\": {\\\"cat\\\": 10, \\\"solo\\\": 4}, \\\"2_dogs\\\": {\\\"dog\\\": 7, \\\"solo\\\": 3}}\"}"
+        });
+        let expected = vec![
+            ("cat".to_string(), 10),
+            ("dog".to_string(), 7),
+            ("solo".to_string(), 7),
+        ];
+        assert_eq!(tag_frequency(&encoded), expected);
+
+        let decoded = json!({
+            "ss_tag_frequency": {
+                "1_cats": {"cat": 10, "solo": 4},
+                "2_dogs": {"dog": 7, "solo": 3}
+            }
+        });
+        assert_eq!(tag_frequency(&decoded), expected);
+
+        assert!(tag_frequency(&json!({})).is_empty());
+        assert!(tag_frequency(&json!({"ss_tag_frequency": "garbage"})).is_empty());
+    }
+
+    #[test]
+    fn test_decode_double_encoded_strings() {
+        let inner = json!({"network_dim": 64, "flags": ["a", "b"]});
+        let once = serde_json::to_string(&inner).unwrap();
+        let twice = serde_json::to_string(&once).unwrap();
+        let thrice = serde_json::to_string(&twice).unwrap();
+
+        assert_eq!(
+            decode_json_strings(json!({"args": twice})),
+            json!({"args": inner})
+        );
+        assert_eq!(
+            decode_json_strings(json!({"args": thrice})),
+            json!({"args": inner})
+        );
+
+        // Double-encoded __metadata__ as produced by some exporters
+        let raw = json!({
+            "__metadata__": serde_json::to_string(&json!({"ss_network_args": twice})).unwrap()
+        });
+        assert_eq!(
+            extract_training_metadata(&raw),
+            json!({"ss_network_args": inner})
+        );
+
+        // Quoted plain strings are left alone
+        let quoted = json!({"name": "\"my model\""});
+        assert_eq!(decode_json_strings(quoted.clone()), quoted);
+    }
+
+    #[test]
+    fn test_flatten() {
+        let value = json!({
+            "ss_bucket_info": {
+                "buckets": {"0": {"resolution": [1280, 800], "count": 78}},
+                "mean_img_ar_error": 0.0
+            },
+            "ss_dataset_dirs": [],
+            "ss_seed": 42
+        });
+        let flat = flatten(&value);
+        let expected: BTreeMap<String, Value> = [
+            ("ss_bucket_info.buckets.0.count", json!(78)),
+            ("ss_bucket_info.buckets.0.resolution.0", json!(1280)),
+            ("ss_bucket_info.buckets.0.resolution.1", json!(800)),
+            ("ss_bucket_info.mean_img_ar_error", json!(0.0)),
+            ("ss_dataset_dirs", json!([])),
+            ("ss_seed", json!(42)),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect();
+        assert_eq!(flat, expected);
+    }
 }
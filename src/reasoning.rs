@@ -1,7 +1,9 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::path::Path;
 use tokio::fs;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
 
 /// Represents a single message in a conversation
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +27,203 @@ pub struct ReasoningEntry {
     pub template: String,
     /// List of messages exchanged in the conversation
     pub conversations: Vec<Message>,
+    /// Optional system prompt preceding the conversation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+}
+
+impl ReasoningEntry {
+    /// Checks that the entry is internally consistent
+    ///
+    /// The `user`, `reasoning` and `assistant` fields must be non-empty, and
+    /// `conversations` must consist of one or more turns with the `user`,
+    /// `reasoning` and `assistant` roles in that order, optionally preceded by a
+    /// `system` message. `template` must match either
+    /// [`ReasoningDataset::create_template_with_system`] for the entry's fields or,
+    /// for multi-turn entries, [`ReasoningDataset::template_from_conversations`]
+    /// with the default [`TemplateConfig`] and the entry's system prompt.
+    ///
+    /// # Errors
+    ///
+    /// Returns every problem found, as human-readable messages.
+    pub fn validate(&self) -> std::result::Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        for (name, value) in [
+            ("user", &self.user),
+            ("reasoning", &self.reasoning),
+            ("assistant", &self.assistant),
+        ] {
+            if value.trim().is_empty() {
+                errors.push(format!("`{name}` is empty"));
+            }
+        }
+
+        let turns = match self.conversations.split_first() {
+            Some((first, rest)) if first.role == "system" => rest,
+            _ => &self.conversations[..],
+        };
+        if turns.is_empty() {
+            errors.push("conversations have no turns".to_string());
+        }
+        for (i, turn) in turns.chunks(3).enumerate() {
+            let roles: Vec<&str> = turn.iter().map(|m| m.role.as_str()).collect();
+            if roles != ["user", "reasoning", "assistant"] {
+                errors.push(format!(
+                    "turn {} has roles [{}], expected [user, reasoning, assistant]",
+                    i + 1,
+                    roles.join(", ")
+                ));
+            }
+        }
+
+        let field_template = ReasoningDataset::create_template_with_system(
+            self.system.as_deref(),
+            &self.user,
+            &self.reasoning,
+            &self.assistant,
+        );
+        let multi_turn = turns.len() > 3;
+        if self.template != field_template
+            && !(multi_turn && self.template == self.conversation_template())
+        {
+            errors.push(
+                "`template` doesn't match the system, user, reasoning and assistant fields"
+                    .to_string(),
+            );
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Renders `conversations` with the default template, adding the system
+    /// prompt unless the conversation already starts with one
+    fn conversation_template(&self) -> String {
+        let config = TemplateConfig::default();
+        match &self.system {
+            Some(system)
+                if self
+                    .conversations
+                    .first()
+                    .is_none_or(|m| m.role != "system") =>
+            {
+                let mut messages = vec![Message {
+                    content: system.clone(),
+                    role: "system".to_string(),
+                }];
+                messages.extend(self.conversations.iter().cloned());
+                ReasoningDataset::template_from_conversations(&messages, &config)
+            }
+            _ => ReasoningDataset::template_from_conversations(&self.conversations, &config),
+        }
+    }
+}
+
+/// Delimiters and role names used to render a chat template
+///
+/// Each section is rendered as `{start_token}{role}\n{content}{end_token}`, and
+/// sections are joined with newlines. The default is `ChatML` with the
+/// `user`, `reasoning` and `assistant` roles.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TemplateConfig {
+    /// Token written before each role name
+    pub start_token: String,
+    /// Token written after each section's content
+    pub end_token: String,
+    /// Role names, matched with sections by position
+    pub roles: Vec<String>,
+}
+
+impl Default for TemplateConfig {
+    fn default() -> Self {
+        Self {
+            start_token: "<|im_start|>".to_string(),
+            end_token: "<|im_end|>".to_string(),
+            roles: vec![
+                "user".to_string(),
+                "reasoning".to_string(),
+                "assistant".to_string(),
+            ],
+        }
+    }
+}
+
+/// How [`ReasoningDataset::to_openai_jsonl`] handles roles other than
+/// `system`, `user` and `assistant`, such as `reasoning`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExtraRoles {
+    /// Prepend their content to the next assistant message, separated by a blank line
+    #[default]
+    FoldIntoAssistant,
+    /// Leave them out of the export
+    Drop,
+}
+
+/// Roles accepted by the `OpenAI` fine-tuning format
+const OPENAI_ROLES: [&str; 3] = ["system", "user", "assistant"];
+
+/// Default role renames applied by [`ReasoningDataset::to_sharegpt_json`]
+const DEFAULT_SHAREGPT_ROLES: [(&str, &str); 3] = [
+    ("user", "human"),
+    ("assistant", "gpt"),
+    ("reasoning", "gpt"),
+];
+
+/// Advances a `SplitMix64` generator and returns its next output
+///
+/// Used for seeded shuffling, where the output only has to be reproducible
+/// across runs and platforms.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Reads `ReasoningEntry` values from a JSONL file one line at a time
+///
+/// Created by [`ReasoningDataset::stream_jsonl`]. Only the current line is held
+/// in memory, so files larger than RAM can be processed.
+#[derive(Debug)]
+pub struct ReasoningEntryStream {
+    lines: Lines<BufReader<fs::File>>,
+    line_number: usize,
+}
+
+impl ReasoningEntryStream {
+    /// Returns the next entry, or `None` at the end of the file
+    ///
+    /// Blank lines are skipped. A line that fails to parse yields an `Err`, and
+    /// the following call continues with the next line.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The file cannot be read
+    /// - The current line cannot be parsed as a `ReasoningEntry`
+    pub async fn next_entry(&mut self) -> Option<Result<ReasoningEntry>> {
+        loop {
+            let line = match self.lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.line_number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line_number = self.line_number;
+            return Some(
+                serde_json::from_str(&line)
+                    .with_context(|| format!("Failed to parse entry on line {line_number}")),
+            );
+        }
+    }
 }
 
 /// Represents a collection of reasoning dataset entries
@@ -69,11 +268,364 @@ impl ReasoningDataset {
         Ok(())
     }
 
+    /// Loads a reasoning dataset from a JSONL file, one `ReasoningEntry` per line
+    ///
+    /// Blank lines are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The file cannot be read
+    /// - A non-blank line cannot be parsed as a `ReasoningEntry`
+    pub async fn load_jsonl<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let content = fs::read_to_string(path).await?;
+        let mut dataset = Self::new();
+        for (i, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = serde_json::from_str(line)
+                .with_context(|| format!("Failed to parse entry on line {}", i + 1))?;
+            dataset.add_entry(entry);
+        }
+        Ok(dataset)
+    }
+
+    /// Opens a JSONL file for reading one `ReasoningEntry` at a time
+    ///
+    /// Unlike [`ReasoningDataset::load_jsonl`], entries are parsed lazily and
+    /// malformed lines don't abort the stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened
+    ///
+    /// # Example
+    /// ```no_run
+    /// use dset::reasoning::ReasoningDataset;
+    ///
+    /// async fn example() -> anyhow::Result<()> {
+    ///     let mut stream = ReasoningDataset::stream_jsonl("huge.jsonl").await?;
+    ///     while let Some(entry) = stream.next_entry().await {
+    ///         match entry {
+    ///             Ok(entry) => println!("{}", entry.user),
+    ///             Err(e) => eprintln!("Skipping: {e}"),
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn stream_jsonl<P: AsRef<Path>>(path: P) -> Result<ReasoningEntryStream> {
+        let file = fs::File::open(path).await?;
+        Ok(ReasoningEntryStream {
+            lines: BufReader::new(file).lines(),
+            line_number: 0,
+        })
+    }
+
+    /// Saves the reasoning dataset to a JSONL file, one `ReasoningEntry` per line
+    ///
+    /// Every line, including the last one, ends with a newline.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The file cannot be written
+    /// - An entry cannot be serialized to JSON
+    pub async fn save_jsonl<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut content = String::new();
+        for entry in &self.entries {
+            content.push_str(&serde_json::to_string(entry)?);
+            content.push('\n');
+        }
+        fs::write(path, content).await?;
+        Ok(())
+    }
+
+    /// Exports the dataset as a `ShareGPT` JSON array
+    ///
+    /// Each entry becomes `{"conversations": [{"from": ..., "value": ...}, ...]}`,
+    /// starting with a `system` turn when the entry has a system prompt.
+    /// Roles are renamed with the default `ShareGPT` mapping (`user` → `human`,
+    /// `assistant` → `gpt`, `reasoning` → `gpt`), overridden by `role_map`.
+    /// Roles that aren't mapped are kept as-is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The file cannot be written
+    /// - The dataset cannot be serialized to JSON
+    ///
+    /// # Example
+    /// ```no_run
+    /// use dset::reasoning::ReasoningDataset;
+    ///
+    /// async fn example(dataset: &ReasoningDataset) -> anyhow::Result<()> {
+    ///     // Keep reasoning as a separate "thought" turn
+    ///     dataset.to_sharegpt_json("sharegpt.json", &[("reasoning", "thought")]).await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn to_sharegpt_json<P: AsRef<Path>>(
+        &self,
+        path: P,
+        role_map: &[(&str, &str)],
+    ) -> Result<()> {
+        let rename = |role: &str| -> String {
+            role_map
+                .iter()
+                .chain(DEFAULT_SHAREGPT_ROLES.iter())
+                .find(|(from, _)| *from == role)
+                .map_or(role, |(_, to)| to)
+                .to_string()
+        };
+
+        let conversations: Vec<serde_json::Value> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let system = entry.system.iter().map(|system| {
+                    serde_json::json!({
+                        "from": rename("system"),
+                        "value": system,
+                    })
+                });
+                let turns: Vec<serde_json::Value> = system
+                    .chain(entry.conversations.iter().map(|message| {
+                        serde_json::json!({
+                            "from": rename(&message.role),
+                            "value": message.content,
+                        })
+                    }))
+                    .collect();
+                serde_json::json!({ "conversations": turns })
+            })
+            .collect();
+
+        let content = serde_json::to_string_pretty(&conversations)?;
+        fs::write(path, content).await?;
+        Ok(())
+    }
+
+    /// Exports the dataset as `OpenAI` fine-tuning JSONL
+    ///
+    /// Each entry becomes one `{"messages": [{"role": ..., "content": ...}, ...]}`
+    /// line, starting with a system message taken from the entry's own `system`
+    /// field or, failing that, from `system_prompt`. Roles
+    /// outside `system`, `user` and `assistant` are handled according to `extra_roles`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if:
+    /// - The file cannot be written
+    /// - An entry cannot be serialized to JSON
+    ///
+    /// # Example
+    /// ```no_run
+    /// use dset::reasoning::{ExtraRoles, ReasoningDataset};
+    ///
+    /// async fn example(dataset: &ReasoningDataset) -> anyhow::Result<()> {
+    ///     dataset
+    ///         .to_openai_jsonl("train.jsonl", Some("You are a helpful assistant."), ExtraRoles::Drop)
+    ///         .await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn to_openai_jsonl<P: AsRef<Path>>(
+        &self,
+        path: P,
+        system_prompt: Option<&str>,
+        extra_roles: ExtraRoles,
+    ) -> Result<()> {
+        let mut content = String::new();
+        for entry in &self.entries {
+            let mut messages = Vec::new();
+            if let Some(system_prompt) = entry.system.as_deref().or(system_prompt) {
+                messages.push(serde_json::json!({"role": "system", "content": system_prompt}));
+            }
+
+            // Content of extra roles waiting to be folded into the next assistant turn
+            let mut pending: Vec<&str> = Vec::new();
+            let flush = |pending: &mut Vec<&str>, messages: &mut Vec<serde_json::Value>| {
+                if !pending.is_empty() {
+                    messages.push(
+                        serde_json::json!({"role": "assistant", "content": pending.join("\n\n")}),
+                    );
+                    pending.clear();
+                }
+            };
+
+            for message in &entry.conversations {
+                let role = message.role.as_str();
+                if !OPENAI_ROLES.contains(&role) {
+                    if extra_roles == ExtraRoles::FoldIntoAssistant {
+                        pending.push(&message.content);
+                    }
+                    continue;
+                }
+                if role == "assistant" && !pending.is_empty() {
+                    pending.push(&message.content);
+                    flush(&mut pending, &mut messages);
+                    continue;
+                }
+                flush(&mut pending, &mut messages);
+                messages.push(serde_json::json!({"role": role, "content": message.content}));
+            }
+            flush(&mut pending, &mut messages);
+
+            content.push_str(&serde_json::to_string(
+                &serde_json::json!({ "messages": messages }),
+            )?);
+            content.push('\n');
+        }
+        fs::write(path, content).await?;
+        Ok(())
+    }
+
+    /// Validates every entry with [`ReasoningEntry::validate`]
+    ///
+    /// # Errors
+    ///
+    /// Returns the index and problems of every invalid entry.
+    pub fn validate_all(&self) -> std::result::Result<(), Vec<(usize, Vec<String>)>> {
+        let errors: Vec<_> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| entry.validate().err().map(|errors| (i, errors)))
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     /// Adds a new entry to the dataset
     pub fn add_entry(&mut self, entry: ReasoningEntry) {
         self.entries.push(entry);
     }
 
+    /// Appends the entries of another dataset
+    ///
+    /// When `dedupe_by_user` is true, entries whose `user` field already exists
+    /// (in this dataset or earlier in `other`) are skipped.
+    ///
+    /// # Returns
+    ///
+    /// The number of entries added
+    pub fn merge(&mut self, other: ReasoningDataset, dedupe_by_user: bool) -> usize {
+        let mut seen: HashSet<String> = if dedupe_by_user {
+            self.entries
+                .iter()
+                .map(|entry| entry.user.clone())
+                .collect()
+        } else {
+            HashSet::new()
+        };
+
+        let before = self.len();
+        for entry in other.entries {
+            if dedupe_by_user && !seen.insert(entry.user.clone()) {
+                continue;
+            }
+            self.add_entry(entry);
+        }
+        self.len() - before
+    }
+
+    /// Returns a new dataset with the entries matching `predicate`
+    ///
+    /// # Example
+    /// ```
+    /// use dset::reasoning::ReasoningDataset;
+    ///
+    /// let dataset = ReasoningDataset::new();
+    /// let detailed = dataset.filter(|entry| entry.reasoning.len() >= 200);
+    /// assert!(detailed.is_empty());
+    /// ```
+    #[must_use]
+    pub fn filter(&self, predicate: impl Fn(&ReasoningEntry) -> bool) -> ReasoningDataset {
+        ReasoningDataset {
+            entries: self
+                .entries
+                .iter()
+                .filter(|entry| predicate(entry))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Keeps only the entries matching `predicate`, in place
+    pub fn retain(&mut self, predicate: impl FnMut(&ReasoningEntry) -> bool) {
+        self.entries.retain(predicate);
+    }
+
+    /// Returns a new dataset with `f` applied to every entry
+    #[must_use]
+    pub fn map(&self, f: impl Fn(&ReasoningEntry) -> ReasoningEntry) -> ReasoningDataset {
+        ReasoningDataset {
+            entries: self.entries.iter().map(f).collect(),
+        }
+    }
+
+    /// Splits the dataset into reproducible train and test sets
+    ///
+    /// Entry indices are shuffled with a generator seeded by `seed`, and the
+    /// first `test_fraction` of them (rounded to the nearest entry) form the test
+    /// set. `test_fraction` is clamped to `[0.0, 1.0]`. Both sets keep the
+    /// original relative order of their entries.
+    ///
+    /// # Returns
+    ///
+    /// A `(train, test)` tuple
+    ///
+    /// # Example
+    /// ```
+    /// use dset::reasoning::ReasoningDataset;
+    ///
+    /// let dataset = ReasoningDataset::new();
+    /// let (train, test) = dataset.split(0.1, 42);
+    /// assert!(train.is_empty() && test.is_empty());
+    /// ```
+    #[must_use]
+    pub fn split(&self, test_fraction: f64, seed: u64) -> (ReasoningDataset, ReasoningDataset) {
+        let test_fraction = if test_fraction.is_nan() {
+            0.0
+        } else {
+            test_fraction.clamp(0.0, 1.0)
+        };
+
+        // Fisher-Yates shuffle
+        let mut indices: Vec<usize> = (0..self.len()).collect();
+        let mut state = seed;
+        for i in (1..indices.len()).rev() {
+            #[allow(clippy::cast_possible_truncation)]
+            let j = (splitmix64(&mut state) % (i as u64 + 1)) as usize;
+            indices.swap(i, j);
+        }
+
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_precision_loss,
+            clippy::cast_sign_loss
+        )]
+        let test_len = (self.len() as f64 * test_fraction).round() as usize;
+        let (test_indices, train_indices) = indices.split_at(test_len);
+
+        let collect = |indices: &[usize]| {
+            let mut indices = indices.to_vec();
+            indices.sort_unstable();
+            ReasoningDataset {
+                entries: indices
+                    .into_iter()
+                    .map(|i| self.entries[i].clone())
+                    .collect(),
+            }
+        };
+        (collect(train_indices), collect(test_indices))
+    }
+
     /// Returns the number of entries in the dataset
     #[must_use]
     pub fn len(&self) -> usize {
@@ -89,9 +641,90 @@ impl ReasoningDataset {
     /// Creates a template string from user, reasoning, and assistant content
     #[must_use]
     pub fn create_template(user: &str, reasoning: &str, assistant: &str) -> String {
-        format!(
-            "<|im_start|>user\n{user}<|im_end|>\n<|im_start|>reasoning\n{reasoning}<|im_end|>\n<|im_start|>assistant\n{assistant}<|im_end|>",
-        )
+        Self::create_template_with(&TemplateConfig::default(), &[user, reasoning, assistant])
+    }
+
+    /// Creates a template string with an optional leading `system` section
+    ///
+    /// Without a system prompt this is the same as [`ReasoningDataset::create_template`].
+    #[must_use]
+    pub fn create_template_with_system(
+        system: Option<&str>,
+        user: &str,
+        reasoning: &str,
+        assistant: &str,
+    ) -> String {
+        match system {
+            Some(system) => {
+                let mut config = TemplateConfig::default();
+                config.roles.insert(0, "system".to_string());
+                Self::create_template_with(&config, &[system, user, reasoning, assistant])
+            }
+            None => Self::create_template(user, reasoning, assistant),
+        }
+    }
+
+    /// Creates a template string using custom delimiters and role names
+    ///
+    /// Sections are matched with `config.roles` by position; sections without a
+    /// matching role are ignored.
+    ///
+    /// # Example
+    /// ```
+    /// use dset::reasoning::{ReasoningDataset, TemplateConfig};
+    ///
+    /// let config = TemplateConfig {
+    ///     start_token: "[".to_string(),
+    ///     end_token: "[/]".to_string(),
+    ///     roles: vec!["INST".to_string(), "RESP".to_string()],
+    /// };
+    /// let template = ReasoningDataset::create_template_with(&config, &["Hi", "Hello!"]);
+    /// assert_eq!(template, "[INST\nHi[/]\n[RESP\nHello![/]");
+    /// ```
+    #[must_use]
+    pub fn create_template_with(config: &TemplateConfig, sections: &[&str]) -> String {
+        let messages: Vec<Message> = config
+            .roles
+            .iter()
+            .zip(sections)
+            .map(|(role, content)| Message {
+                content: (*content).to_string(),
+                role: role.clone(),
+            })
+            .collect();
+        Self::template_from_conversations(&messages, config)
+    }
+
+    /// Creates a template string from a conversation with any number of turns
+    ///
+    /// Each message is rendered with its own role and the tokens from `config`;
+    /// `config.roles` isn't used.
+    ///
+    /// # Example
+    /// ```
+    /// use dset::reasoning::{Message, ReasoningDataset, TemplateConfig};
+    ///
+    /// let messages = [
+    ///     Message { content: "Hi".to_string(), role: "user".to_string() },
+    ///     Message { content: "Hello!".to_string(), role: "assistant".to_string() },
+    ///     Message { content: "How are you?".to_string(), role: "user".to_string() },
+    /// ];
+    /// let template =
+    ///     ReasoningDataset::template_from_conversations(&messages, &TemplateConfig::default());
+    /// assert!(template.ends_with("<|im_start|>user\nHow are you?<|im_end|>"));
+    /// ```
+    #[must_use]
+    pub fn template_from_conversations(messages: &[Message], config: &TemplateConfig) -> String {
+        messages
+            .iter()
+            .map(|message| {
+                format!(
+                    "{}{}\n{}{}",
+                    config.start_token, message.role, message.content, config.end_token
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
     }
 }
 
@@ -106,6 +739,31 @@ mod tests {
     use super::*;
     use tempfile::NamedTempFile;
 
+    /// Creates an entry with a matching template and conversation
+    fn sample_entry(user: &str, reasoning: &str, assistant: &str) -> ReasoningEntry {
+        ReasoningEntry {
+            user: user.to_string(),
+            reasoning: reasoning.to_string(),
+            assistant: assistant.to_string(),
+            template: ReasoningDataset::create_template(user, reasoning, assistant),
+            conversations: vec![
+                Message {
+                    content: user.to_string(),
+                    role: "user".to_string(),
+                },
+                Message {
+                    content: reasoning.to_string(),
+                    role: "reasoning".to_string(),
+                },
+                Message {
+                    content: assistant.to_string(),
+                    role: "assistant".to_string(),
+                },
+            ],
+            system: None,
+        }
+    }
+
     #[tokio::test]
     async fn test_dataset_operations() -> Result<()> {
         // Create a new dataset
@@ -135,6 +793,7 @@ mod tests {
                     role: "assistant".to_string(),
                 },
             ],
+            system: None,
         };
 
         // Add entry to dataset
@@ -149,4 +808,391 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_jsonl_round_trip() -> Result<()> {
+        let mut dataset = ReasoningDataset::new();
+        dataset.add_entry(sample_entry("Q1", "R1", "A1"));
+        dataset.add_entry(sample_entry("Q2", "R2", "A2"));
+        dataset.add_entry(sample_entry("Q3", "R3\nwith a newline", "A3"));
+
+        let temp_file = NamedTempFile::new()?;
+        dataset.save_jsonl(temp_file.path()).await?;
+
+        let content = fs::read_to_string(temp_file.path()).await?;
+        assert_eq!(content.lines().count(), 3);
+        assert!(content.ends_with('\n'));
+
+        // Blank lines are skipped on load
+        fs::write(temp_file.path(), format!("\n{content}\n\n")).await?;
+        let loaded = ReasoningDataset::load_jsonl(temp_file.path()).await?;
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded.entries[2].reasoning, "R3\nwith a newline");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_jsonl() -> Result<()> {
+        let mut dataset = ReasoningDataset::new();
+        dataset.add_entry(sample_entry("Q1", "R1", "A1"));
+        dataset.add_entry(sample_entry("Q2", "R2", "A2"));
+
+        let temp_file = NamedTempFile::new()?;
+        dataset.save_jsonl(temp_file.path()).await?;
+        let content = fs::read_to_string(temp_file.path()).await?;
+        let mut lines = content.lines();
+        let first = lines.next().unwrap();
+        let second = lines.next().unwrap();
+        fs::write(
+            temp_file.path(),
+            format!("{first}\n\n{{not json}}\n{second}\n"),
+        )
+        .await?;
+
+        let mut stream = ReasoningDataset::stream_jsonl(temp_file.path()).await?;
+        assert_eq!(stream.next_entry().await.unwrap()?.user, "Q1");
+        let err = stream.next_entry().await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("line 3"));
+        assert_eq!(stream.next_entry().await.unwrap()?.user, "Q2");
+        assert!(stream.next_entry().await.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_template_with() {
+        // The default config reproduces the ChatML template
+        assert_eq!(
+            ReasoningDataset::create_template("Q", "R", "A"),
+            "<|im_start|>user\nQ<|im_end|>\n<|im_start|>reasoning\nR<|im_end|>\n<|im_start|>assistant\nA<|im_end|>"
+        );
+
+        let config = TemplateConfig {
+            start_token: "<start_of_turn>".to_string(),
+            end_token: "<end_of_turn>".to_string(),
+            roles: vec!["user".to_string(), "model".to_string()],
+        };
+        assert_eq!(
+            ReasoningDataset::create_template_with(&config, &["Q", "A", "ignored"]),
+            "<start_of_turn>user\nQ<end_of_turn>\n<start_of_turn>model\nA<end_of_turn>"
+        );
+    }
+
+    #[test]
+    fn test_template_from_conversations() {
+        let entry = sample_entry("Q", "R", "A");
+        assert_eq!(
+            ReasoningDataset::template_from_conversations(
+                &entry.conversations,
+                &TemplateConfig::default()
+            ),
+            entry.template
+        );
+
+        let turn = |role: &str, content: &str| Message {
+            content: content.to_string(),
+            role: role.to_string(),
+        };
+        let config = TemplateConfig {
+            start_token: "<".to_string(),
+            end_token: ">".to_string(),
+            roles: Vec::new(),
+        };
+        let messages = [
+            turn("user", "Q1"),
+            turn("assistant", "A1"),
+            turn("user", "Q2"),
+            turn("assistant", "A2"),
+        ];
+        assert_eq!(
+            ReasoningDataset::template_from_conversations(&messages, &config),
+            "<user\nQ1>\n<assistant\nA1>\n<user\nQ2>\n<assistant\nA2>"
+        );
+        assert_eq!(
+            ReasoningDataset::template_from_conversations(&[], &config),
+            ""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_to_sharegpt_json() -> Result<()> {
+        let mut dataset = ReasoningDataset::new();
+        dataset.add_entry(sample_entry("Q1", "R1", "A1"));
+        dataset.add_entry(sample_entry("Q2", "R2", "A2"));
+
+        let temp_file = NamedTempFile::new()?;
+        dataset.to_sharegpt_json(temp_file.path(), &[]).await?;
+        let exported: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(temp_file.path()).await?)?;
+        assert_eq!(
+            exported[1],
+            serde_json::json!({
+                "conversations": [
+                    {"from": "human", "value": "Q2"},
+                    {"from": "gpt", "value": "R2"},
+                    {"from": "gpt", "value": "A2"}
+                ]
+            })
+        );
+
+        // Custom mappings take precedence over the defaults
+        dataset
+            .to_sharegpt_json(temp_file.path(), &[("reasoning", "thought")])
+            .await?;
+        let exported: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(temp_file.path()).await?)?;
+        assert_eq!(exported.as_array().map(Vec::len), Some(2));
+        assert_eq!(exported[0]["conversations"][1]["from"], "thought");
+        assert_eq!(exported[0]["conversations"][2]["from"], "gpt");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_to_openai_jsonl() -> Result<()> {
+        let mut dataset = ReasoningDataset::new();
+        dataset.add_entry(sample_entry("Q1", "R1", "A1"));
+        dataset.add_entry(sample_entry("Q2", "R2", "A2"));
+
+        let temp_file = NamedTempFile::new()?;
+        dataset
+            .to_openai_jsonl(
+                temp_file.path(),
+                Some("Be brief."),
+                ExtraRoles::FoldIntoAssistant,
+            )
+            .await?;
+        let content = fs::read_to_string(temp_file.path()).await?;
+        assert_eq!(content.lines().count(), dataset.len());
+        let first: serde_json::Value = serde_json::from_str(content.lines().next().unwrap())?;
+        assert_eq!(
+            first,
+            serde_json::json!({
+                "messages": [
+                    {"role": "system", "content": "Be brief."},
+                    {"role": "user", "content": "Q1"},
+                    {"role": "assistant", "content": "R1\n\nA1"}
+                ]
+            })
+        );
+
+        dataset
+            .to_openai_jsonl(temp_file.path(), None, ExtraRoles::Drop)
+            .await?;
+        let content = fs::read_to_string(temp_file.path()).await?;
+        assert_eq!(content.lines().count(), dataset.len());
+        let second: serde_json::Value = serde_json::from_str(content.lines().nth(1).unwrap())?;
+        assert_eq!(
+            second,
+            serde_json::json!({
+                "messages": [
+                    {"role": "user", "content": "Q2"},
+                    {"role": "assistant", "content": "A2"}
+                ]
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_retain_map() {
+        let mut dataset = ReasoningDataset::new();
+        dataset.add_entry(sample_entry("Q1", "Short", "A1"));
+        dataset.add_entry(sample_entry("Q2", "A much longer reasoning", "A2"));
+        dataset.add_entry(sample_entry("Q3", "Also quite long", "answer 3"));
+
+        let long = dataset.filter(|entry| entry.reasoning.len() > 5);
+        assert_eq!(long.len(), 2);
+        assert_eq!(long.entries[0].user, "Q2");
+        assert_eq!(dataset.len(), 3);
+
+        let mapped = dataset.map(|entry| ReasoningEntry {
+            assistant: entry.assistant.to_uppercase(),
+            ..entry.clone()
+        });
+        assert_eq!(mapped.entries[2].assistant, "ANSWER 3");
+        assert_eq!(mapped.entries[0].user, "Q1");
+        // The original dataset is left untouched
+        assert_eq!(dataset.entries[2].assistant, "answer 3");
+
+        dataset.retain(|entry| entry.user != "Q2");
+        assert_eq!(dataset.len(), 2);
+        assert_eq!(dataset.entries[1].user, "Q3");
+    }
+
+    #[test]
+    fn test_split() {
+        let mut dataset = ReasoningDataset::new();
+        for i in 0..20 {
+            dataset.add_entry(sample_entry(&format!("Q{i}"), "R", "A"));
+        }
+        let users = |dataset: &ReasoningDataset| -> Vec<String> {
+            dataset.entries.iter().map(|e| e.user.clone()).collect()
+        };
+
+        let (train, test) = dataset.split(0.25, 7);
+        assert_eq!((train.len(), test.len()), (15, 5));
+        let mut all = users(&train);
+        all.extend(users(&test));
+        all.sort();
+        let mut expected = users(&dataset);
+        expected.sort();
+        assert_eq!(all, expected);
+
+        // Same seed, same split
+        let (train_again, test_again) = dataset.split(0.25, 7);
+        assert_eq!(users(&train), users(&train_again));
+        assert_eq!(users(&test), users(&test_again));
+
+        // Out-of-range fractions are clamped
+        assert_eq!(dataset.split(1.5, 7).1.len(), 20);
+        assert_eq!(dataset.split(-1.0, 7).0.len(), 20);
+
+        let (train, test) = ReasoningDataset::new().split(0.5, 7);
+        assert!(train.is_empty() && test.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_system_prompt() -> Result<()> {
+        // Entries written before the field existed still deserialize
+        let legacy = serde_json::to_value(sample_entry("Q", "R", "A"))?;
+        assert!(legacy.get("system").is_none());
+        let entry: ReasoningEntry = serde_json::from_value(legacy)?;
+        assert_eq!(entry.system, None);
+
+        assert_eq!(
+            ReasoningDataset::create_template_with_system(None, "Q", "R", "A"),
+            ReasoningDataset::create_template("Q", "R", "A")
+        );
+        let template =
+            ReasoningDataset::create_template_with_system(Some("Be brief."), "Q", "R", "A");
+        assert_eq!(
+            template,
+            format!(
+                "<|im_start|>system\nBe brief.<|im_end|>\n{}",
+                ReasoningDataset::create_template("Q", "R", "A")
+            )
+        );
+
+        let mut entry = sample_entry("Q", "R", "A");
+        entry.system = Some("Be brief.".to_string());
+        assert!(entry.validate().is_err());
+        entry.template = template;
+        assert!(entry.validate().is_ok());
+
+        // The entry's own system prompt wins over the export-wide one
+        let mut dataset = ReasoningDataset::new();
+        dataset.add_entry(entry);
+        let temp_file = NamedTempFile::new()?;
+        dataset
+            .to_openai_jsonl(temp_file.path(), Some("Default."), ExtraRoles::Drop)
+            .await?;
+        let exported: serde_json::Value =
+            serde_json::from_str(fs::read_to_string(temp_file.path()).await?.trim())?;
+        assert_eq!(
+            exported["messages"][0],
+            serde_json::json!({"role": "system", "content": "Be brief."})
+        );
+
+        dataset.to_sharegpt_json(temp_file.path(), &[]).await?;
+        let exported: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(temp_file.path()).await?)?;
+        assert_eq!(
+            exported[0]["conversations"][0],
+            serde_json::json!({"from": "system", "value": "Be brief."})
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut dataset = ReasoningDataset::new();
+        dataset.add_entry(sample_entry("Q1", "R1", "A1"));
+        assert!(dataset.validate_all().is_ok());
+
+        // Edited answer without regenerating the template
+        let mut stale = sample_entry("Q2", "R2", "A2");
+        stale.assistant = "A2 (edited)".to_string();
+        dataset.add_entry(stale);
+
+        let mut broken = sample_entry("", "R3", "A3");
+        broken.conversations.swap(1, 2);
+        dataset.add_entry(broken);
+
+        let errors = dataset.validate_all().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].0, 1);
+        assert_eq!(errors[0].1.len(), 1);
+        assert!(errors[0].1[0].contains("template"));
+        assert_eq!(errors[1].0, 2);
+        assert_eq!(
+            errors[1].1,
+            vec![
+                "`user` is empty".to_string(),
+                "turn 1 has roles [user, assistant, reasoning], expected [user, reasoning, assistant]"
+                    .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_multi_turn() {
+        let mut entry = sample_entry("Q2", "R2", "A2");
+        let mut conversations = sample_entry("Q1", "R1", "A1").conversations;
+        conversations.append(&mut entry.conversations);
+        entry.conversations = conversations;
+        entry.template = ReasoningDataset::template_from_conversations(
+            &entry.conversations,
+            &TemplateConfig::default(),
+        );
+        assert_eq!(entry.validate(), Ok(()));
+
+        // A leading system message is allowed
+        entry.conversations.insert(
+            0,
+            Message {
+                content: "Be brief.".to_string(),
+                role: "system".to_string(),
+            },
+        );
+        entry.template = ReasoningDataset::template_from_conversations(
+            &entry.conversations,
+            &TemplateConfig::default(),
+        );
+        assert_eq!(entry.validate(), Ok(()));
+
+        // Each turn is checked on its own
+        entry.conversations.pop();
+        assert_eq!(
+            entry.validate(),
+            Err(vec![
+                "turn 2 has roles [user, reasoning], expected [user, reasoning, assistant]"
+                    .to_string(),
+                "`template` doesn't match the system, user, reasoning and assistant fields"
+                    .to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut dataset = ReasoningDataset::new();
+        dataset.add_entry(sample_entry("Q1", "R1", "A1"));
+
+        let mut other = ReasoningDataset::new();
+        other.add_entry(sample_entry("Q1", "R1 again", "A1 again"));
+        other.add_entry(sample_entry("Q2", "R2", "A2"));
+        other.add_entry(sample_entry("Q2", "R2 again", "A2 again"));
+
+        let mut deduped = dataset.clone();
+        assert_eq!(deduped.merge(other.clone(), true), 1);
+        assert_eq!(deduped.len(), 2);
+        assert_eq!(deduped.entries[1].reasoning, "R2");
+
+        assert_eq!(dataset.merge(other, false), 3);
+        assert_eq!(dataset.len(), 4);
+    }
 }
@@ -3,79 +3,807 @@
 use anyhow::Context;
 use memmap2::Mmap;
 use safetensors::SafeTensors;
+use safetensors::tensor::Metadata;
+use serde::Serialize;
 use serde_json::Value;
-use std::{fs::File, path::Path};
-use tokio::task;
+use std::future::Future;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    hash::BuildHasher,
+    path::{Path, PathBuf},
+};
+use tokio::io::AsyncReadExt;
+
+/// Maximum accepted size of a safetensors JSON header, matching the `safetensors` crate
+const MAX_HEADER_SIZE: usize = 100_000_000;
+
+/// A source that can provide the JSON header of a safetensors file.
+///
+/// Implementations exist for local files (`Path`, `PathBuf`), in-memory bytes (`[u8]`,
+/// `Vec<u8>`) and, with the `url` feature enabled, remote files via [`UrlSource`].
+/// Functions such as [`extract_metadata_from`] accept any source so downstream tools
+/// don't have to branch on where a model lives.
+pub trait MetadataSource {
+    /// Reads the raw JSON header bytes, without the 8-byte little-endian length prefix.
+    ///
+    /// # Errors
+    /// Returns an error if the source cannot be read or does not contain a valid
+    /// safetensors header length prefix.
+    fn read_header(&self) -> impl Future<Output = anyhow::Result<Vec<u8>>> + Send;
+}
+
+/// Decodes the 8-byte little-endian header length prefix of a safetensors file
+fn header_len(prefix: [u8; 8]) -> anyhow::Result<usize> {
+    let len = usize::try_from(u64::from_le_bytes(prefix)).context("Header length too large")?;
+    if len > MAX_HEADER_SIZE {
+        anyhow::bail!("Header length {len} exceeds the maximum of {MAX_HEADER_SIZE} bytes");
+    }
+    Ok(len)
+}
+
+impl MetadataSource for Path {
+    async fn read_header(&self) -> anyhow::Result<Vec<u8>> {
+        let mut file = tokio::fs::File::open(self)
+            .await
+            .with_context(|| format!("Failed to open file: {}", self.display()))?;
+
+        let mut prefix = [0u8; 8];
+        file.read_exact(&mut prefix)
+            .await
+            .with_context(|| format!("Failed to read header length from: {}", self.display()))?;
+
+        let mut header = vec![0u8; header_len(prefix)?];
+        file.read_exact(&mut header)
+            .await
+            .with_context(|| format!("Failed to read header from: {}", self.display()))?;
+        Ok(header)
+    }
+}
+
+impl MetadataSource for PathBuf {
+    async fn read_header(&self) -> anyhow::Result<Vec<u8>> {
+        self.as_path().read_header().await
+    }
+}
+
+impl MetadataSource for [u8] {
+    async fn read_header(&self) -> anyhow::Result<Vec<u8>> {
+        let prefix: [u8; 8] = self
+            .get(..8)
+            .and_then(|bytes| bytes.try_into().ok())
+            .context("Buffer too small to contain a safetensors header")?;
+        let end = 8 + header_len(prefix)?;
+        let header = self
+            .get(8..end)
+            .context("Buffer ends before the end of the safetensors header")?;
+        Ok(header.to_vec())
+    }
+}
+
+impl MetadataSource for Vec<u8> {
+    async fn read_header(&self) -> anyhow::Result<Vec<u8>> {
+        self.as_slice().read_header().await
+    }
+}
+
+/// A remote safetensors file fetched over HTTP.
+///
+/// Only the header is downloaded, using HTTP range requests, so inspecting a
+/// multi-GB checkpoint costs a few kilobytes of traffic.
+#[cfg(feature = "url")]
+#[derive(Debug, Clone)]
+pub struct UrlSource {
+    url: String,
+    client: reqwest::Client,
+}
+
+#[cfg(feature = "url")]
+impl UrlSource {
+    /// Creates a source for the safetensors file at `url`
+    #[must_use]
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Fetches `len` bytes starting at `start`
+    async fn fetch_range(&self, start: usize, len: usize) -> anyhow::Result<Vec<u8>> {
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let end = start + len - 1;
+        let bytes = self
+            .client
+            .get(&self.url)
+            .header(reqwest::header::RANGE, format!("bytes={start}-{end}"))
+            .send()
+            .await
+            .with_context(|| format!("Failed to request {}", self.url))?
+            .error_for_status()
+            .with_context(|| format!("Failed to fetch {}", self.url))?
+            .bytes()
+            .await
+            .with_context(|| format!("Failed to read response from {}", self.url))?;
+
+        // Servers that ignore the range header return the whole file
+        let bytes = if bytes.len() > len {
+            bytes.get(start..=end).unwrap_or_default()
+        } else {
+            &bytes[..]
+        };
+        if bytes.len() != len {
+            anyhow::bail!("Unexpected end of data while reading {}", self.url);
+        }
+        Ok(bytes.to_vec())
+    }
+}
+
+#[cfg(feature = "url")]
+impl MetadataSource for UrlSource {
+    async fn read_header(&self) -> anyhow::Result<Vec<u8>> {
+        let prefix: [u8; 8] = self
+            .fetch_range(0, 8)
+            .await?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Invalid header length prefix from {}", self.url))?;
+        self.fetch_range(8, header_len(prefix)?).await
+    }
+}
+
+/// Reads the safetensors header from any [`MetadataSource`] and parses it as JSON.
+///
+/// The returned value contains the `__metadata__` entry (if any) and one entry per
+/// tensor, the same shape as returned by [`inspect_state_dict`].
+///
+/// # Errors
+/// Returns an error if:
+/// - The header cannot be read from the source
+/// - The header is not valid JSON
+pub async fn read_header_json<S: MetadataSource + ?Sized>(source: &S) -> anyhow::Result<Value> {
+    let header = source.read_header().await?;
+    serde_json::from_slice(&header).context("Failed to parse safetensors header as JSON")
+}
+
+/// Extracts and decodes the embedded training metadata from any [`MetadataSource`].
+///
+/// # Errors
+/// Returns an error if:
+/// - The header cannot be read from the source
+/// - The header is not valid JSON
+pub async fn extract_metadata_from<S: MetadataSource + ?Sized>(
+    source: &S,
+) -> anyhow::Result<Value> {
+    let metadata_json = read_header_json(source).await?;
+
+    // Debug print the raw metadata
+    log::info!("Raw metadata: {metadata_json}");
+
+    Ok(decode_header_metadata(&metadata_json))
+}
+
+/// Decodes the training metadata embedded in a parsed safetensors header
+fn decode_header_metadata(metadata_json: &Value) -> Value {
+    // Extract metadata from the __metadata__ field if it exists
+    let metadata_to_process = if let Some(meta) = metadata_json.get("__metadata__") {
+        if let Some(meta_str) = meta.get("metadata") {
+            if let Some(s) = meta_str.as_str() {
+                serde_json::from_str(s).unwrap_or(Value::Object(serde_json::Map::new()))
+            } else {
+                Value::Object(serde_json::Map::new())
+            }
+        } else {
+            Value::Object(serde_json::Map::new())
+        }
+    } else {
+        Value::Object(serde_json::Map::new())
+    };
+
+    // Process the metadata
+    crate::metadata::extract_training_metadata(&metadata_to_process)
+}
+
+/// Reads a single value from the training metadata of a safetensors file.
+///
+/// `key` is first looked up as-is, then as a dotted path into nested objects
+/// (and arrays, by index), such as `ss_bucket_info.mean_img_ar_error`.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or its header is invalid.
+pub fn get_metadata_value(path: &Path, key: &str) -> anyhow::Result<Option<Value>> {
+    let header = serde_json::to_value(read_tensor_metadata(path)?)
+        .context("Failed to convert state dictionary to JSON value")?;
+    let metadata = decode_header_metadata(&header);
+
+    if let Some(value) = metadata.get(key) {
+        return Ok(Some(value.clone()));
+    }
+    Ok(key
+        .split('.')
+        .try_fold(&metadata, |value, segment| match value {
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => value.get(segment),
+        })
+        .cloned())
+}
+
+/// Extracts and decodes the embedded training metadata of a safetensors file.
+///
+/// Only the header is read from disk; tensor data is never loaded.
+///
+/// # Errors
+/// Returns an error if:
+/// - Failed to open the file
+/// - Failed to read the safetensors header
+pub async fn extract_metadata(path: &Path) -> anyhow::Result<Value> {
+    extract_metadata_from(path)
+        .await
+        .with_context(|| format!("Failed to read metadata from file: {}", path.display()))
+}
 
 /// Process a safetensors file and extract its embedded metadata to a JSON file
 ///
+/// The metadata is written next to the model, replacing its extension with
+/// `metadata.json`. Only the header is read from disk; tensor data is never loaded.
+///
 /// # Errors
 /// Returns an error if:
 /// - Failed to open the file
-/// - Failed to memory map the file
 /// - Failed to read the safetensors header
 /// - Failed to write the metadata JSON file
 pub async fn process_file(path: &Path) -> anyhow::Result<()> {
+    process_file_to(path, &path.with_extension("metadata.json")).await
+}
+
+/// Processes every `.safetensors` file in a directory with [`process_file`]
+///
+/// Files that fail to process are logged and skipped rather than aborting the
+/// batch.
+///
+/// # Arguments
+/// * `dir` - Directory containing the models
+/// * `recursive` - Whether to also process files in subdirectories
+///
+/// # Returns
+/// The number of files whose metadata was written
+///
+/// # Errors
+/// Returns an error if the directory cannot be walked
+pub async fn process_directory(dir: &Path, recursive: bool) -> anyhow::Result<usize> {
+    let summary = crate::process_directory(dir, &["safetensors"], recursive, |path| async move {
+        process_file(&path).await
+    })
+    .await?;
+    Ok(summary.processed)
+}
+
+/// Combines the metadata and tensor lists of a sharded safetensors checkpoint.
+///
+/// `index_path` points to a Hugging Face style `model.safetensors.index.json`,
+/// whose `weight_map` maps tensor names to shard files relative to the index.
+/// The returned value has the form:
+///
+/// ```json
+/// {
+///   "index_metadata": { "total_size": 123 },
+///   "metadata": { "format": "pt" },
+///   "shards": ["model-00001-of-00002.safetensors", "model-00002-of-00002.safetensors"],
+///   "tensors": { "lm_head.weight": { "dtype": "F16", "shape": [..], "data_offsets": [..], "shard": "..." } }
+/// }
+/// ```
+///
+/// Metadata entries shared by several shards appear once; if shards disagree on a
+/// key, the value from the first shard (in file name order) is kept.
+///
+/// # Errors
+/// Returns an error if:
+/// - The index cannot be read or has no `weight_map` object
+/// - A referenced shard file is missing
+/// - A shard header cannot be read
+pub async fn process_sharded(index_path: &Path) -> anyhow::Result<Value> {
+    let index: Value = serde_json::from_str(
+        &tokio::fs::read_to_string(index_path)
+            .await
+            .with_context(|| format!("Failed to read index: {}", index_path.display()))?,
+    )
+    .with_context(|| format!("Failed to parse index: {}", index_path.display()))?;
+    let weight_map = index
+        .get("weight_map")
+        .and_then(Value::as_object)
+        .with_context(|| format!("Index {} has no weight_map object", index_path.display()))?;
+
+    let mut shards: Vec<&str> = weight_map.values().filter_map(Value::as_str).collect();
+    shards.sort_unstable();
+    shards.dedup();
+
+    let base_dir = index_path.parent().unwrap_or_else(|| Path::new(""));
+    for shard in &shards {
+        let shard_path = base_dir.join(shard);
+        if !shard_path.exists() {
+            anyhow::bail!(
+                "Shard {} referenced by {} is missing",
+                shard_path.display(),
+                index_path.display()
+            );
+        }
+    }
+
+    let mut metadata = serde_json::Map::new();
+    let mut tensors = serde_json::Map::new();
+    for shard in &shards {
+        let shard_path = base_dir.join(shard);
+        let header = read_header_json(&shard_path)
+            .await
+            .with_context(|| format!("Failed to read shard header: {}", shard_path.display()))?;
+
+        if let Value::Object(shard_metadata) = decode_header_metadata(&header) {
+            for (key, value) in shard_metadata {
+                match metadata.get(&key) {
+                    Some(existing) if *existing != value => log::warn!(
+                        "Shard {shard} disagrees on metadata key {key}, keeping the first value"
+                    ),
+                    Some(_) => {}
+                    None => {
+                        metadata.insert(key, value);
+                    }
+                }
+            }
+        }
+
+        if let Value::Object(entries) = header {
+            for (name, mut info) in entries {
+                if name == "__metadata__" {
+                    continue;
+                }
+                if let Value::Object(info) = &mut info {
+                    info.insert("shard".to_string(), Value::String((*shard).to_string()));
+                }
+                tensors.insert(name, info);
+            }
+        }
+    }
+
+    for name in weight_map.keys() {
+        if !tensors.contains_key(name) {
+            log::warn!("Tensor {name} from the weight map was not found in its shard");
+        }
+    }
+
+    Ok(serde_json::json!({
+        "index_metadata": index.get("metadata").cloned().unwrap_or(Value::Null),
+        "metadata": metadata,
+        "shards": shards,
+        "tensors": tensors,
+    }))
+}
+
+/// Process a safetensors file and write its embedded metadata to `output` as pretty JSON
+///
+/// Missing parent directories of `output` are created, so metadata can be mirrored
+/// from a read-only model directory into a separate one.
+///
+/// # Errors
+/// Returns an error if:
+/// - Failed to open the file
+/// - Failed to read the safetensors header
+/// - Failed to create the output directory or write the metadata JSON file
+pub async fn process_file_to(path: &Path, output: &Path) -> anyhow::Result<()> {
     log::info!("Processing file: {}", path.display());
 
-    // Spawn blocking file operations in a separate thread
-    let path = path.to_path_buf();
-    task::spawn_blocking(move || -> anyhow::Result<()> {
-        let file = File::open(&path)
-            .with_context(|| format!("Failed to open file: {}", path.display()))?;
-
-        // Safety: The file is opened read-only and won't be modified while mapped
-        let mmap = unsafe { Mmap::map(&file) }
-            .with_context(|| format!("Failed to memory map file: {}", path.display()))?;
-
-        let (_header_size, metadata) = SafeTensors::read_metadata(&mmap)
-            .with_context(|| format!("Failed to read metadata from file: {}", path.display()))?;
-
-        // Debug print the raw metadata
-        log::info!("Raw metadata: {:?}", metadata);
-
-        // Convert the raw metadata to a JSON value
-        let metadata_json: Value =
-            serde_json::to_value(&metadata).context("Failed to convert metadata to JSON value")?;
-
-        // Extract metadata from the __metadata__ field if it exists
-        let metadata_to_process = if let Some(meta) = metadata_json.get("__metadata__") {
-            if let Some(meta_str) = meta.get("metadata") {
-                if let Some(s) = meta_str.as_str() {
-                    serde_json::from_str(s).unwrap_or(Value::Object(serde_json::Map::new()))
-                } else {
-                    Value::Object(serde_json::Map::new())
+    let processed_metadata = extract_metadata(path).await?;
+
+    // Write metadata to JSON file
+    let json_path = output;
+    if let Some(parent) = json_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+    tokio::fs::write(
+        &json_path,
+        serde_json::to_string_pretty(&processed_metadata)
+            .context("Failed to serialize metadata to JSON")?,
+    )
+    .await
+    .with_context(|| format!("Failed to write metadata to {}", json_path.display()))?;
+
+    if processed_metadata
+        .as_object()
+        .is_none_or(serde_json::Map::is_empty)
+    {
+        log::info!("No training metadata found in {}", path.display());
+    } else {
+        log::info!("Wrote metadata to {}", json_path.display());
+    }
+    Ok(())
+}
+
+/// A value that differs between two sets of metadata
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValueChange {
+    /// Value in the first file
+    pub old: Value,
+    /// Value in the second file
+    pub new: Value,
+}
+
+/// Differences between the training metadata of two safetensors files.
+///
+/// Nested objects are compared key by key, and their keys are reported as
+/// dotted paths such as `ss_network_args.network_dim`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MetadataDiff {
+    /// Keys only present in the second file
+    pub added: BTreeMap<String, Value>,
+    /// Keys only present in the first file
+    pub removed: BTreeMap<String, Value>,
+    /// Keys present in both files with different values
+    pub changed: BTreeMap<String, ValueChange>,
+}
+
+impl MetadataDiff {
+    /// Returns true if both files have identical metadata
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// Records the differences between `old` and `new` under the key `path`
+    fn compare(&mut self, path: String, old: &Value, new: &Value) {
+        match (old, new) {
+            (Value::Object(old_map), Value::Object(new_map)) => {
+                let join = |key: &str| {
+                    if path.is_empty() {
+                        key.to_string()
+                    } else {
+                        format!("{path}.{key}")
+                    }
+                };
+                for (key, old_value) in old_map {
+                    match new_map.get(key) {
+                        Some(new_value) => self.compare(join(key), old_value, new_value),
+                        None => {
+                            self.removed.insert(join(key), old_value.clone());
+                        }
+                    }
+                }
+                for (key, new_value) in new_map {
+                    if !old_map.contains_key(key) {
+                        self.added.insert(join(key), new_value.clone());
+                    }
                 }
-            } else {
-                Value::Object(serde_json::Map::new())
             }
-        } else {
-            Value::Object(serde_json::Map::new())
-        };
+            _ if old != new => {
+                self.changed.insert(
+                    path,
+                    ValueChange {
+                        old: old.clone(),
+                        new: new.clone(),
+                    },
+                );
+            }
+            _ => {}
+        }
+    }
+}
 
-        // Process the metadata
-        let processed_metadata = crate::metadata::extract_training_metadata(&metadata_to_process);
+/// Compares the decoded training metadata of two safetensors files.
+///
+/// Both files go through the same decoding as [`extract_metadata`], so
+/// JSON-encoded `ss_*` fields are compared structurally rather than as strings.
+///
+/// # Errors
+///
+/// Returns an error if the metadata of either file cannot be read.
+pub async fn diff_metadata(a: &Path, b: &Path) -> anyhow::Result<MetadataDiff> {
+    let old = extract_metadata(a).await?;
+    let new = extract_metadata(b).await?;
 
-        // Write metadata to JSON file
-        let json_path = path.with_extension("metadata.json");
-        std::fs::write(
-            &json_path,
-            serde_json::to_string_pretty(&processed_metadata)
-                .context("Failed to serialize metadata to JSON")?,
-        )
-        .with_context(|| format!("Failed to write metadata to {}", json_path.display()))?;
+    let mut diff = MetadataDiff::default();
+    diff.compare(String::new(), &old, &new);
+    Ok(diff)
+}
+
+/// Merges `updates` into the training metadata of a safetensors file.
+///
+/// The updates go into the JSON object stored as `__metadata__.metadata`, where
+/// [`extract_metadata`], [`get_metadata_value`] and [`diff_metadata`] read it; the
+/// object is created if missing. Existing keys are overwritten and other keys are
+/// kept. Tensor entries and
+/// tensor data are copied unchanged, so their `data_offsets` stay valid. The
+/// file is rewritten through a temporary sibling file that replaces the original
+/// once fully written.
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - The file cannot be read or written
+/// - The header is invalid, `__metadata__` is not a JSON object, or its
+///   `metadata` entry is not a string holding a JSON object
+pub async fn update_metadata<S: BuildHasher>(
+    path: &Path,
+    updates: HashMap<String, String, S>,
+) -> anyhow::Result<()> {
+    let bytes = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+    let header = bytes.as_slice().read_header().await?;
+    let data = &bytes[8 + header.len()..];
+
+    let mut header: serde_json::Map<String, Value> =
+        serde_json::from_slice(&header).context("Failed to parse safetensors header as JSON")?;
+    let Value::Object(header_metadata) = header
+        .entry("__metadata__")
+        .or_insert_with(|| Value::Object(serde_json::Map::new()))
+    else {
+        anyhow::bail!("__metadata__ in {} is not a JSON object", path.display());
+    };
+    let mut metadata: serde_json::Map<String, Value> = match header_metadata.get("metadata") {
+        Some(Value::String(metadata)) => serde_json::from_str(metadata).with_context(|| {
+            format!(
+                "__metadata__.metadata in {} is not a JSON object",
+                path.display()
+            )
+        })?,
+        Some(_) => anyhow::bail!(
+            "__metadata__.metadata in {} is not a string",
+            path.display()
+        ),
+        None => serde_json::Map::new(),
+    };
+    for (key, value) in updates {
+        metadata.insert(key, Value::String(value));
+    }
+    header_metadata.insert(
+        "metadata".to_string(),
+        Value::String(serde_json::to_string(&metadata)?),
+    );
+
+    // Pad the header with spaces so tensor data stays 8-byte aligned
+    let mut header =
+        serde_json::to_vec(&header).context("Failed to serialize safetensors header")?;
+    header.resize(header.len().next_multiple_of(8), b' ');
+
+    let mut output = Vec::with_capacity(8 + header.len() + data.len());
+    output.extend_from_slice(&(header.len() as u64).to_le_bytes());
+    output.extend_from_slice(&header);
+    output.extend_from_slice(data);
 
-        if processed_metadata
-            .as_object()
-            .is_none_or(serde_json::Map::is_empty)
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    tokio::fs::write(&tmp_path, &output)
+        .await
+        .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+    tokio::fs::rename(&tmp_path, path)
+        .await
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+/// Checks that the header of a safetensors file is consistent with its size.
+///
+/// Every tensor's `data_offsets` must be well-formed, tensors must not overlap,
+/// and the last tensor must end exactly at the end of the file. This catches
+/// truncated downloads that still have a readable header.
+///
+/// # Errors
+///
+/// Returns an error if the header cannot be read, or describes the first
+/// offending tensor if the offsets are inconsistent.
+pub async fn verify(path: &Path) -> anyhow::Result<()> {
+    let header = path.read_header().await?;
+    let file_len = tokio::fs::metadata(path)
+        .await
+        .with_context(|| format!("Failed to read metadata of {}", path.display()))?
+        .len();
+    let data_len = file_len - 8 - header.len() as u64;
+
+    let header: serde_json::Map<String, Value> =
+        serde_json::from_slice(&header).context("Failed to parse safetensors header as JSON")?;
+
+    let mut tensors = Vec::new();
+    for (name, info) in header.iter().filter(|(name, _)| *name != "__metadata__") {
+        let offsets = info
+            .get("data_offsets")
+            .and_then(Value::as_array)
+            .and_then(|offsets| match offsets.as_slice() {
+                [start, end] => Some((start.as_u64()?, end.as_u64()?)),
+                _ => None,
+            })
+            .with_context(|| format!("Tensor '{name}' has missing or malformed data_offsets"))?;
+        if offsets.1 < offsets.0 {
+            anyhow::bail!(
+                "Tensor '{name}' ends before it starts (data_offsets [{}, {}])",
+                offsets.0,
+                offsets.1
+            );
+        }
+        tensors.push((offsets, name.as_str()));
+    }
+    tensors.sort_unstable();
+
+    let mut previous: Option<(u64, &str)> = None;
+    for &((start, end), name) in &tensors {
+        if let Some((previous_end, previous_name)) = previous
+            && start < previous_end
         {
-            log::info!("No training metadata found in {}", path.display());
-        } else {
-            log::info!("Wrote metadata to {}", json_path.display());
+            anyhow::bail!(
+                "Tensor '{name}' (starting at byte {start}) overlaps tensor '{previous_name}' (ending at byte {previous_end})"
+            );
         }
-        Ok(())
-    })
-    .await?
+        previous = Some((end, name));
+    }
+
+    let data_end = previous.map_or(0, |(end, _)| end);
+    if data_end != data_len {
+        let last = previous.map_or("<none>", |(_, name)| name);
+        anyhow::bail!(
+            "Tensor data ends at byte {data_end} (tensor '{last}') but {} has {data_len} bytes of tensor data",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Reads the parsed safetensors header of a file.
+///
+/// The file is memory-mapped, so only the pages holding the header are
+/// actually read from disk.
+fn read_tensor_metadata(path: &Path) -> anyhow::Result<Metadata> {
+    // Read the content of the safensor file as binary
+    let file = File::open(path).context("Failed to open safensor file")?;
+    let mmap = unsafe { Mmap::map(&file) }.context("Failed to memory map safensor file")?;
+
+    // Read the state dictionary from the memory-mapped file
+    let (_header_size, metadata) =
+        SafeTensors::read_metadata(&mmap).context("Failed to read metadata from safensor file")?;
+    Ok(metadata)
+}
+
+/// Counts the total number of parameters in a safetensors file.
+///
+/// The count is the sum of the element counts (the product of the shape) of
+/// every tensor. Only the header is read, so this stays fast on multi-GB files.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or its header is invalid.
+pub fn count_parameters(path: &Path) -> anyhow::Result<u64> {
+    let metadata = read_tensor_metadata(path)?;
+    Ok(metadata
+        .tensors()
+        .values()
+        .map(|info| info.shape.iter().map(|&dim| dim as u64).product::<u64>())
+        .sum())
+}
+
+/// Summary of a single tensor stored in a safetensors file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TensorInfo {
+    /// Name of the tensor in the state dictionary
+    pub name: String,
+    /// Data type, such as `F32`, `F16` or `BF16`
+    pub dtype: String,
+    /// Dimensions of the tensor
+    pub shape: Vec<usize>,
+    /// Size of the tensor data in bytes
+    pub num_bytes: u64,
+}
+
+/// Lists the tensors of a safetensors file, in the order they are stored.
+///
+/// The `__metadata__` entry is not a tensor and is skipped.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or its header is invalid.
+pub fn list_tensors(path: &Path) -> anyhow::Result<Vec<TensorInfo>> {
+    let metadata = read_tensor_metadata(path)?;
+    let mut tensors: Vec<_> = metadata.tensors().into_iter().collect();
+    tensors.sort_by_key(|(name, info)| (info.data_offsets, name.clone()));
+
+    Ok(tensors
+        .into_iter()
+        .map(|(name, info)| {
+            let (start, end) = info.data_offsets;
+            TensorInfo {
+                name,
+                dtype: format!("{:?}", info.dtype),
+                shape: info.shape.clone(),
+                num_bytes: (end - start) as u64,
+            }
+        })
+        .collect())
+}
+
+/// Counts how many tensors of a safetensors file use each dtype.
+///
+/// Handy to tell at a glance whether a checkpoint is fp16, bf16 or mixed.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or its header is invalid.
+pub fn dtype_summary(path: &Path) -> anyhow::Result<HashMap<String, usize>> {
+    let metadata = read_tensor_metadata(path)?;
+    let mut summary = HashMap::new();
+    for info in metadata.tensors().values() {
+        *summary.entry(format!("{:?}", info.dtype)).or_insert(0) += 1;
+    }
+    Ok(summary)
+}
+
+/// Kind of model stored in a safetensors file, as detected by [`classify_model`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ModelKind {
+    /// A `LoRA` (or similar low-rank) adapter
+    Lora,
+    /// A full model checkpoint
+    FullCheckpoint,
+    /// Neither pattern was recognized
+    Unknown,
+}
+
+/// Tensor name fragments used by `LoRA` adapters (kohya-ss and PEFT naming)
+const LORA_TENSOR_PATTERNS: [&str; 6] = [
+    "lora_up",
+    "lora_down",
+    "lora_A",
+    "lora_B",
+    "lora_unet_",
+    "lora_te",
+];
+
+/// Tensor name prefixes used by full checkpoints (Stable Diffusion and transformers naming)
+const CHECKPOINT_TENSOR_PREFIXES: [&str; 7] = [
+    "model.diffusion_model.",
+    "first_stage_model.",
+    "cond_stage_model.",
+    "conditioner.",
+    "model.layers.",
+    "model.embed_tokens.",
+    "transformer.",
+];
+
+/// Detects whether a safetensors file holds a `LoRA` or a full checkpoint.
+///
+/// Detection is based on tensor names (`lora_up`, `lora_down`, `lora_A`,
+/// `lora_B`, ...) and on the `ss_network_module` training metadata written by
+/// `LoRA` trainers. Only the header is read.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or its header is invalid.
+pub fn classify_model(path: &Path) -> anyhow::Result<ModelKind> {
+    let metadata = read_tensor_metadata(path)?;
+    let tensors = metadata.tensors();
+
+    let is_lora_tensor = |name: &str| {
+        LORA_TENSOR_PATTERNS
+            .iter()
+            .any(|pattern| name.contains(pattern))
+    };
+    let has_network_module = metadata
+        .metadata()
+        .as_ref()
+        .is_some_and(|meta| meta.contains_key("ss_network_module"));
+    if has_network_module || tensors.keys().any(|name| is_lora_tensor(name)) {
+        return Ok(ModelKind::Lora);
+    }
+
+    let is_checkpoint_tensor = |name: &str| {
+        CHECKPOINT_TENSOR_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+    };
+    if tensors.keys().any(|name| is_checkpoint_tensor(name)) {
+        return Ok(ModelKind::FullCheckpoint);
+    }
+
+    Ok(ModelKind::Unknown)
 }
 
 /// Inspects the state dictionary of a targeted safensor file.
@@ -98,13 +826,7 @@ pub async fn process_file(path: &Path) -> anyhow::Result<()> {
 /// - The file cannot be read
 /// - The content cannot be parsed as JSON
 pub fn inspect_state_dict(path: &Path) -> anyhow::Result<Value> {
-    // Read the content of the safensor file as binary
-    let file = File::open(path).context("Failed to open safensor file")?;
-    let mmap = unsafe { Mmap::map(&file) }.context("Failed to memory map safensor file")?;
-
-    // Read the state dictionary from the memory-mapped file
-    let (_header_size, metadata) =
-        SafeTensors::read_metadata(&mmap).context("Failed to read metadata from safensor file")?;
+    let metadata = read_tensor_metadata(path)?;
 
     // Convert the raw metadata to a JSON value
     let state_dict: Value = serde_json::to_value(&metadata)
@@ -118,11 +840,18 @@ mod tests {
     use super::*;
     use std::fs;
     use std::io::Write;
-    use std::path::PathBuf;
     use tempfile::TempDir;
 
     fn create_test_safetensor(dir: &TempDir, metadata: &str) -> anyhow::Result<PathBuf> {
-        let file_path = dir.path().join("test.safetensors");
+        create_named_safetensor(dir, "test.safetensors", metadata)
+    }
+
+    fn create_named_safetensor(
+        dir: &TempDir,
+        name: &str,
+        metadata: &str,
+    ) -> anyhow::Result<PathBuf> {
+        let file_path = dir.path().join(name);
 
         // Create a minimal safetensors file with metadata
         let mut file = fs::File::create(&file_path)?;
@@ -189,6 +918,35 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_extract_metadata_in_memory() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let file_path = create_test_safetensor(&temp_dir, r#"{"ss_network_dim": 64}"#)?;
+
+        let metadata = extract_metadata(&file_path).await?;
+        assert_eq!(metadata, serde_json::json!({"ss_network_dim": 64}));
+
+        // Nothing is written to disk
+        assert!(!file_path.with_extension("metadata.json").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_file_to_custom_path() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let file_path = create_test_safetensor(&temp_dir, r#"{"ss_network_dim": 64}"#)?;
+        let output = temp_dir.path().join("meta/nested/model.json");
+
+        process_file_to(&file_path, &output).await?;
+
+        let json: Value = serde_json::from_str(&fs::read_to_string(&output)?)?;
+        assert_eq!(json, serde_json::json!({"ss_network_dim": 64}));
+        assert!(!file_path.with_extension("metadata.json").exists());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_process_file_empty_metadata() -> anyhow::Result<()> {
         let temp_dir = TempDir::new()?;
@@ -248,11 +1006,8 @@ mod tests {
         Ok(())
     }
 
-    #[tokio::test]
-    async fn test_inspect_state_dict() -> anyhow::Result<()> {
-        // Create a temporary directory for the test
-        let temp_dir = TempDir::new()?;
-
+    /// Creates a LoRA-shaped safetensors file with mixed F32/F16 tensors
+    fn create_lora_safetensor(temp_dir: &TempDir) -> anyhow::Result<PathBuf> {
         // Create a test safetensors file with complex metadata
         let metadata = r#"{
             "ss_network_args": {
@@ -303,6 +1058,15 @@ mod tests {
         // We need at least 295040 bytes of tensor data based on the offsets
         file.write_all(&vec![0u8; 295040])?;
 
+        Ok(file_path)
+    }
+
+    #[tokio::test]
+    async fn test_inspect_state_dict() -> anyhow::Result<()> {
+        // Create a temporary directory for the test
+        let temp_dir = TempDir::new()?;
+        let file_path = create_lora_safetensor(&temp_dir)?;
+
         // Test the inspect_state_dict function
         let state_dict = inspect_state_dict(&file_path)?;
 
@@ -338,4 +1102,348 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_metadata_sources_agree() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let metadata = r#"{"ss_network_dim": 64}"#;
+        let file_path = create_test_safetensor(&temp_dir, metadata)?;
+
+        // Path, PathBuf and in-memory bytes all yield the same metadata
+        let from_path = extract_metadata_from(file_path.as_path()).await?;
+        let from_path_buf = extract_metadata_from(&file_path).await?;
+        let bytes = fs::read(&file_path)?;
+        let from_bytes = extract_metadata_from(bytes.as_slice()).await?;
+
+        assert_eq!(from_path, serde_json::json!({"ss_network_dim": 64}));
+        assert_eq!(from_path, from_path_buf);
+        assert_eq!(from_path, from_bytes);
+
+        // Raw header contains the tensor entries
+        let header = read_header_json(bytes.as_slice()).await?;
+        assert!(header.get("test_tensor").is_some());
+
+        // Truncated buffers are rejected
+        assert!(read_header_json(&bytes[..4]).await.is_err());
+        assert!(read_header_json(&bytes[..16]).await.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_parameters() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let file_path = create_lora_safetensor(&temp_dir)?;
+
+        // 768 * 64 + 64 * 768 + 32
+        assert_eq!(count_parameters(&file_path)?, 98_336);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_tensors() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let file_path = create_lora_safetensor(&temp_dir)?;
+
+        let tensors = list_tensors(&file_path)?;
+        assert_eq!(
+            tensors,
+            vec![
+                TensorInfo {
+                    name: "lora_up.weight".into(),
+                    dtype: "F32".into(),
+                    shape: vec![768, 64],
+                    num_bytes: 196_608,
+                },
+                TensorInfo {
+                    name: "lora_down.weight".into(),
+                    dtype: "F16".into(),
+                    shape: vec![64, 768],
+                    num_bytes: 98_304,
+                },
+                TensorInfo {
+                    name: "conv.bias".into(),
+                    dtype: "F32".into(),
+                    shape: vec![32],
+                    num_bytes: 128,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dtype_summary() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let file_path = create_lora_safetensor(&temp_dir)?;
+
+        let summary = dtype_summary(&file_path)?;
+        assert_eq!(
+            summary,
+            HashMap::from([("F32".to_string(), 2), ("F16".to_string(), 1)])
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_metadata_round_trip() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let file_path = create_lora_safetensor(&temp_dir)?;
+        let original = fs::read(&file_path)?;
+        let original_header_len = 8 + original.as_slice().read_header().await?.len();
+
+        update_metadata(
+            &file_path,
+            HashMap::from([("training_note".to_string(), "fine-tuned on v2".to_string())]),
+        )
+        .await?;
+
+        // The new key is visible to the metadata readers and the existing metadata is kept
+        assert_eq!(
+            get_metadata_value(&file_path, "training_note")?,
+            Some(Value::String("fine-tuned on v2".to_string()))
+        );
+        let metadata = extract_metadata_from(&file_path).await?;
+        assert_eq!(metadata["training_note"], "fine-tuned on v2");
+        assert_eq!(metadata["ss_network_args"]["network_dim"], 64);
+
+        // Tensor data is untouched and the file is still a valid safetensors file
+        let updated = fs::read(&file_path)?;
+        let updated_header_len = 8 + updated.as_slice().read_header().await?.len();
+        assert_eq!(updated_header_len % 8, 0);
+        assert_eq!(
+            &updated[updated_header_len..],
+            &original[original_header_len..]
+        );
+        let tensors = SafeTensors::deserialize(&updated)?;
+        assert_eq!(tensors.tensor("conv.bias")?.shape(), &[32]);
+        assert_eq!(list_tensors(&file_path)?.len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_diff_metadata() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let a = create_named_safetensor(
+            &temp_dir,
+            "a.safetensors",
+            r#"{
+                "ss_learning_rate": "0.0001",
+                "ss_network_args": "{\"network_dim\": 32, \"network_alpha\": 16}",
+                "ss_seed": "42"
+            }"#,
+        )?;
+        let b = create_named_safetensor(
+            &temp_dir,
+            "b.safetensors",
+            r#"{
+                "ss_learning_rate": "0.0002",
+                "ss_network_args": "{\"network_dim\": 64, \"network_alpha\": 16}",
+                "ss_epochs": "10"
+            }"#,
+        )?;
+
+        let diff = diff_metadata(&a, &b).await?;
+        assert_eq!(
+            diff.added,
+            BTreeMap::from([("ss_epochs".to_string(), serde_json::json!("10"))])
+        );
+        assert_eq!(
+            diff.removed,
+            BTreeMap::from([("ss_seed".to_string(), serde_json::json!("42"))])
+        );
+        assert_eq!(
+            diff.changed.keys().collect::<Vec<_>>(),
+            ["ss_learning_rate", "ss_network_args.network_dim"]
+        );
+        assert_eq!(
+            diff.changed["ss_network_args.network_dim"],
+            ValueChange {
+                old: serde_json::json!(32),
+                new: serde_json::json!(64),
+            }
+        );
+
+        assert!(diff_metadata(&a, &a).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_metadata_value() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let metadata = r#"{
+            "ss_learning_rate": "0.0001",
+            "ss_bucket_info": {
+                "buckets": {"0": {"resolution": [1280, 800], "count": 78}},
+                "mean_img_ar_error": 0.5
+            }
+        }"#;
+        let file_path = create_test_safetensor(&temp_dir, metadata)?;
+
+        assert_eq!(
+            get_metadata_value(&file_path, "ss_learning_rate")?,
+            Some(serde_json::json!("0.0001"))
+        );
+        assert_eq!(
+            get_metadata_value(&file_path, "ss_bucket_info.mean_img_ar_error")?,
+            Some(serde_json::json!(0.5))
+        );
+        assert_eq!(
+            get_metadata_value(&file_path, "ss_bucket_info.buckets.0.resolution.1")?,
+            Some(serde_json::json!(800))
+        );
+        assert_eq!(get_metadata_value(&file_path, "ss_seed")?, None);
+        assert_eq!(
+            get_metadata_value(&file_path, "ss_bucket_info.missing")?,
+            None
+        );
+
+        Ok(())
+    }
+
+    /// Writes a safetensors file with the given tensor entries and amount of tensor data
+    fn create_raw_safetensor(
+        dir: &TempDir,
+        tensors: &Value,
+        data_len: usize,
+    ) -> anyhow::Result<PathBuf> {
+        let file_path = dir.path().join("raw.safetensors");
+        let header = serde_json::to_vec(tensors)?;
+        let mut file = fs::File::create(&file_path)?;
+        file.write_all(&(header.len() as u64).to_le_bytes())?;
+        file.write_all(&header)?;
+        file.write_all(&vec![0u8; data_len])?;
+        Ok(file_path)
+    }
+
+    #[tokio::test]
+    async fn test_verify() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        verify(&create_lora_safetensor(&temp_dir)?).await?;
+
+        let tensors = serde_json::json!({
+            "a": {"dtype": "F32", "shape": [2], "data_offsets": [0, 8]},
+            "b": {"dtype": "F32", "shape": [2], "data_offsets": [4, 12]}
+        });
+        let err = verify(&create_raw_safetensor(&temp_dir, &tensors, 12)?)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Tensor 'b'"), "{err}");
+        assert!(err.to_string().contains("overlaps"), "{err}");
+
+        // Truncated download
+        let tensors = serde_json::json!({
+            "a": {"dtype": "F32", "shape": [2], "data_offsets": [0, 8]},
+            "b": {"dtype": "F32", "shape": [2], "data_offsets": [8, 16]}
+        });
+        let err = verify(&create_raw_safetensor(&temp_dir, &tensors, 10)?)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("tensor 'b'"), "{err}");
+
+        let tensors = serde_json::json!({
+            "a": {"dtype": "F32", "shape": [2], "data_offsets": [8, 0]}
+        });
+        let err = verify(&create_raw_safetensor(&temp_dir, &tensors, 8)?)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("ends before it starts"), "{err}");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_classify_model() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        assert_eq!(
+            classify_model(&create_lora_safetensor(&temp_dir)?)?,
+            ModelKind::Lora
+        );
+
+        let tensors = serde_json::json!({
+            "model.diffusion_model.input_blocks.0.0.weight": {
+                "dtype": "F16", "shape": [4], "data_offsets": [0, 8]
+            }
+        });
+        let file_path = create_raw_safetensor(&temp_dir, &tensors, 8)?;
+        assert_eq!(classify_model(&file_path)?, ModelKind::FullCheckpoint);
+
+        // Metadata hints are enough even with unusual tensor names
+        let tensors = serde_json::json!({
+            "__metadata__": {"ss_network_module": "networks.lora"},
+            "unet.block.alpha": {"dtype": "F16", "shape": [4], "data_offsets": [0, 8]}
+        });
+        let file_path = create_raw_safetensor(&temp_dir, &tensors, 8)?;
+        assert_eq!(classify_model(&file_path)?, ModelKind::Lora);
+
+        let file_path = create_test_safetensor(&temp_dir, "{}")?;
+        assert_eq!(classify_model(&file_path)?, ModelKind::Unknown);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_directory() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        create_named_safetensor(&temp_dir, "a.safetensors", r#"{"ss_seed": 1}"#)?;
+        create_named_safetensor(&temp_dir, "b.safetensors", r#"{"ss_seed": 2}"#)?;
+        fs::write(temp_dir.path().join("broken.safetensors"), b"oops")?;
+        fs::create_dir(temp_dir.path().join("nested"))?;
+        create_named_safetensor(&temp_dir, "nested/c.safetensors", "{}")?;
+
+        // The broken file is skipped without aborting the batch
+        assert_eq!(process_directory(temp_dir.path(), false).await?, 2);
+        assert!(temp_dir.path().join("a.metadata.json").exists());
+        assert!(!temp_dir.path().join("nested/c.metadata.json").exists());
+
+        assert_eq!(process_directory(temp_dir.path(), true).await?, 3);
+        assert!(temp_dir.path().join("nested/c.metadata.json").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_sharded() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let shard_1 = "model-00001-of-00002.safetensors";
+        let shard_2 = "model-00002-of-00002.safetensors";
+        create_named_safetensor(&temp_dir, shard_1, r#"{"ss_seed": 42}"#)?;
+        let second = create_named_safetensor(&temp_dir, shard_2, r#"{"ss_seed": 42}"#)?;
+
+        // Rename the second shard's tensor so both shards contribute one
+        let bytes = fs::read(&second)?;
+        fs::write(
+            &second,
+            String::from_utf8_lossy(&bytes).replace("test_tensor", "last_tensor"),
+        )?;
+
+        let index_path = temp_dir.path().join("model.safetensors.index.json");
+        fs::write(
+            &index_path,
+            serde_json::to_string(&serde_json::json!({
+                "metadata": {"total_size": 8},
+                "weight_map": {"test_tensor": shard_1, "last_tensor": shard_2}
+            }))?,
+        )?;
+
+        let combined = process_sharded(&index_path).await?;
+        assert_eq!(combined["index_metadata"]["total_size"], 8);
+        assert_eq!(combined["metadata"], serde_json::json!({"ss_seed": 42}));
+        assert_eq!(combined["shards"], serde_json::json!([shard_1, shard_2]));
+        assert_eq!(combined["tensors"]["test_tensor"]["shard"], shard_1);
+        assert_eq!(combined["tensors"]["last_tensor"]["shard"], shard_2);
+        assert_eq!(combined["tensors"]["last_tensor"]["dtype"], "F32");
+
+        // A missing shard is reported
+        fs::remove_file(&second)?;
+        let err = process_sharded(&index_path).await.unwrap_err();
+        assert!(err.to_string().contains(shard_2), "{err}");
+
+        Ok(())
+    }
 }
@@ -1,7 +1,8 @@
 #![warn(clippy::all, clippy::pedantic)]
 
 use crate::caption::{
-    E621Config, process_e621_json_data, process_e621_tags, should_ignore_e621_tag,
+    ArtifactPosition, DanbooruConfig, E621Config, build_e621_caption, build_e621_captions,
+    process_danbooru_json_data, process_e621_json_data, process_e621_tags, should_ignore_e621_tag,
 };
 use crate::process_e621_json_file;
 use serde_json::json;
@@ -23,6 +24,17 @@ fn test_should_ignore_e621_tag() {
     assert!(!should_ignore_e621_tag("red_background"));
 }
 
+#[test]
+fn test_should_ignore_e621_tag_repeated_calls() {
+    // The cached patterns give the same answers on every call
+    for _ in 0..1000 {
+        assert!(should_ignore_e621_tag("2023"));
+        assert!(should_ignore_e621_tag("16:9"));
+        assert!(should_ignore_e621_tag("conditional_dnp"));
+        assert!(!should_ignore_e621_tag("wolf"));
+    }
+}
+
 #[test]
 fn test_artist_formatting() {
     // Create a mock JSON with artist tags
@@ -123,6 +135,8 @@ fn test_process_e621_tags() {
     assert!(processed_tags.contains(&"conditional_dnp".to_string())); // conditional_dnp included
     assert!(processed_tags.contains(&"16:9".to_string())); // aspect ratio included
     assert!(processed_tags.contains(&"character_name".to_string())); // underscores preserved
+    assert!(processed_tags.contains(&"by artist_with_underscores".to_string())); // artists too
+    assert!(processed_tags.contains(&"by artist2".to_string())); // (artist) still removed
 }
 
 #[tokio::test]
@@ -597,3 +611,629 @@ async fn test_e621_config() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_e621_artifact_tags_sidecar() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = temp_dir.path().join("artifacts.json");
+
+    let json_data = json!({
+        "post": {
+            "file": {
+                "url": "https://example.com/artifact_post.jpg"
+            },
+            "rating": "s",
+            "tags": {
+                "general": ["solo"],
+                "meta": ["hi_res", "watermark"]
+            }
+        }
+    });
+    let file_path_arc = Arc::new(file_path.clone());
+
+    let artifact_tags = ["watermark".to_string()].into_iter().collect();
+    let config = E621Config::new()
+        .with_format(Some("{rating}, {general}, {meta}".to_string()))
+        .with_artifact_tags(Some(artifact_tags))
+        .with_artifact_position(ArtifactPosition::Sidecar);
+    process_e621_json_data(&json_data, &file_path_arc, Some(config)).await?;
+
+    // The watermark tag is moved out of the caption...
+    let content = fs::read_to_string(temp_dir.path().join("artifact_post.txt")).await?;
+    assert_eq!(content, "safe, solo, hi res");
+
+    // ...and into the sidecar file
+    let sidecar = fs::read_to_string(temp_dir.path().join("artifact_post.artifacts")).await?;
+    assert_eq!(sidecar, "watermark");
+
+    // Front placement puts artifact tags before everything else
+    let artifact_tags = ["watermark".to_string()].into_iter().collect();
+    let config = E621Config::new()
+        .with_format(Some("{rating}, {general}, {meta}".to_string()))
+        .with_artifact_tags(Some(artifact_tags))
+        .with_artifact_position(ArtifactPosition::Front);
+    process_e621_json_data(&json_data, &file_path_arc, Some(config)).await?;
+    let content = fs::read_to_string(temp_dir.path().join("artifact_post.txt")).await?;
+    assert_eq!(content, "watermark, safe, solo, hi res");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_e621_min_score() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = temp_dir.path().join("low_score.json");
+    let caption_path = temp_dir.path().join("low_score_post.txt");
+
+    let json_data = json!({
+        "post": {
+            "file": {
+                "url": "https://example.com/low_score_post.jpg"
+            },
+            "rating": "s",
+            "score": {"up": 1, "down": -6, "total": -5},
+            "tags": {
+                "general": ["solo"]
+            }
+        }
+    });
+    let file_path_arc = Arc::new(file_path);
+
+    // Posts below the minimum score are skipped
+    let config = E621Config::new().with_min_score(Some(0));
+    process_e621_json_data(&json_data, &file_path_arc, Some(config)).await?;
+    assert!(!caption_path.exists());
+
+    // Without a minimum the post is processed as before
+    process_e621_json_data(&json_data, &file_path_arc, None).await?;
+    assert!(caption_path.exists());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_e621_blacklist() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = temp_dir.path().join("blacklist.json");
+
+    let tags_json = json!({
+        "general": ["solo", "young", "blood_splatter"],
+        "species": ["wolf"]
+    });
+    let json_data = json!({
+        "post": {
+            "file": {
+                "url": "https://example.com/blacklist_post.jpg"
+            },
+            "rating": "s",
+            "tags": tags_json
+        }
+    });
+
+    // Both the raw and the normalized spelling are matched
+    let blacklist = ["young".to_string(), "blood splatter".to_string()]
+        .into_iter()
+        .collect();
+    let config = E621Config::new()
+        .with_format(Some("{rating}, {species}, {general}".to_string()))
+        .with_blacklist(blacklist);
+
+    let processed_tags = process_e621_tags(&tags_json, Some(&config));
+    assert_eq!(processed_tags, vec!["wolf", "solo"]);
+
+    process_e621_json_data(&json_data, &Arc::new(file_path), Some(config)).await?;
+    let content = fs::read_to_string(temp_dir.path().join("blacklist_post.txt")).await?;
+    assert_eq!(content, "safe, wolf, solo");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_e621_require_any() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = Arc::new(temp_dir.path().join("require_any.json"));
+    let caption_path = temp_dir.path().join("themed_post.txt");
+
+    let json_data = json!({
+        "post": {
+            "file": {
+                "url": "https://example.com/themed_post.jpg"
+            },
+            "rating": "s",
+            "tags": {
+                "general": ["solo", "snow_field"],
+                "species": ["wolf"]
+            }
+        }
+    });
+
+    // No required tag present: nothing is written
+    let required = ["fox".to_string(), "cat".to_string()].into_iter().collect();
+    let config = E621Config::new().with_require_any(Some(required));
+    process_e621_json_data(&json_data, &file_path, Some(config)).await?;
+    assert!(!caption_path.exists());
+
+    // An empty set never matches
+    let config = E621Config::new().with_require_any(Some(std::collections::HashSet::new()));
+    process_e621_json_data(&json_data, &file_path, Some(config)).await?;
+    assert!(!caption_path.exists());
+
+    // A match in any category, in either spelling, writes the caption
+    let required = ["snow field".to_string()].into_iter().collect();
+    let config = E621Config::new().with_require_any(Some(required));
+    process_e621_json_data(&json_data, &file_path, Some(config)).await?;
+    assert!(caption_path.exists());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_e621_max_general() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = Arc::new(temp_dir.path().join("max_general.json"));
+
+    let general: Vec<String> = (0..50).map(|i| format!("tag{i}")).collect();
+    let json_data = json!({
+        "post": {
+            "file": {
+                "url": "https://example.com/max_general_post.jpg"
+            },
+            "rating": "s",
+            "tags": {
+                "general": general,
+                "meta": ["hi_res", "absurd_res"]
+            }
+        }
+    });
+
+    let limits = [("meta".to_string(), 1)].into_iter().collect();
+    let config = E621Config::new()
+        .with_format(Some("{general}, {meta}".to_string()))
+        .with_max_general(Some(10))
+        .with_max_tags_per_category(Some(limits));
+    process_e621_json_data(&json_data, &file_path, Some(config)).await?;
+
+    // The first 10 general tags survive in source order
+    let content = fs::read_to_string(temp_dir.path().join("max_general_post.txt")).await?;
+    let expected: Vec<String> = (0..10)
+        .map(|i| format!("tag{i}"))
+        .chain(["hi res".to_string()])
+        .collect();
+    assert_eq!(content, expected.join(", "));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_e621_category_order() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = Arc::new(temp_dir.path().join("category_order.json"));
+
+    let tags_json = json!({
+        "artist": ["some_artist"],
+        "copyright": ["some_franchise"],
+        "general": ["solo"],
+        "meta": ["hi_res"]
+    });
+    let json_data = json!({
+        "post": {
+            "file": {
+                "url": "https://example.com/ordered_post.jpg"
+            },
+            "rating": "s",
+            "tags": tags_json
+        }
+    });
+
+    // Meta first, copyright dropped
+    let order = ["meta", "artist", "general"].map(String::from).to_vec();
+    let config = E621Config::new().with_category_order(Some(order));
+
+    let processed_tags = process_e621_tags(&tags_json, Some(&config));
+    assert_eq!(processed_tags, vec!["hi res", "by some artist", "solo"]);
+
+    process_e621_json_data(&json_data, &file_path, Some(config)).await?;
+    let content = fs::read_to_string(temp_dir.path().join("ordered_post.txt")).await?;
+    assert_eq!(content, "safe, hi res, by some artist, solo");
+
+    // Placeholders of omitted or empty categories are removed from a custom format
+    let order = ["artist", "general"].map(String::from).to_vec();
+    let config = E621Config::new()
+        .with_category_order(Some(order))
+        .with_format(Some(
+            "{rating}, {artists}, {species}, {copyright}, {general}".to_string(),
+        ));
+    process_e621_json_data(&json_data, &file_path, Some(config)).await?;
+    let content = fs::read_to_string(temp_dir.path().join("ordered_post.txt")).await?;
+    assert_eq!(content, "safe, by some artist, solo");
+
+    // Unknown categories are rejected
+    let order = vec!["general".to_string(), "lore".to_string()];
+    let config = E621Config::new().with_category_order(Some(order));
+    let err = process_e621_json_data(&json_data, &file_path, Some(config))
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Unknown e621 category 'lore'"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_e621_dedupe_across_categories() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = Arc::new(temp_dir.path().join("dedupe.json"));
+
+    let json_data = json!({
+        "post": {
+            "file": {
+                "url": "https://example.com/dedupe_post.jpg"
+            },
+            "rating": "s",
+            "tags": {
+                "species": ["wolf", "canine"],
+                "general": ["safe", "wolf", "solo"]
+            }
+        }
+    });
+    let format = Some("{rating}, {species}, {general}".to_string());
+
+    // Duplicates are kept by default
+    let config = E621Config::new().with_format(format.clone());
+    process_e621_json_data(&json_data, &file_path, Some(config)).await?;
+    let content = fs::read_to_string(temp_dir.path().join("dedupe_post.txt")).await?;
+    assert_eq!(content, "safe, wolf, canine, safe, wolf, solo");
+
+    // Later repeats are dropped, but the rating is exempt
+    let config = E621Config::new()
+        .with_format(format)
+        .with_dedupe_across_categories(true);
+    process_e621_json_data(&json_data, &file_path, Some(config)).await?;
+    let content = fs::read_to_string(temp_dir.path().join("dedupe_post.txt")).await?;
+    assert_eq!(content, "safe, wolf, canine, safe, solo");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_e621_multiple_posts() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = Arc::new(temp_dir.path().join("posts.json"));
+
+    let json_data = json!({
+        "posts": [
+            {
+                "file": {"url": "https://example.com/first_post.jpg"},
+                "rating": "s",
+                "tags": {"general": ["solo"]}
+            },
+            {
+                "file": {"url": ""},
+                "rating": "s",
+                "tags": {"general": ["broken"]}
+            },
+            {
+                "file": {"url": "https://example.com/second_post.png"},
+                "rating": "e",
+                "tags": {"species": ["wolf"]}
+            }
+        ]
+    });
+
+    // The malformed post is reported without dropping the others
+    let err = process_e621_json_data(&json_data, &file_path, None)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("1 of 3 e621 posts"));
+
+    let first = fs::read_to_string(temp_dir.path().join("first_post.txt")).await?;
+    assert!(first.starts_with("safe, "));
+    assert!(first.contains("solo"));
+
+    let second = fs::read_to_string(temp_dir.path().join("second_post.txt")).await?;
+    assert!(second.starts_with("explicit, "));
+    assert!(second.contains("wolf"));
+
+    Ok(())
+}
+
+#[test]
+fn test_e621_escape_parentheses() {
+    let tags_json = json!({
+        "artist": ["some_artist_(artist)"],
+        "general": ["reflector_(object)"]
+    });
+
+    // Raw parentheses are kept by default
+    let processed_tags = process_e621_tags(&tags_json, None);
+    assert_eq!(processed_tags, vec!["by some artist", "reflector (object)"]);
+
+    // Escaping applies to non-artist tags only
+    let config = E621Config::new().with_escape_parentheses(true);
+    let processed_tags = process_e621_tags(&tags_json, Some(&config));
+    assert_eq!(
+        processed_tags,
+        vec!["by some artist", "reflector \\(object\\)"]
+    );
+}
+
+#[tokio::test]
+async fn test_e621_include_sources() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = Arc::new(temp_dir.path().join("sources.json"));
+    let caption_path = temp_dir.path().join("sourced_post.txt");
+
+    let mut json_data = json!({
+        "post": {
+            "file": {
+                "url": "https://example.com/sourced_post.jpg"
+            },
+            "rating": "s",
+            "sources": [
+                "https://twitter.com/someone/status/1",
+                "https://www.pixiv.net/artworks/2",
+                "https://x.twitter.com/someone",
+                "https://www.example.co.uk/gallery",
+                "https://someone.github.io/art",
+                "https://co.uk/",
+                "not a url",
+                "https://localhost/image.png"
+            ],
+            "tags": {
+                "general": ["solo"]
+            }
+        }
+    });
+
+    // Off by default
+    let format = Some("{rating}, {general}, {sources}".to_string());
+    let config = E621Config::new().with_format(format.clone());
+    process_e621_json_data(&json_data, &file_path, Some(config)).await?;
+    assert_eq!(
+        fs::read_to_string(&caption_path).await?,
+        "safe, solo, {sources}"
+    );
+
+    // Domains are deduplicated and malformed URLs skipped
+    let config = E621Config::new()
+        .with_format(format)
+        .with_include_sources(true);
+    process_e621_json_data(&json_data, &file_path, Some(config.clone())).await?;
+    assert_eq!(
+        fs::read_to_string(&caption_path).await?,
+        "safe, solo, twitter, pixiv, example, someone"
+    );
+
+    // Posts without sources leave no placeholder behind
+    json_data["post"]["sources"] = json!([]);
+    process_e621_json_data(&json_data, &file_path, Some(config)).await?;
+    assert_eq!(fs::read_to_string(&caption_path).await?, "safe, solo");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_e621_ignored_patterns() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = Arc::new(temp_dir.path().join("ignored.json"));
+
+    let tags_json = json!({
+        "general": ["solo", "2023", "unknown_artist_signature", "wolf"],
+        "meta": ["hi_res", "absurd_res"]
+    });
+    let json_data = json!({
+        "post": {
+            "file": {
+                "url": "https://example.com/ignored_post.jpg"
+            },
+            "rating": "s",
+            "tags": tags_json
+        }
+    });
+
+    // User patterns apply in addition to the built-in ones
+    let patterns = vec![r"_res$".to_string(), r"^unknown_".to_string()];
+    let config = E621Config::new()
+        .with_format(Some("{rating}, {general}".to_string()))
+        .with_ignored_patterns(Some(patterns));
+    assert_eq!(
+        process_e621_tags(&tags_json, Some(&config)),
+        vec!["solo", "wolf"]
+    );
+
+    process_e621_json_data(&json_data, &file_path, Some(config)).await?;
+    let content = fs::read_to_string(temp_dir.path().join("ignored_post.txt")).await?;
+    assert_eq!(content, "safe, solo, wolf");
+
+    // Invalid patterns are reported
+    let config = E621Config::new().with_ignored_patterns(Some(vec!["(unclosed".to_string()]));
+    let err = process_e621_json_data(&json_data, &file_path, Some(config))
+        .await
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Invalid ignored tag pattern '(unclosed'")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_build_e621_caption_matches_written_caption() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = Arc::new(temp_dir.path().join("build.json"));
+
+    let json_data = json!({
+        "post": {
+            "file": {
+                "url": "https://example.com/built_post.jpg"
+            },
+            "rating": "e",
+            "tags": {
+                "artist": ["some_artist"],
+                "character": ["someone"],
+                "species": ["wolf"],
+                "copyright": ["some_franchise"],
+                "general": ["solo", "2023"],
+                "meta": ["hi_res"]
+            }
+        }
+    });
+    let config = E621Config::new();
+
+    let caption = build_e621_caption(&json_data, &config)?;
+    assert_eq!(
+        caption.as_deref(),
+        Some("explicit, by some artist, someone, wolf, some franchise, solo, hi res")
+    );
+
+    process_e621_json_data(&json_data, &file_path, Some(config.clone())).await?;
+    let written = fs::read_to_string(temp_dir.path().join("built_post.txt")).await?;
+    assert_eq!(caption, Some(written));
+
+    // Nothing to write: no tags besides the rating, or no post at all
+    let rating_only = json!({
+        "post": {
+            "file": {"url": "https://example.com/rating_only.jpg"},
+            "rating": "s",
+            "tags": {"general": ["2023"]}
+        }
+    });
+    assert_eq!(build_e621_caption(&rating_only, &config)?, None);
+    assert_eq!(build_e621_caption(&json!({}), &config)?, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_build_e621_captions_matches_multi_post_export() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = Arc::new(temp_dir.path().join("posts.json"));
+
+    let json_data = json!({
+        "posts": [
+            {
+                "file": {"url": "https://example.com/first.jpg"},
+                "rating": "s",
+                "tags": {"species": ["wolf"], "general": ["solo"]}
+            },
+            {
+                "file": {"url": "https://example.com/skipped.jpg"},
+                "rating": "s",
+                "tags": {"general": ["2023"]}
+            },
+            {
+                "file": {"url": "https://example.com/second.png"},
+                "rating": "q",
+                "tags": {"species": ["fox"], "general": ["duo"]}
+            }
+        ]
+    });
+    let config = E621Config::new().with_format(Some("{rating}, {species}, {general}".to_string()));
+
+    // A single-post builder can't represent several captions
+    assert!(build_e621_caption(&json_data, &config).is_err());
+
+    let captions = build_e621_captions(&json_data, &config)?;
+    assert_eq!(
+        captions,
+        vec![
+            ("first".to_string(), "safe, wolf, solo".to_string()),
+            ("second".to_string(), "questionable, fox, duo".to_string()),
+        ]
+    );
+
+    process_e621_json_data(&json_data, &file_path, Some(config)).await?;
+    for (stem, caption) in &captions {
+        let written = fs::read_to_string(temp_dir.path().join(format!("{stem}.txt"))).await?;
+        assert_eq!(&written, caption);
+    }
+    assert!(!temp_dir.path().join("skipped.txt").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_e621_aliases() -> anyhow::Result<()> {
+    let json_data = json!({
+        "post": {
+            "file": {
+                "url": "https://example.com/alias_post.jpg"
+            },
+            "rating": "s",
+            "tags": {
+                "species": ["canid", "wolf"],
+                "general": ["canine", "simple_background", "solo", "outdoors"]
+            }
+        }
+    });
+
+    let aliases = [
+        ("canid", "canine"),
+        ("simple background", ""),
+        ("solo", "solo focus"),
+    ]
+    .map(|(from, to)| (from.to_string(), to.to_string()))
+    .into_iter()
+    .collect();
+    let config = E621Config::new()
+        .with_format(Some("{rating}, {species}, {general}".to_string()))
+        .with_aliases(Some(aliases))
+        .with_blacklist(["solo focus".to_string()].into_iter().collect());
+
+    // Aliases apply before the blacklist; the aliased tag duplicates "canine"
+    let caption = build_e621_caption(&json_data, &config)?;
+    assert_eq!(
+        caption.as_deref(),
+        Some("safe, canine, wolf, canine, outdoors")
+    );
+
+    // With deduplication the aliased tag collapses with the existing one
+    let config = config.with_dedupe_across_categories(true);
+    let caption = build_e621_caption(&json_data, &config)?;
+    assert_eq!(caption.as_deref(), Some("safe, canine, wolf, outdoors"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_process_danbooru_json_data() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = Arc::new(temp_dir.path().join("post.json"));
+
+    let data = json!({
+        "md5": "abc123",
+        "file_url": "https://cdn.donmai.us/original/ab/c1/abc123.png",
+        "rating": "s",
+        "tag_string_artist": "some_artist",
+        "tag_string_character": "hatsune_miku",
+        "tag_string_copyright": "vocaloid",
+        "tag_string_general": "1girl  solo long_hair 2023",
+        "tag_string_meta": ""
+    });
+    process_danbooru_json_data(&data, &file_path, None).await?;
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("abc123.txt")).await?,
+        "sensitive, by some artist, hatsune miku, vocaloid, 1girl, solo, long hair"
+    );
+
+    // Arrays of posts are processed, falling back to md5 without a file URL
+    let config = DanbooruConfig::new()
+        .with_rating_conversions(None)
+        .with_artist_prefix(None)
+        .with_replace_underscores(false)
+        .with_blacklist(["solo".to_string()].into_iter().collect())
+        .with_format(Some("{artists} | {general} | {rating}".to_string()));
+    let data = json!([
+        {"md5": "def456", "rating": "g", "tag_string_artist": "an_artist", "tag_string_general": "solo smile"},
+        {"md5": "empty", "rating": "g", "tag_string_general": "solo"},
+        {"rating": "g", "tag_string_general": "no_file"}
+    ]);
+    process_danbooru_json_data(&data, &file_path, Some(config)).await?;
+    assert_eq!(
+        fs::read_to_string(temp_dir.path().join("def456.txt")).await?,
+        "an_artist | smile | g"
+    );
+    assert!(!temp_dir.path().join("empty.txt").exists());
+
+    Ok(())
+}
@@ -1,7 +1,20 @@
 #![warn(clippy::all, clippy::pedantic)]
 
-use crate::caption::{format_text_content, replace_special_chars, replace_string};
-use crate::process_json_to_caption;
+use crate::caption::{
+    CaptionStats, TagDiff, TagPosition, TagSetOp, apply_tag_map_file, caption_stats, cooccurrence,
+    diff_tags, ensure_tag, export_dir_to_jsonl, find_missing_captions, find_near_duplicates,
+    find_orphan_captions, find_unescaped_parens, fix_mojibake, format_text_content,
+    format_text_content_preserving_paragraphs, import_from_csv, json_to_txt, length_histogram,
+    merge_caption_files, normalize_tag, normalize_tags_in_file, prepend_trigger,
+    prepend_trigger_in_dir, remove_matching_tags, replace_regex, replace_special_chars,
+    replace_string, replace_string_case_insensitive, replace_string_in_dir, split_to_sidecars,
+    strip_trigger, tag_difference, tag_frequency, tag_intersection, tag_set_op_files, tag_union,
+    txt_to_json, write_cooccurrence_csv, write_tag_frequency_csv,
+};
+use crate::{
+    JsonCaptionConfig, normalize_caption_extension, process_json_to_caption,
+    process_json_to_caption_with_config,
+};
 use serde_json::json;
 use tempfile::TempDir;
 use tokio::fs;
@@ -46,6 +59,24 @@ fn test_format_text_content() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_format_text_content_preserving_paragraphs() -> anyhow::Result<()> {
+    // Whitespace within lines is collapsed and lines are trimmed
+    let text = "  First  line\twith   tabs  \nSecond line  ";
+    let formatted = format_text_content_preserving_paragraphs(text)?;
+    assert_eq!(formatted, "First line with tabs\nSecond line");
+
+    // Runs of blank lines become a single paragraph break
+    let text = "\n\nParagraph one.\n \n\t\n\nParagraph two.\n\n";
+    let formatted = format_text_content_preserving_paragraphs(text)?;
+    assert_eq!(formatted, "Paragraph one.\n\nParagraph two.");
+
+    // The original function still collapses everything
+    assert_eq!(format_text_content(text)?, "Paragraph one. Paragraph two.");
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_replace_string() -> anyhow::Result<()> {
     let temp_dir = TempDir::new()?;
@@ -91,6 +122,128 @@ async fn test_replace_string() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_replace_string_in_dir() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    let nested = dir.join("nested");
+    fs::create_dir(&nested).await?;
+
+    fs::write(dir.join("a.txt"), "old tag, other").await?;
+    fs::write(nested.join("b.txt"), "another, old tag").await?;
+    fs::write(dir.join("c.txt"), "nothing to change").await?;
+    fs::write(dir.join("d.caption"), "old tag").await?;
+    // Invalid UTF-8 should be skipped rather than abort the walk
+    fs::write(dir.join("e.txt"), [0xff, 0xfe, 0x00]).await?;
+
+    let modified = replace_string_in_dir(dir, "*.txt", "old tag", "new tag").await?;
+    assert_eq!(modified, 2);
+    assert_eq!(
+        fs::read_to_string(dir.join("a.txt")).await?,
+        "new tag, other"
+    );
+    assert_eq!(
+        fs::read_to_string(nested.join("b.txt")).await?,
+        "another, new tag"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("c.txt")).await?,
+        "nothing to change"
+    );
+    assert_eq!(fs::read_to_string(dir.join("d.caption")).await?, "old tag");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replace_string_in_dir_glob_pattern() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+
+    fs::write(dir.join("img_001.txt"), "old tag").await?;
+    fs::write(dir.join("IMG_002.TXT"), "old tag").await?;
+    fs::write(dir.join("other.txt"), "old tag").await?;
+    fs::write(dir.join("img_003.caption"), "old tag").await?;
+
+    // Only file names matching the whole pattern are touched
+    let modified = replace_string_in_dir(dir, "img_*.txt", "old tag", "new tag").await?;
+    assert_eq!(modified, 2);
+    assert_eq!(
+        fs::read_to_string(dir.join("img_001.txt")).await?,
+        "new tag"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("IMG_002.TXT")).await?,
+        "new tag"
+    );
+    assert_eq!(fs::read_to_string(dir.join("other.txt")).await?, "old tag");
+    assert_eq!(
+        fs::read_to_string(dir.join("img_003.caption")).await?,
+        "old tag"
+    );
+
+    // `?` matches a single character, a bare extension still works
+    let modified = replace_string_in_dir(dir, "img_00?.caption", "old tag", "new tag").await?;
+    assert_eq!(modified, 1);
+    let modified = replace_string_in_dir(dir, "txt", "new tag", "final tag").await?;
+    assert_eq!(modified, 2);
+    assert_eq!(fs::read_to_string(dir.join("other.txt")).await?, "old tag");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replace_string_case_insensitive() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = temp_dir.path().join("test_case.txt");
+    fs::write(&file_path, "Photo of a cat. A photo, a PHOTO ($1).").await?;
+
+    // All casings are replaced in a single call, surrounding text is untouched
+    replace_string_case_insensitive(&file_path, "photo", "picture").await?;
+    let content = fs::read_to_string(&file_path).await?;
+    assert_eq!(content, "picture of a cat. A picture, a picture ($1).");
+
+    // Regex metacharacters in the search string are matched literally
+    replace_string_case_insensitive(&file_path, " ($1)", "").await?;
+    let content = fs::read_to_string(&file_path).await?;
+    assert_eq!(content, "picture of a cat. A picture, a picture.");
+
+    // Empty search string leaves the file unchanged
+    replace_string_case_insensitive(&file_path, "", "x").await?;
+    let after = fs::read_to_string(&file_path).await?;
+    assert_eq!(after, content);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replace_regex() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = temp_dir.path().join("test_regex.txt");
+    fs::write(
+        &file_path,
+        "wolf, 2023, by artist_one, 1999, red background",
+    )
+    .await?;
+
+    // Strip year tags; an empty replacement also formats the content
+    replace_regex(&file_path, r"\b\d{4},\s*", "").await?;
+    let content = fs::read_to_string(&file_path).await?;
+    assert_eq!(content, "wolf, by artist_one, red background");
+
+    // Capture group references are expanded in the replacement
+    replace_regex(&file_path, r"by (\w+)", "$1 (artist)").await?;
+    let content = fs::read_to_string(&file_path).await?;
+    assert_eq!(content, "wolf, artist_one (artist), red background");
+
+    // Invalid patterns are reported as errors and leave the file untouched
+    assert!(replace_regex(&file_path, r"(unclosed", "x").await.is_err());
+    let after = fs::read_to_string(&file_path).await?;
+    assert_eq!(after, content);
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_replace_special_chars() -> anyhow::Result<()> {
     let temp_dir = TempDir::new()?;
@@ -236,3 +389,904 @@ async fn test_process_json_to_caption() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_find_unescaped_parens() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+
+    // Correctly escaped caption should not be reported
+    fs::write(
+        temp_dir.path().join("good.txt"),
+        "tag1, reflector \\(object\\), tag2",
+    )
+    .await?;
+
+    // Manually edited caption with raw and half-escaped parentheses
+    fs::write(
+        temp_dir.path().join("bad.txt"),
+        "tag1, reflector (object), painting \\(artwork), tag2",
+    )
+    .await?;
+
+    // Files with other extensions are ignored
+    fs::write(temp_dir.path().join("other.json"), "tag (raw)").await?;
+
+    let results = find_unescaped_parens(temp_dir.path(), "txt").await?;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].0, temp_dir.path().join("bad.txt"));
+    assert_eq!(
+        results[0].1,
+        vec!["reflector (object)", "painting \\(artwork)"]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_process_json_to_caption_with_config() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = temp_dir.path().join("config_tags.json");
+    let caption_path = file_path.with_extension("txt");
+
+    let json_data = json!({
+        "b_tag": 0.9,
+        "a_tag": 0.4,
+        "c (paren)": 0.6,
+        "d_tag": 0.3,
+        "e_tag": 0.1
+    });
+    fs::write(&file_path, serde_json::to_string_pretty(&json_data)?).await?;
+
+    // Top-k is applied after threshold filtering and sorting
+    let config = JsonCaptionConfig::new()
+        .with_threshold(0.35)
+        .with_top_k(Some(2));
+    process_json_to_caption_with_config(&file_path, &config).await?;
+    assert_eq!(
+        fs::read_to_string(&caption_path).await?,
+        "b_tag, c \\(paren\\)"
+    );
+
+    // Without probability sorting, tags are sorted alphabetically
+    let config = JsonCaptionConfig::new()
+        .with_separator(" | ")
+        .with_escape_parens(false)
+        .with_sort_by_probability(false);
+    process_json_to_caption_with_config(&file_path, &config).await?;
+    assert_eq!(
+        fs::read_to_string(&caption_path).await?,
+        "a_tag | b_tag | c (paren) | d_tag"
+    );
+
+    // Top-k keeps the most probable tags even when the least probable sorts first
+    let json_data = json!({"aaa": 0.25, "zzz": 0.9, "mmm": 0.5});
+    fs::write(&file_path, serde_json::to_string_pretty(&json_data)?).await?;
+    let config = config.with_top_k(Some(2));
+    process_json_to_caption_with_config(&file_path, &config).await?;
+    assert_eq!(fs::read_to_string(&caption_path).await?, "mmm | zzz");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_process_json_to_caption_grouped() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = temp_dir.path().join("wd14.json");
+    let caption_path = file_path.with_extension("txt");
+
+    // wd14-style grouped tagger output
+    let json_data = json!({
+        "rating": {"general": 0.95, "sensitive": 0.04},
+        "general": {"1girl": 0.98, "solo": 0.9, "outdoors": 0.3, "tree": 0.1},
+        "character": {"hatsune_miku": 0.85, "megurine_luka": 0.25}
+    });
+    fs::write(&file_path, serde_json::to_string_pretty(&json_data)?).await?;
+
+    // Groups are flattened with the global threshold
+    process_json_to_caption(&file_path).await?;
+    assert_eq!(
+        fs::read_to_string(&caption_path).await?,
+        "1girl, general, solo, hatsune_miku, outdoors, megurine_luka"
+    );
+
+    // Per-group thresholds drop ratings and low-confidence characters
+    let mut thresholds = std::collections::HashMap::new();
+    thresholds.insert("rating".to_string(), 1.1);
+    thresholds.insert("character".to_string(), 0.5);
+    let config = JsonCaptionConfig::new().with_group_thresholds(Some(thresholds));
+    process_json_to_caption_with_config(&file_path, &config).await?;
+    assert_eq!(
+        fs::read_to_string(&caption_path).await?,
+        "1girl, solo, hatsune_miku, outdoors"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_normalize_caption_extension() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    fs::write(dir.join("a.caption"), "caption a").await?;
+    fs::write(dir.join("b.CAP"), "caption b").await?;
+    fs::write(dir.join("c.txt"), "existing c").await?;
+    fs::write(dir.join("c.cap"), "colliding c").await?;
+
+    let from_exts = vec!["caption".to_string(), "cap".to_string()];
+
+    // Dry run reports the renames without touching the files
+    let renamed = normalize_caption_extension(dir, &from_exts, "txt", true).await?;
+    assert_eq!(renamed, 2);
+    assert!(dir.join("a.caption").exists());
+    assert!(!dir.join("a.txt").exists());
+
+    let renamed = normalize_caption_extension(dir, &from_exts, "txt", false).await?;
+    assert_eq!(renamed, 2);
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).await?, "caption a");
+    assert_eq!(fs::read_to_string(dir.join("b.txt")).await?, "caption b");
+
+    // Collisions are skipped and the existing file is preserved
+    assert_eq!(fs::read_to_string(dir.join("c.txt")).await?, "existing c");
+    assert!(dir.join("c.cap").exists());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_prepend_trigger() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = temp_dir.path().join("test.txt");
+    fs::write(&file_path, "tag1, my_lora_style, tag2").await?;
+
+    // Inserted once, even when run twice
+    prepend_trigger(&file_path, "my_lora", ", ").await?;
+    prepend_trigger(&file_path, "my_lora", ", ").await?;
+    assert_eq!(
+        fs::read_to_string(&file_path).await?,
+        "my_lora, tag1, my_lora_style, tag2"
+    );
+
+    // Already present elsewhere in the caption
+    fs::write(&file_path, "tag1, my_lora").await?;
+    prepend_trigger(&file_path, "my_lora", ", ").await?;
+    assert_eq!(fs::read_to_string(&file_path).await?, "tag1, my_lora");
+
+    // Surrounding whitespace is ignored, and a trailing sentence is kept
+    fs::write(&file_path, "tag1., A my_lora sentence.\n").await?;
+    prepend_trigger(&file_path, " my_lora ", ", ").await?;
+    prepend_trigger(&file_path, "my_lora", ", ").await?;
+    assert_eq!(
+        fs::read_to_string(&file_path).await?,
+        "my_lora, tag1., A my_lora sentence.\n"
+    );
+
+    // Directory-wide variant only counts modified files
+    let nested = temp_dir.path().join("nested");
+    fs::create_dir(&nested).await?;
+    fs::write(nested.join("a.txt"), "tag1").await?;
+    fs::write(nested.join("b.txt"), "").await?;
+    fs::write(nested.join("c.wd"), "tag1").await?;
+    assert_eq!(
+        prepend_trigger_in_dir(temp_dir.path(), "txt", "my_lora", ", ").await?,
+        2
+    );
+    assert_eq!(
+        fs::read_to_string(nested.join("a.txt")).await?,
+        "my_lora, tag1"
+    );
+    assert_eq!(fs::read_to_string(nested.join("b.txt")).await?, "my_lora");
+    assert_eq!(fs::read_to_string(nested.join("c.wd")).await?, "tag1");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_strip_trigger() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = temp_dir.path().join("test.txt");
+
+    // Whole-tag matches are removed wherever they appear
+    fs::write(&file_path, "cat, cathedral, tag1,cat").await?;
+    assert!(strip_trigger(&file_path, "cat", ", ").await?);
+    assert_eq!(fs::read_to_string(&file_path).await?, "cathedral, tag1");
+
+    // No match leaves the file untouched
+    fs::write(&file_path, "cathedral,tag1").await?;
+    assert!(!strip_trigger(&file_path, "cat", ", ").await?);
+    assert_eq!(fs::read_to_string(&file_path).await?, "cathedral,tag1");
+
+    // Undoes prepend_trigger, keeping the sentence and trailing newline
+    fs::write(&file_path, "tag1., A cat, sleeping\n").await?;
+    prepend_trigger(&file_path, " cat ", ", ").await?;
+    assert!(strip_trigger(&file_path, " cat ", ", ").await?);
+    assert_eq!(
+        fs::read_to_string(&file_path).await?,
+        "tag1., A cat, sleeping\n"
+    );
+
+    // The last tag before the sentence is matched too
+    fs::write(&file_path, "tag1, cat., A sentence.").await?;
+    assert!(strip_trigger(&file_path, "cat", ", ").await?);
+    assert_eq!(fs::read_to_string(&file_path).await?, "tag1., A sentence.");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_normalize_tags() -> anyhow::Result<()> {
+    assert_eq!(normalize_tag("  Long_Hair\t", true), "long hair");
+    assert_eq!(normalize_tag("Long_Hair", false), "long_hair");
+    assert_eq!(normalize_tag("blue \n  EYES", false), "blue eyes");
+
+    let temp_dir = TempDir::new()?;
+    let file_path = temp_dir.path().join("test.txt");
+
+    // Normalized duplicates are dropped, keeping the first occurrence
+    fs::write(&file_path, "Long_Hair, blue  eyes,long hair, Solo").await?;
+    assert!(normalize_tags_in_file(&file_path, ", ", true).await?);
+    assert_eq!(
+        fs::read_to_string(&file_path).await?,
+        "long hair, blue eyes, solo"
+    );
+
+    // Already normalized files are left untouched
+    assert!(!normalize_tags_in_file(&file_path, ", ", true).await?);
+
+    // Only the tags are normalized; the sentence and trailing newline are kept
+    fs::write(&file_path, "Long_Hair, SOLO., A Fox named Max_Power.\n").await?;
+    assert!(normalize_tags_in_file(&file_path, ", ", true).await?);
+    assert_eq!(
+        fs::read_to_string(&file_path).await?,
+        "long hair, solo., A Fox named Max_Power.\n"
+    );
+    assert!(!normalize_tags_in_file(&file_path, ", ", true).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_find_missing_captions() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    fs::create_dir(dir.join("sub")).await?;
+    fs::write(dir.join("captioned.png"), "").await?;
+    fs::write(dir.join("captioned.txt"), "tag1, tag2").await?;
+    fs::write(dir.join("empty.jpg"), "").await?;
+    fs::write(dir.join("empty.txt"), "  \n").await?;
+    fs::write(dir.join("sub/missing.PNG"), "").await?;
+    fs::write(dir.join("ignored.gif"), "").await?;
+
+    let missing = find_missing_captions(dir, &["png", "jpg"], "txt").await?;
+    assert_eq!(
+        missing,
+        vec![dir.join("empty.jpg"), dir.join("sub/missing.PNG")]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_find_orphan_captions() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    fs::create_dir(dir.join("sub")).await?;
+    fs::write(dir.join("kept.JPG"), "").await?;
+    fs::write(dir.join("kept.txt"), "tags").await?;
+    fs::write(dir.join("kept.caption"), "caption").await?;
+    fs::write(dir.join("culled.txt"), "tags").await?;
+    fs::write(dir.join("sub/culled.caption"), "caption").await?;
+    // An image in another directory doesn't count
+    fs::write(dir.join("sub/kept.png"), "").await?;
+    fs::write(dir.join("notes.md"), "not a caption").await?;
+
+    let orphans = find_orphan_captions(dir, &["png", "jpg"], &["txt", "caption"]).await?;
+    assert_eq!(
+        orphans,
+        vec![dir.join("culled.txt"), dir.join("sub/culled.caption")]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_apply_tag_map_file() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    let map_path = dir.join("map.csv");
+    fs::write(
+        &map_path,
+        "canine,dog\nwatermark,\n\n\"smile, open mouth\",smile\nkitty,cat\n",
+    )
+    .await?;
+    fs::write(dir.join("a.txt"), "canine, watermark, dog, cat").await?;
+    fs::write(dir.join("b.txt"), "kitty, canines").await?;
+    fs::write(dir.join("c.txt"), "solo, forest").await?;
+    fs::write(dir.join("d.tags"), "canine").await?;
+
+    assert_eq!(apply_tag_map_file(dir, &map_path, "txt", ", ").await?, 2);
+    // Renames that create duplicates are deduplicated and empty mappings delete
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).await?, "dog, cat");
+    // Only whole tags match
+    assert_eq!(fs::read_to_string(dir.join("b.txt")).await?, "cat, canines");
+    assert_eq!(fs::read_to_string(dir.join("c.txt")).await?, "solo, forest");
+    assert_eq!(fs::read_to_string(dir.join("d.tags")).await?, "canine");
+
+    // The last tag before a sentence is remapped, and the sentence is kept
+    fs::write(
+        dir.join("e.txt"),
+        "solo, canine., A canine, kitty and dog\n",
+    )
+    .await?;
+    assert_eq!(apply_tag_map_file(dir, &map_path, "txt", ", ").await?, 1);
+    assert_eq!(
+        fs::read_to_string(dir.join("e.txt")).await?,
+        "solo, dog., A canine, kitty and dog\n"
+    );
+
+    // Malformed rows are rejected
+    fs::write(&map_path, "a,b,c\n").await?;
+    let err = apply_tag_map_file(dir, &map_path, "txt", ", ")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Line 1"));
+
+    // Quoted fields may span lines, and line numbers account for them
+    fs::write(&map_path, "\"multi\nline\",tag\na,b,c\n").await?;
+    let err = apply_tag_map_file(dir, &map_path, "txt", ", ")
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Line 3"), "{err}");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_remove_matching_tags() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    fs::write(dir.join("a.txt"), "2023, 2023_art, wolf").await?;
+    fs::write(dir.join("b.txt"), "fox, snow").await?;
+    fs::write(dir.join("c.txt"), "some_name_(artist), fox").await?;
+
+    assert_eq!(remove_matching_tags(dir, "txt", r"\d{4}", ", ").await?, 1);
+    assert_eq!(
+        fs::read_to_string(dir.join("a.txt")).await?,
+        "2023_art, wolf"
+    );
+    assert_eq!(fs::read_to_string(dir.join("b.txt")).await?, "fox, snow");
+
+    // Alternations are anchored as a whole
+    assert_eq!(
+        remove_matching_tags(dir, "txt", r".*_\(artist\)|snow", ", ").await?,
+        2
+    );
+    assert_eq!(fs::read_to_string(dir.join("b.txt")).await?, "fox");
+    assert_eq!(fs::read_to_string(dir.join("c.txt")).await?, "fox");
+
+    assert!(remove_matching_tags(dir, "txt", "(", ", ").await.is_err());
+
+    // Only the tags are matched; the sentence and trailing newline are kept
+    fs::write(dir.join("d.txt"), "wolf, 2023., Painted in 1999\n").await?;
+    assert_eq!(
+        remove_matching_tags(dir, "txt", r"\d{4}|Painted.*", ", ").await?,
+        1
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("d.txt")).await?,
+        "wolf., Painted in 1999\n"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ensure_tag() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    fs::write(dir.join("a.txt"), "wolf, forest").await?;
+    fs::write(dir.join("b.txt"), "solo, wolf").await?;
+    fs::write(dir.join("c.txt"), "solo wolf").await?;
+    fs::write(dir.join("d.txt"), "").await?;
+
+    assert_eq!(
+        ensure_tag(dir, "txt", "solo", TagPosition::Append, ", ").await?,
+        3
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("a.txt")).await?,
+        "wolf, forest, solo"
+    );
+    assert_eq!(fs::read_to_string(dir.join("b.txt")).await?, "solo, wolf");
+    // Partial matches don't count
+    assert_eq!(
+        fs::read_to_string(dir.join("c.txt")).await?,
+        "solo wolf, solo"
+    );
+    assert_eq!(fs::read_to_string(dir.join("d.txt")).await?, "solo");
+
+    // Idempotent
+    assert_eq!(
+        ensure_tag(dir, "txt", "solo", TagPosition::Append, ", ").await?,
+        0
+    );
+
+    assert_eq!(
+        ensure_tag(dir, "txt", "wolf", TagPosition::Prepend, ", ").await?,
+        2
+    );
+    assert_eq!(fs::read_to_string(dir.join("d.txt")).await?, "wolf, solo");
+
+    // Tags go before a trailing sentence, which is never mistaken for tags
+    let dir = &dir.join("sentences");
+    fs::create_dir(dir).await?;
+    fs::write(dir.join("e.txt"), "wolf, solo., A wolf, alone\n").await?;
+    assert_eq!(
+        ensure_tag(dir, "txt", "forest", TagPosition::Append, ", ").await?,
+        1
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("e.txt")).await?,
+        "wolf, solo, forest., A wolf, alone\n"
+    );
+    assert_eq!(
+        ensure_tag(dir, "txt", "solo", TagPosition::Prepend, ", ").await?,
+        0
+    );
+    assert_eq!(
+        ensure_tag(dir, "txt", "alone", TagPosition::Prepend, ", ").await?,
+        1
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("e.txt")).await?,
+        "alone, wolf, solo, forest., A wolf, alone\n"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_length_histogram() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+
+    let stats = length_histogram(dir, "txt").await?;
+    assert_eq!(stats.files, 0);
+    assert!(stats.chars.mean.abs() < f64::EPSILON);
+
+    fs::write(dir.join("a.txt"), "a, b").await?;
+    fs::write(dir.join("b.txt"), "  abc, de, f  ").await?;
+    fs::write(dir.join("c.txt"), "a long caption without commas").await?;
+    fs::write(dir.join("d.txt"), "x,y,z,w").await?;
+    fs::write(dir.join("e.tags"), "ignored").await?;
+
+    let stats = length_histogram(dir, "txt").await?;
+    assert_eq!(stats.files, 4);
+    assert_eq!((stats.chars.min, stats.chars.max), (4, 29));
+    assert!((stats.chars.median - 8.5).abs() < f64::EPSILON);
+    assert!((stats.chars.mean - 12.5).abs() < f64::EPSILON);
+    assert_eq!((stats.tags.min, stats.tags.max), (1, 4));
+    assert!((stats.tags.median - 2.5).abs() < f64::EPSILON);
+    assert!((stats.tags.mean - 2.5).abs() < f64::EPSILON);
+
+    let json = serde_json::to_value(stats)?;
+    assert_eq!(json["tags"]["max"], 4);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_find_near_duplicates() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    fs::write(dir.join("a.txt"), "wolf, forest, snow, night").await?;
+    fs::write(dir.join("b.txt"), "night,snow, forest, wolf").await?;
+    fs::write(dir.join("c.txt"), "wolf, forest, snow, day").await?;
+    fs::write(dir.join("d.txt"), "cat, indoors").await?;
+    fs::write(dir.join("e.txt"), "").await?;
+
+    let pairs = find_near_duplicates(dir, "txt", 0.6).await?;
+    assert_eq!(pairs.len(), 3);
+    assert_eq!(pairs[0], (dir.join("a.txt"), dir.join("b.txt"), 1.0));
+    // 3 shared tags out of 5 distinct
+    assert_eq!(pairs[1], (dir.join("a.txt"), dir.join("c.txt"), 0.6));
+    assert_eq!(pairs[2], (dir.join("b.txt"), dir.join("c.txt"), 0.6));
+
+    assert_eq!(find_near_duplicates(dir, "txt", 1.0).await?.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cooccurrence() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    fs::write(dir.join("a.txt"), "wolf, forest, snow, wolf").await?;
+    fs::write(dir.join("b.txt"), "forest, wolf").await?;
+    fs::write(dir.join("c.txt"), "solo").await?;
+
+    let pair = |a: &str, b: &str| (a.to_string(), b.to_string());
+    let counts = cooccurrence(dir, "txt", ", ").await?;
+    // Self-pairs are skipped and repeated tags count once per file
+    assert_eq!(counts.len(), 3);
+    assert_eq!(counts[&pair("forest", "wolf")], 2);
+    assert_eq!(counts[&pair("forest", "snow")], 1);
+    assert_eq!(counts[&pair("snow", "wolf")], 1);
+
+    let csv_path = dir.join("pairs.csv");
+    write_cooccurrence_csv(&counts, &csv_path).await?;
+    assert_eq!(
+        fs::read_to_string(&csv_path).await?,
+        "tag_a,tag_b,count\nforest,wolf,2\nforest,snow,1\nsnow,wolf,1\n"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_split_to_sidecars() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+
+    fs::write(dir.join("a.txt"), "wolf, forest., A wolf in a forest.\n").await?;
+    split_to_sidecars(&dir.join("a.txt"), "tags", "caption", false).await?;
+    assert_eq!(
+        fs::read_to_string(dir.join("a.tags")).await?,
+        "wolf, forest"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("a.caption")).await?,
+        "A wolf in a forest."
+    );
+
+    // Without a sentence the caption sidecar is skipped unless requested
+    fs::write(dir.join("b.txt"), "wolf, forest").await?;
+    split_to_sidecars(&dir.join("b.txt"), "tags", "caption", false).await?;
+    assert_eq!(
+        fs::read_to_string(dir.join("b.tags")).await?,
+        "wolf, forest"
+    );
+    assert!(!dir.join("b.caption").exists());
+    split_to_sidecars(&dir.join("b.txt"), "tags", "caption", true).await?;
+    assert_eq!(fs::read_to_string(dir.join("b.caption")).await?, "");
+
+    // The source file is never overwritten
+    assert!(
+        split_to_sidecars(&dir.join("b.txt"), "txt", "caption", true)
+            .await
+            .is_err()
+    );
+    assert_eq!(fs::read_to_string(dir.join("b.txt")).await?, "wolf, forest");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_txt_to_json() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    let json_path = dir.join("a.json");
+
+    fs::write(dir.join("a.txt"), "wolf, forest., A wolf in a forest.").await?;
+    txt_to_json(&dir.join("a.txt"), &json_path).await?;
+    let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&json_path).await?)?;
+    assert_eq!(
+        json,
+        json!({"caption": "A wolf in a forest.", "tags": ["wolf", "forest"]})
+    );
+    // Round-trips back to the original text
+    json_to_txt(&json_path, &dir.join("a2.txt")).await?;
+    assert_eq!(
+        fs::read_to_string(dir.join("a2.txt")).await?,
+        "wolf, forest., A wolf in a forest."
+    );
+
+    fs::write(dir.join("c.txt"), "wolf, forest").await?;
+    txt_to_json(&dir.join("c.txt"), &json_path).await?;
+    json_to_txt(&json_path, &dir.join("c2.txt")).await?;
+    assert_eq!(
+        fs::read_to_string(dir.join("c2.txt")).await?,
+        "wolf, forest"
+    );
+
+    fs::write(dir.join("b.txt"), "").await?;
+    txt_to_json(&dir.join("b.txt"), &json_path).await?;
+    let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&json_path).await?)?;
+    assert_eq!(json, json!({"caption": "", "tags": []}));
+    json_to_txt(&json_path, &dir.join("b2.txt")).await?;
+    assert_eq!(fs::read_to_string(dir.join("b2.txt")).await?, "");
+
+    // Caption-only JSON is read like json_to_text
+    fs::write(&json_path, r#"{"caption": "A wolf."}"#).await?;
+    json_to_txt(&json_path, &dir.join("d.txt")).await?;
+    assert_eq!(fs::read_to_string(dir.join("d.txt")).await?, "A wolf.");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_merge_caption_files() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let a = temp_dir.path().join("image.wd");
+    let b = temp_dir.path().join("image.florence");
+    let out = temp_dir.path().join("image.txt");
+
+    // Pure tags merged with pure prose
+    fs::write(&a, "tag2, tag1, tag2").await?;
+    fs::write(&b, "A cat sitting on a chair.").await?;
+    merge_caption_files(&a, &b, &out, ", ", true).await?;
+    assert_eq!(
+        fs::read_to_string(&out).await?,
+        "tag2, tag1, A cat sitting on a chair."
+    );
+
+    // Prose files are never split into tags, even when they end without punctuation
+    fs::write(&b, "a cat, sitting on a chair").await?;
+    merge_caption_files(&a, &b, &out, ", ", true).await?;
+    assert_eq!(
+        fs::read_to_string(&out).await?,
+        "tag2, tag1, a cat, sitting on a chair"
+    );
+
+    // Tag files ending in punctuation are still tags
+    let tags = temp_dir.path().join("image.tags");
+    fs::write(&tags, "tag3, jr.").await?;
+    merge_caption_files(&a, &tags, &out, ", ", true).await?;
+    assert_eq!(fs::read_to_string(&out).await?, "tag2, tag1, tag3, jr.");
+
+    // Both files with tags and sentences, file `a` first
+    fs::write(&a, "tag1, tag2., First sentence.").await?;
+    fs::write(&tags, "tag2, tag3., Second sentence.").await?;
+    merge_caption_files(&a, &tags, &out, ", ", true).await?;
+    assert_eq!(
+        fs::read_to_string(&out).await?,
+        "tag1, tag2, tag3, First sentence. Second sentence."
+    );
+
+    // Without deduplication every tag is kept
+    merge_caption_files(&a, &tags, &out, ", ", false).await?;
+    assert_eq!(
+        fs::read_to_string(&out).await?,
+        "tag1, tag2, tag2, tag3, First sentence. Second sentence."
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_caption_stats() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let file_path = temp_dir.path().join("test.txt");
+    let content = " tag1, tag two,, , A short caption. \n";
+    fs::write(&file_path, content).await?;
+
+    let stats = caption_stats(&file_path).await?;
+    assert_eq!(
+        stats,
+        CaptionStats {
+            char_count: 34,
+            word_count: 7,
+            tag_count: 3,
+        }
+    );
+
+    // The file is left untouched
+    assert_eq!(fs::read_to_string(&file_path).await?, content);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tag_frequency() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    fs::write(dir.join("a.txt"), "wolf, forest, wolf").await?;
+    fs::write(dir.join("b.txt"), "wolf, snow, \"quoted\" tag").await?;
+    fs::write(dir.join("c.wd"), "forest, forest").await?;
+
+    // Tags are counted once per file and other extensions are ignored
+    let counts = tag_frequency(dir, "txt", ", ").await?;
+    assert_eq!(counts.len(), 4);
+    assert_eq!(counts["wolf"], 2);
+    assert_eq!(counts["forest"], 1);
+    assert_eq!(counts["snow"], 1);
+
+    // CSV output is sorted by count, then alphabetically
+    let csv_path = dir.join("counts.csv");
+    write_tag_frequency_csv(&counts, &csv_path).await?;
+    assert_eq!(
+        fs::read_to_string(&csv_path).await?,
+        "tag,count\nwolf,2\n\"\"\"quoted\"\" tag\",1\nforest,1\nsnow,1\n"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_import_from_csv() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    let csv_path = dir.join("captions.csv");
+    let out_dir = dir.join("out");
+
+    fs::write(
+        &csv_path,
+        "filename,caption\nimg001,\"A wolf, standing in snow\"\nimg002,\"Said \"\"hi\"\"\"\n",
+    )
+    .await?;
+    assert_eq!(import_from_csv(&csv_path, &out_dir, "txt", false).await?, 2);
+    assert_eq!(
+        fs::read_to_string(out_dir.join("img001.txt")).await?,
+        "A wolf, standing in snow"
+    );
+    assert_eq!(
+        fs::read_to_string(out_dir.join("img002.txt")).await?,
+        "Said \"hi\""
+    );
+
+    // Existing files are only replaced with overwrite
+    assert!(
+        import_from_csv(&csv_path, &out_dir, "txt", false)
+            .await
+            .is_err()
+    );
+    assert_eq!(import_from_csv(&csv_path, &out_dir, "txt", true).await?, 2);
+
+    // Duplicate filenames within the CSV
+    fs::write(&csv_path, "a,first\na,second\n").await?;
+    let err = import_from_csv(&csv_path, &dir.join("dup"), "txt", false)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Line 2"));
+    assert!(!dir.join("dup").exists());
+    assert_eq!(
+        import_from_csv(&csv_path, &dir.join("dup"), "txt", true).await?,
+        1
+    );
+    assert_eq!(fs::read_to_string(dir.join("dup/a.txt")).await?, "second");
+
+    // Filenames can't escape the output directory
+    fs::write(&csv_path, "../escape,caption\n").await?;
+    assert!(
+        import_from_csv(&csv_path, &out_dir, "txt", true)
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_export_dir_to_jsonl() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path().join("dataset");
+    fs::create_dir_all(dir.join("sub")).await?;
+    fs::write(dir.join("a.txt"), "wolf, forest\n").await?;
+    fs::write(dir.join("sub/b.txt"), "A \"quoted\" caption").await?;
+    fs::write(dir.join("c.json"), "{}").await?;
+
+    let out = temp_dir.path().join("captions.jsonl");
+    assert_eq!(export_dir_to_jsonl(&dir, "txt", &out).await?, 2);
+
+    let content = fs::read_to_string(&out).await?;
+    let records: Vec<serde_json::Value> = content
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<_, _>>()?;
+    assert_eq!(
+        records,
+        vec![
+            serde_json::json!({"file": "a.txt", "caption": "wolf, forest"}),
+            serde_json::json!({"file": "sub/b.txt", "caption": "A \"quoted\" caption"}),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_fix_mojibake() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().join("caption.txt");
+
+    fs::write(&path, "cafÃ© Ã\u{a0} la mode, itâ€™s a naÃ¯ve ðŸ\u{90}º").await?;
+    assert!(fix_mojibake(&path).await?);
+    assert_eq!(
+        fs::read_to_string(&path).await?,
+        "café à la mode, it’s a naïve 🐺"
+    );
+
+    // Already repaired and legitimately accented text is left alone
+    assert!(!fix_mojibake(&path).await?);
+    fs::write(&path, "Pokémon, «déjà vu», Ærøskøbing, 5°C").await?;
+    assert!(!fix_mojibake(&path).await?);
+
+    // Text mis-decoded twice is repaired fully
+    fs::write(&path, "cafÃƒÂ©").await?;
+    assert!(fix_mojibake(&path).await?);
+    assert_eq!(fs::read_to_string(&path).await?, "café");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_diff_tags() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let a = temp_dir.path().join("a.txt");
+    let b = temp_dir.path().join("b.txt");
+
+    fs::write(&a, "wolf, forest, night, wolf., A wolf at night.").await?;
+    fs::write(&b, "night, snow, wolf, moon., A wolf at night.").await?;
+    let diff = diff_tags(&a, &b, ", ").await?;
+    assert_eq!(
+        diff,
+        TagDiff {
+            added: vec!["snow".to_string(), "moon".to_string()],
+            removed: vec!["forest".to_string()],
+            unchanged: vec!["wolf".to_string(), "night".to_string()],
+            sentence_changed: false,
+        }
+    );
+    assert!(!diff.is_empty());
+
+    fs::write(&b, "wolf, forest, night., A wolf in the snow.").await?;
+    let diff = diff_tags(&a, &b, ", ").await?;
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+    assert!(diff.sentence_changed);
+
+    assert!(TagDiff::between("wolf, night", "night,wolf", ", ").is_empty());
+
+    // Tags ending in punctuation are not mistaken for prose
+    let diff = TagDiff::between("wolf, forest", "wolf, forest, jr.", ", ");
+    assert_eq!(diff.added, ["jr."]);
+    assert!(diff.removed.is_empty());
+    assert!(!diff.sentence_changed);
+    assert!(
+        diff_tags(&a, &temp_dir.path().join("missing.txt"), ", ")
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tag_set_ops() -> anyhow::Result<()> {
+    let a = ["wolf", "night", "wolf", "moon"];
+    let b = ["moon", "snow", "wolf", "snow"];
+    assert_eq!(tag_union(&a, &b), ["wolf", "night", "moon", "snow"]);
+    assert_eq!(tag_intersection(&a, &b), ["wolf", "moon"]);
+    assert_eq!(tag_difference(&a, &b), ["night"]);
+    assert_eq!(tag_difference(&b, &a), ["snow"]);
+    assert!(tag_intersection(&a, &[]).is_empty());
+
+    let temp_dir = TempDir::new()?;
+    let wd = temp_dir.path().join("image.wd");
+    let tags = temp_dir.path().join("image.tags");
+    fs::write(&wd, "wolf, night,moon").await?;
+    fs::write(&tags, "moon, snow").await?;
+    assert_eq!(
+        tag_set_op_files(&wd, &tags, ", ", TagSetOp::Difference).await?,
+        ["wolf", "night"]
+    );
+    assert_eq!(
+        tag_set_op_files(&wd, &tags, ", ", TagSetOp::Union).await?,
+        ["wolf", "night", "moon", "snow"]
+    );
+    assert_eq!(
+        tag_set_op_files(&wd, &tags, ", ", TagSetOp::Intersection).await?,
+        ["moon"]
+    );
+
+    // Sentences aren't tags, and the last tag before one still matches
+    fs::write(&tags, "snow, wolf., A wolf, night\n").await?;
+    assert_eq!(
+        tag_set_op_files(&wd, &tags, ", ", TagSetOp::Intersection).await?,
+        ["wolf"]
+    );
+    assert_eq!(
+        tag_set_op_files(&tags, &wd, ", ", TagSetOp::Difference).await?,
+        ["snow"]
+    );
+
+    Ok(())
+}
@@ -0,0 +1,275 @@
+#![warn(clippy::all, clippy::pedantic)]
+
+use crate::{
+    ProcessSummary, format_json_file, format_json_file_sorted, format_json_file_with_indent,
+    process_directory, process_json_file, process_json_file_ref,
+    process_safetensors_file_incremental, rename_file_without_image_extension,
+    rename_file_without_image_extension_with, split_content, split_content_with,
+};
+use serde_json::{Value, json};
+use tempfile::TempDir;
+use tokio::fs;
+
+#[tokio::test]
+async fn test_rename_file_without_image_extension() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+
+    for name in ["a.jpg.toml", "b.WEBP.txt", "c.jxl.json", "photo.png"] {
+        fs::write(dir.join(name), name).await?;
+        rename_file_without_image_extension(&dir.join(name)).await?;
+    }
+    assert_eq!(fs::read_to_string(dir.join("a.toml")).await?, "a.jpg.toml");
+    assert_eq!(fs::read_to_string(dir.join("b.txt")).await?, "b.WEBP.txt");
+    assert_eq!(fs::read_to_string(dir.join("c.json")).await?, "c.jxl.json");
+    // Actual images are left alone
+    assert!(dir.join("photo.png").exists());
+
+    // A custom set replaces the defaults
+    fs::write(dir.join("d.heic.txt"), "d").await?;
+    fs::write(dir.join("e.jpg.txt"), "e").await?;
+    rename_file_without_image_extension_with(&dir.join("d.heic.txt"), &["heic"], false).await?;
+    rename_file_without_image_extension_with(&dir.join("e.jpg.txt"), &["heic"], false).await?;
+    assert!(dir.join("d.txt").exists());
+    assert!(dir.join("e.jpg.txt").exists());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rename_file_without_image_extension_collision() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let source = temp_dir.path().join("image.jpg.txt");
+    let target = temp_dir.path().join("image.txt");
+    fs::write(&source, "new").await?;
+    fs::write(&target, "existing").await?;
+
+    // Refuses to clobber the existing file by default
+    let err = rename_file_without_image_extension(&source)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    assert_eq!(fs::read_to_string(&source).await?, "new");
+    assert_eq!(fs::read_to_string(&target).await?, "existing");
+
+    rename_file_without_image_extension_with(&source, &["jpg"], true).await?;
+    assert!(!source.exists());
+    assert_eq!(fs::read_to_string(&target).await?, "new");
+
+    Ok(())
+}
+
+#[test]
+fn test_split_content_with() {
+    // Only the first delimiter splits
+    let (tags, sentence) = split_content("tag1, tag2., First sentence., Second.");
+    assert_eq!(tags, vec!["tag1", "tag2"]);
+    assert_eq!(sentence, "First sentence., Second.");
+
+    // split_content trims the sentence, split_content_with keeps it verbatim
+    let (_, sentence) = split_content("tag1., A sentence. \n");
+    assert_eq!(sentence, "A sentence.");
+    let (_, sentence) = split_content_with("tag1., A sentence. \n", "., ");
+    assert_eq!(sentence, "A sentence. \n");
+
+    // Without a delimiter everything is a tag
+    let (tags, sentence) = split_content("tag1, tag2, tag3.");
+    assert_eq!(tags, vec!["tag1", "tag2", "tag3."]);
+    assert_eq!(sentence, "");
+
+    // A custom delimiter keeps the trailing period on the last tag
+    let (tags, sentence) = split_content_with("tag1, tag3.\n  Indented sentence. ", "\n");
+    assert_eq!(tags, vec!["tag1", "tag3."]);
+    assert_eq!(sentence, "  Indented sentence. ");
+
+    let (tags, sentence) = split_content_with("tag1, tag2", "");
+    assert_eq!(tags, vec!["tag1", "tag2"]);
+    assert_eq!(sentence, "");
+}
+
+#[tokio::test]
+async fn test_process_json_file() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().join("data.json");
+    fs::write(
+        &path,
+        json!({"name": "luna", "tags": ["a", "b"]}).to_string(),
+    )
+    .await?;
+
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    process_json_file(&path, |data: Value| async move {
+        tx.send(data["name"].clone()).ok();
+        Ok(())
+    })
+    .await?;
+    assert_eq!(rx.await?, "luna");
+
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    process_json_file_ref(&path, |data: &Value| {
+        let tag_count = data["tags"].as_array().map_or(0, Vec::len);
+        async move {
+            tx.send(tag_count).ok();
+            Ok(())
+        }
+    })
+    .await?;
+    assert_eq!(rx.await?, 2);
+
+    // Processor errors are propagated
+    let result = process_json_file_ref(&path, |_: &Value| async {
+        Err(std::io::Error::other("processor failed"))
+    })
+    .await;
+    assert_eq!(result.unwrap_err().to_string(), "processor failed");
+
+    // Gzip-compressed files are decompressed, by extension or by header
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    std::io::Write::write_all(&mut encoder, br#"{"name": "gzipped"}"#)?;
+    let compressed = encoder.finish()?;
+    for name in ["data.json.gz", "disguised.json"] {
+        let gz_path = temp_dir.path().join(name);
+        fs::write(&gz_path, &compressed).await?;
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        process_json_file(&gz_path, |data: Value| async move {
+            tx.send(data["name"].clone()).ok();
+            Ok(())
+        })
+        .await?;
+        assert_eq!(rx.await?, "gzipped");
+    }
+
+    // Invalid JSON is rejected before the processor runs
+    fs::write(&path, "not json").await?;
+    assert!(
+        process_json_file(&path, |_: Value| async { Ok(()) })
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_format_json_file_sorted() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().join("meta.json");
+    fs::write(
+        &path,
+        r#"{"zeta": 1, "alpha": {"b": [3, 1, {"y": 0, "x": 0}], "a": null}}"#,
+    )
+    .await?;
+
+    format_json_file_sorted(path.clone()).await?;
+    let formatted = fs::read_to_string(&path).await?;
+    let position = |needle: &str| formatted.find(needle).unwrap();
+    assert!(position("\"alpha\"") < position("\"zeta\""));
+    assert!(position("\"a\"") < position("\"b\""));
+    assert!(position("\"x\"") < position("\"y\""));
+    // Arrays keep their order and the content is unchanged
+    assert!(position("3") < position("1"));
+    let reparsed: Value = serde_json::from_str(&formatted)?;
+    assert_eq!(
+        reparsed,
+        json!({"zeta": 1, "alpha": {"b": [3, 1, {"y": 0, "x": 0}], "a": null}})
+    );
+
+    // Formatting is idempotent
+    format_json_file_sorted(path.clone()).await?;
+    assert_eq!(fs::read_to_string(&path).await?, formatted);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_format_json_file_with_indent() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().join("data.json");
+    let data = json!({"outer": {"inner": [1]}});
+    fs::write(&path, data.to_string()).await?;
+
+    format_json_file_with_indent(path.clone(), "    ").await?;
+    let formatted = fs::read_to_string(&path).await?;
+    assert!(formatted.contains("\n    \"outer\": {\n        \"inner\": [\n            1"));
+
+    format_json_file_with_indent(path.clone(), "\t").await?;
+    assert!(fs::read_to_string(&path).await?.contains("\n\t\"outer\""));
+
+    // The default matches serde_json's pretty printer
+    format_json_file(path.clone()).await?;
+    assert_eq!(
+        fs::read_to_string(&path).await?,
+        serde_json::to_string_pretty(&data)?
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_process_safetensors_file_incremental() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().join("model.safetensors");
+    let json_path = path.with_extension("json");
+
+    // A safetensors file without tensors, only metadata
+    let header = br#"{"__metadata__":{"ss_output_name":"luna"}}"#;
+    let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+    bytes.extend_from_slice(header);
+    fs::write(&path, &bytes).await?;
+
+    assert!(process_safetensors_file_incremental(&path).await?);
+    let json: Value = serde_json::from_str(&fs::read_to_string(&json_path).await?)?;
+    assert_eq!(json["ss_output_name"], "luna");
+
+    // The sidecar is newer than the model, so nothing is re-extracted
+    fs::write(&json_path, "{}").await?;
+    assert!(!process_safetensors_file_incremental(&path).await?);
+    assert_eq!(fs::read_to_string(&json_path).await?, "{}");
+
+    // A stale sidecar is regenerated
+    let stale = std::time::SystemTime::now() - std::time::Duration::from_secs(100);
+    std::fs::File::options()
+        .write(true)
+        .open(&json_path)?
+        .set_modified(stale)?;
+    assert!(process_safetensors_file_incremental(&path).await?);
+    assert_ne!(fs::read_to_string(&json_path).await?, "{}");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_process_directory() -> anyhow::Result<()> {
+    let temp_dir = TempDir::new()?;
+    let dir = temp_dir.path();
+    fs::create_dir(dir.join("sub")).await?;
+    fs::write(dir.join("a.txt"), "a").await?;
+    fs::write(dir.join("b.TXT"), "b").await?;
+    fs::write(dir.join("bad.txt"), "bad").await?;
+    fs::write(dir.join("c.json"), "{}").await?;
+    fs::write(dir.join("sub/d.txt"), "d").await?;
+
+    let mut seen = Vec::new();
+    let summary = process_directory(dir, &["txt"], false, |path| {
+        seen.push(path.file_name().unwrap().to_string_lossy().into_owned());
+        async move {
+            let content = fs::read_to_string(&path).await?;
+            anyhow::ensure!(content != "bad", "bad caption");
+            Ok(())
+        }
+    })
+    .await?;
+    assert_eq!(
+        summary,
+        ProcessSummary {
+            processed: 2,
+            errored: 1
+        }
+    );
+    assert_eq!(seen, ["a.txt", "b.TXT", "bad.txt"]);
+
+    let summary = process_directory(dir, &[".txt"], true, |_| async { Ok(()) }).await?;
+    assert_eq!(summary.processed, 4);
+
+    Ok(())
+}